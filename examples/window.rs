@@ -1,35 +1,10 @@
-use acure::{Acure, AlignMode, Color, Command, LayoutMode};
-use raw_window_handle::HasWindowHandle;
+use acure::{Acure, AcureBuilder, AlignMode, Color, Command, LayoutMode};
 use winit::{
     event::{Event, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
 };
 
-pub struct Surface<T>
-where
-    T: acure::surface::Surface,
-{
-    surface: T,
-}
-
-impl<T> Surface<T>
-where
-    T: acure::surface::Surface,
-{
-    pub fn new(surface: T) -> Self {
-        Self { surface }
-    }
-
-    pub fn as_mut_raw(&mut self) -> &mut T {
-        &mut self.surface
-    }
-
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.surface.surface_resize(width, height)
-    }
-}
-
 fn main() -> Result<(), impl std::error::Error> {
     let event_loop = EventLoop::new().unwrap();
 
@@ -40,60 +15,10 @@ fn main() -> Result<(), impl std::error::Error> {
         .unwrap();
 
     let mut acure = Acure::new();
-    let mut surface;
-    let handle = window.window_handle().unwrap().as_raw();
-    match handle {
-        raw_window_handle::RawWindowHandle::UiKit(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::AppKit(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Orbital(_) => {
-            panic!("This sample is available only Windows")
-        }
-        #[cfg(target_os = "linux")]
-        raw_window_handle::RawWindowHandle::Xlib(handle) => {
-            use acure::x11::X11Surface;
-            surface = Surface::new(X11Surface::new(handle.window));
-        }
-        raw_window_handle::RawWindowHandle::Xcb(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Wayland(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Drm(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Gbm(_) => {
-            panic!("This sample is available only Windows")
-        }
-        #[cfg(target_os = "windows")]
-        raw_window_handle::RawWindowHandle::Win32(handle) => {
-            use acure::wgl::WglSurface;
-            surface = Surface::new(WglSurface::new(isize::from(handle.hwnd)).unwrap());
-        }
-        raw_window_handle::RawWindowHandle::WinRt(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Web(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::WebCanvas(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::WebOffscreenCanvas(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::AndroidNdk(_) => {
-            panic!("This sample is available only Windows")
-        }
-        raw_window_handle::RawWindowHandle::Haiku(_) => {
-            panic!("This sample is available only Windows")
-        }
-        _ => panic!("This sample is available only Windows"),
-    }
+    let (backend, mut surface) = AcureBuilder::new().vsync(true).build(&window).unwrap();
+    println!("Drawing with {}", backend);
+    let size = window.inner_size();
+    surface.surface_resize(size.width, size.height);
 
     acure.set_layout_mode(LayoutMode::AdjustSize);
     acure.set_align_mode(AlignMode::CenterAligned);
@@ -102,11 +27,14 @@ fn main() -> Result<(), impl std::error::Error> {
     event_loop.run(move |event, elwt| match event {
         Event::WindowEvent { event, window_id } if window_id == window.id() => match event {
             WindowEvent::Resized(size) => {
-                surface.resize(size.width, size.height);
+                surface.surface_resize(size.width, size.height);
             }
             WindowEvent::CloseRequested => elwt.exit(),
             WindowEvent::RedrawRequested => {
-                acure.begin(surface.as_mut_raw());
+                if let Err(e) = acure.begin(&mut *surface) {
+                    eprintln!("{}", e);
+                    return;
+                }
 
                 acure.push(Command::FillRectangle(
                     10,
@@ -125,7 +53,9 @@ fn main() -> Result<(), impl std::error::Error> {
                     Color::ARGB(255, 0, 0, 0),
                     String::from("あ"),
                 ));
-                acure.write(surface.as_mut_raw());
+                if let Err(e) = acure.write(&mut *surface) {
+                    eprintln!("{}", e);
+                }
                 acure.clear();
                 window.pre_present_notify();
             }
@@ -0,0 +1,209 @@
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
+
+use crate::{surface::Surface, AcureError, AeResult, Backend};
+
+// Creates the surface for a window from a list of preferred backends.
+#[derive(Clone, Debug)]
+pub struct AcureBuilder {
+    backends: Vec<Backend>,
+    vsync: bool,
+}
+
+impl Default for AcureBuilder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AcureBuilder {
+    // Prefers Direct2D on Windows and EGL on Wayland and X11, falling back to
+    // plain Xlib or XCB and then to the software rasterizer, with vsync.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            backends: vec![
+                Backend::D2D1,
+                Backend::WGL,
+                Backend::WaylandEGL,
+                Backend::X11EGL,
+                Backend::X11,
+                Backend::XCB,
+                Backend::Software,
+            ],
+            vsync: true,
+        }
+    }
+
+    // Best first. Backends that weren't compiled in, can't draw to the window
+    // or fail to start are skipped, and the reason is logged.
+    #[inline]
+    pub fn backend_preference<I>(mut self, backends: I) -> Self
+    where
+        I: IntoIterator<Item = Backend>,
+    {
+        self.backends = backends.into_iter().collect();
+        self
+    }
+
+    // Ignored by surfaces that can't wait for vertical blanks.
+    #[inline]
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    // Returns the backend that was picked with its surface, which has to be
+    // resized to the window before the first frame. The surface draws with
+    // the window's display connection, so the window has to outlive it.
+    pub fn build<W>(&self, window: &W) -> AeResult<(Backend, Box<dyn Surface>)>
+    where
+        W: HasWindowHandle + HasDisplayHandle,
+    {
+        let display = window.display_handle()?.as_raw();
+        let window = window.window_handle()?.as_raw();
+        for backend in &self.backends {
+            match create(*backend, window, display) {
+                Some(Ok(mut surface)) => {
+                    info!("Using {}", backend);
+                    surface.set_vsync(self.vsync);
+                    return Ok((*backend, surface));
+                }
+                Some(Err(error)) => warn!("Can't use {}: {}", backend, error),
+                None => info!("{} isn't available for this window", backend),
+            }
+        }
+        Err(AcureError::NoSuitableBackend)
+    }
+}
+
+// None if the backend wasn't compiled in or can't draw to this window.
+fn create(
+    backend: Backend,
+    window: RawWindowHandle,
+    display: RawDisplayHandle,
+) -> Option<AeResult<Box<dyn Surface>>> {
+    match (backend, window, display) {
+        #[cfg(all(target_os = "windows", feature = "d2d1"))]
+        (Backend::D2D1, RawWindowHandle::Win32(handle), _) => {
+            Some(unsafe { crate::d2d1::D2D1Surface::new(handle.hwnd.get()) }.map(boxed))
+        }
+        #[cfg(all(target_os = "windows", feature = "wgl"))]
+        (Backend::WGL, RawWindowHandle::Win32(handle), _) => {
+            Some(crate::wgl::WglSurface::new(handle.hwnd.get()).map(boxed))
+        }
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
+        (
+            Backend::WaylandEGL,
+            RawWindowHandle::Wayland(handle),
+            RawDisplayHandle::Wayland(display),
+        ) => Some(
+            unsafe { crate::wayland::WaylandEglSurface::new(display.display, handle.surface) }
+                .map(boxed),
+        ),
+        #[cfg(all(target_os = "linux", feature = "x11_egl"))]
+        (Backend::X11EGL, RawWindowHandle::Xlib(handle), RawDisplayHandle::Xlib(display)) => Some(
+            x_display(display)
+                .and_then(|d| crate::x11egl::X11EglSurface::new(d, handle.window).map(boxed)),
+        ),
+        #[cfg(all(target_os = "linux", feature = "x11"))]
+        (Backend::X11, RawWindowHandle::Xlib(handle), RawDisplayHandle::Xlib(display)) => Some(
+            x_display(display)
+                .and_then(|d| crate::x11::X11Surface::new(d, handle.window).map(boxed)),
+        ),
+        #[cfg(all(target_os = "linux", feature = "x11", feature = "software"))]
+        (Backend::Software, RawWindowHandle::Xlib(handle), RawDisplayHandle::Xlib(display)) => {
+            Some(
+                x_display(display).and_then(|d| {
+                    crate::x11soft::X11SoftwareSurface::new(d, handle.window).map(boxed)
+                }),
+            )
+        }
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
+        (
+            Backend::Software,
+            RawWindowHandle::Wayland(handle),
+            RawDisplayHandle::Wayland(display),
+        ) => Some(
+            unsafe { crate::wayland::WaylandShmSurface::new(display.display, handle.surface) }
+                .map(boxed),
+        ),
+        #[cfg(all(target_os = "linux", feature = "xcb"))]
+        (Backend::XCB, RawWindowHandle::Xcb(handle), RawDisplayHandle::Xcb(display)) => Some(
+            xcb_connection(display)
+                .and_then(|c| crate::xcb::XcbSurface::new(c, handle.window.get()).map(boxed)),
+        ),
+        _ => None,
+    }
+}
+
+// The application's connection, or one of our own if it didn't say.
+#[cfg(all(target_os = "linux", feature = "x11"))]
+fn x_display(handle: raw_window_handle::XlibDisplayHandle) -> AeResult<crate::x11::XDisplay> {
+    match handle.display {
+        Some(display) => Ok(unsafe { crate::x11::XDisplay::borrow(display.as_ptr().cast()) }),
+        None => crate::x11::XDisplay::open(None),
+    }
+}
+
+// Like `x_display`, for XCB.
+#[cfg(all(target_os = "linux", feature = "xcb"))]
+fn xcb_connection(
+    handle: raw_window_handle::XcbDisplayHandle,
+) -> AeResult<crate::xcb::XCBConnection> {
+    use crate::xcb::XCBConnection;
+    match handle.connection {
+        Some(connection) => {
+            unsafe { XCBConnection::from_raw_xcb_connection(connection.as_ptr(), false) }
+                .map_err(|e| AcureError::BackendError(Backend::XCB, e.into()))
+        }
+        None => XCBConnection::connect(None)
+            .map(|(connection, _)| connection)
+            .map_err(|_| {
+                AcureError::DisplayUnavailable(std::env::var("DISPLAY").unwrap_or_default())
+            }),
+    }
+}
+
+#[allow(dead_code)]
+fn boxed<S: Surface + 'static>(surface: S) -> Box<dyn Surface> {
+    Box::new(surface)
+}
+
+#[cfg(test)]
+mod tests {
+    use raw_window_handle::{
+        DisplayHandle, HandleError, WebDisplayHandle, WebWindowHandle, WindowHandle,
+    };
+
+    use super::*;
+
+    // A canvas on a web page, which no backend draws to.
+    struct Canvas;
+
+    impl HasWindowHandle for Canvas {
+        fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+            let handle = RawWindowHandle::Web(WebWindowHandle::new(1));
+            Ok(unsafe { WindowHandle::borrow_raw(handle) })
+        }
+    }
+
+    impl HasDisplayHandle for Canvas {
+        fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+            let handle = RawDisplayHandle::Web(WebDisplayHandle::new());
+            Ok(unsafe { DisplayHandle::borrow_raw(handle) })
+        }
+    }
+
+    #[test]
+    fn fails_without_a_suitable_backend() {
+        assert!(matches!(
+            AcureBuilder::new().build(&Canvas),
+            Err(AcureError::NoSuitableBackend)
+        ));
+        assert!(matches!(
+            AcureBuilder::new().backend_preference([]).build(&Canvas),
+            Err(AcureError::NoSuitableBackend)
+        ));
+    }
+}
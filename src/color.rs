@@ -0,0 +1,511 @@
+use std::str::FromStr;
+
+use crate::{AcureError, AeResult};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    // Alpha,Red,Green,Blue (0-255)
+    ARGB(u8, u8, u8, u8),
+    // Red,Green,Blue,Alpha (0.0-1.0, straight alpha)
+    RGBA(f32, f32, f32, f32),
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = Color::ARGB(0, 0, 0, 0);
+    pub const BLACK: Color = Color::ARGB(255, 0, 0, 0);
+    pub const WHITE: Color = Color::ARGB(255, 255, 255, 255);
+
+    // `h` is in degrees, `s` and `l` are in 0.0-1.0.
+    #[inline]
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Self {
+        Self::from_hsla(h, s, l, 1.0)
+    }
+
+    #[inline]
+    pub fn from_hsla(h: f32, s: f32, l: f32, a: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let l = l.clamp(0.0, 1.0);
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let (r, g, b) = hue_to_rgb(h, c);
+        let m = l - c / 2.0;
+        Color::RGBA(r + m, g + m, b + m, a.clamp(0.0, 1.0))
+    }
+
+    // `h` is in degrees, `s` and `v` are in 0.0-1.0.
+    #[inline]
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        Self::from_hsva(h, s, v, 1.0)
+    }
+
+    #[inline]
+    pub fn from_hsva(h: f32, s: f32, v: f32, a: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let c = v * s;
+        let (r, g, b) = hue_to_rgb(h, c);
+        let m = v - c;
+        Color::RGBA(r + m, g + m, b + m, a.clamp(0.0, 1.0))
+    }
+
+    // Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
+    pub fn from_hex(hex: &str) -> AeResult<Self> {
+        let invalid = || AcureError::InvalidColor(hex.to_owned());
+        let digits = hex.strip_prefix('#').ok_or_else(invalid)?;
+        if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let nibble = |i: usize| u8::from_str_radix(&digits[i..i + 1], 16).unwrap() * 17;
+        let byte = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).unwrap();
+
+        match digits.len() {
+            3 => Ok(Color::ARGB(255, nibble(0), nibble(1), nibble(2))),
+            4 => Ok(Color::ARGB(nibble(3), nibble(0), nibble(1), nibble(2))),
+            6 => Ok(Color::ARGB(255, byte(0), byte(2), byte(4))),
+            8 => Ok(Color::ARGB(byte(6), byte(0), byte(2), byte(4))),
+            _ => Err(invalid()),
+        }
+    }
+
+    // Looks up a CSS named color, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color::TRANSPARENT);
+        }
+        NAMED_COLORS
+            .binary_search_by(|(n, _)| (*n).cmp(name.as_str()))
+            .ok()
+            .map(|i| {
+                let rgb = NAMED_COLORS[i].1;
+                Color::ARGB(255, (rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+            })
+    }
+
+    // Straight-alpha `[r, g, b, a]` in 0.0-1.0.
+    #[inline]
+    pub fn to_rgba(self) -> [f32; 4] {
+        match self {
+            Color::ARGB(a, r, g, b) => [
+                (r as f32) / 255.0,
+                (g as f32) / 255.0,
+                (b as f32) / 255.0,
+                (a as f32) / 255.0,
+            ],
+            Color::RGBA(r, g, b, a) => [
+                r.clamp(0.0, 1.0),
+                g.clamp(0.0, 1.0),
+                b.clamp(0.0, 1.0),
+                a.clamp(0.0, 1.0),
+            ],
+        }
+    }
+
+    // Straight-alpha `[r, g, b, a]` with the color channels decoded to
+    // linear light.
+    #[inline]
+    pub fn to_linear_rgba(self) -> [f32; 4] {
+        let [r, g, b, a] = self.to_rgba();
+        [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+    }
+
+    // Straight-alpha `[a, r, g, b]` in 0-255.
+    #[inline]
+    pub fn to_argb(self) -> [u8; 4] {
+        match self {
+            Color::ARGB(a, r, g, b) => [a, r, g, b],
+            Color::RGBA(..) => {
+                let [r, g, b, a] = self.to_rgba().map(|c| (c * 255.0).round() as u8);
+                [a, r, g, b]
+            }
+        }
+    }
+
+    // Straight-alpha `[r, g, b, a]` in 0-65535, as used by X11.
+    #[inline]
+    pub fn to_rgba16(self) -> [u16; 4] {
+        self.to_rgba().map(|c| (c * 65535.0).round() as u16)
+    }
+
+    // Premultiplied `[r, g, b, a]` in 0.0-1.0.
+    #[inline]
+    pub fn to_premultiplied(self) -> [f32; 4] {
+        let [r, g, b, a] = self.to_rgba();
+        [r * a, g * a, b * a, a]
+    }
+
+    #[inline]
+    pub fn from_premultiplied(rgba: [f32; 4]) -> Self {
+        let [r, g, b, a] = rgba;
+        if a <= 0.0 {
+            return Color::RGBA(0.0, 0.0, 0.0, 0.0);
+        }
+        Color::RGBA(r / a, g / a, b / a, a)
+    }
+
+    // Formats the color as `#rrggbbaa`.
+    pub fn to_hex(self) -> String {
+        let [a, r, g, b] = self.to_argb();
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+
+    #[inline]
+    pub fn with_alpha(self, alpha: f32) -> Self {
+        let [r, g, b, _] = self.to_rgba();
+        Color::RGBA(r, g, b, alpha.clamp(0.0, 1.0))
+    }
+
+    // Interpolates the straight-alpha components.
+    #[inline]
+    pub fn lerp(self, other: Color, t: f32) -> Self {
+        let from = self.to_rgba();
+        let to = other.to_rgba();
+        let [r, g, b, a] = lerp4(from, to, t.clamp(0.0, 1.0));
+        Color::RGBA(r, g, b, a)
+    }
+
+    // Interpolates in premultiplied space, so mixing with a transparent
+    // color fades the alpha without darkening the result.
+    #[inline]
+    pub fn mix(self, other: Color, t: f32) -> Self {
+        let from = self.to_premultiplied();
+        let to = other.to_premultiplied();
+        Self::from_premultiplied(lerp4(from, to, t.clamp(0.0, 1.0)))
+    }
+}
+
+// The space in which colors are blended.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+    // Blend the sRGB-encoded values directly.
+    #[default]
+    Srgb,
+    // Decode to linear light, blend, then encode back to sRGB.
+    Linear,
+}
+
+#[inline]
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl Default for Color {
+    #[inline]
+    fn default() -> Self {
+        Color::TRANSPARENT
+    }
+}
+
+impl FromStr for Color {
+    type Err = AcureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with('#') {
+            Self::from_hex(s)
+        } else if s.ends_with(')') {
+            from_function(s).ok_or_else(|| AcureError::InvalidColor(s.to_owned()))
+        } else {
+            Self::from_name(s).ok_or_else(|| AcureError::InvalidColor(s.to_owned()))
+        }
+    }
+}
+
+// `rgb()`, `rgba()`, `hsl()` and `hsla()`, with either commas or spaces and
+// an optional `/ alpha`.
+fn from_function(s: &str) -> Option<Color> {
+    let (name, args) = s.strip_suffix(')')?.split_once('(')?;
+    let args = args
+        .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+    if args.len() != 3 && args.len() != 4 {
+        return None;
+    }
+    let alpha = match args.get(3) {
+        Some(a) => component(a, 1.0)?,
+        None => 1.0,
+    };
+
+    match name.trim().to_ascii_lowercase().as_str() {
+        "rgb" | "rgba" => {
+            let r = component(args[0], 255.0)?;
+            let g = component(args[1], 255.0)?;
+            let b = component(args[2], 255.0)?;
+            Some(Color::RGBA(r, g, b, alpha))
+        }
+        "hsl" | "hsla" => {
+            let h = args[0].trim_end_matches("deg").parse::<f32>().ok()?;
+            let s = component(args[1], 100.0)?;
+            let l = component(args[2], 100.0)?;
+            Some(Color::from_hsla(h, s, l, alpha))
+        }
+        _ => None,
+    }
+}
+
+// A number out of `max`, or a percentage, as 0.0-1.0.
+#[inline]
+fn component(s: &str, max: f32) -> Option<f32> {
+    let value = match s.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+        None => s.parse::<f32>().ok()? / max,
+    };
+    Some(value.clamp(0.0, 1.0))
+}
+
+#[inline]
+fn lerp4(from: [f32; 4], to: [f32; 4], t: f32) -> [f32; 4] {
+    [
+        from[0] + (to[0] - from[0]) * t,
+        from[1] + (to[1] - from[1]) * t,
+        from[2] + (to[2] - from[2]) * t,
+        from[3] + (to[3] - from[3]) * t,
+    ]
+}
+
+#[inline]
+fn hue_to_rgb(h: f32, c: f32) -> (f32, f32, f32) {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    }
+}
+
+// CSS Color Module Level 4 named colors, sorted by name.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex() {
+        let hex = |s: &str| Color::from_hex(s).unwrap().to_argb();
+        assert_eq!(hex("#f80"), [255, 0xff, 0x88, 0x00]);
+        assert_eq!(hex("#f808"), [0x88, 0xff, 0x88, 0x00]);
+        assert_eq!(hex("#12AbEf"), [255, 0x12, 0xab, 0xef]);
+        assert_eq!(hex("#12abef80"), [0x80, 0x12, 0xab, 0xef]);
+        for bad in ["", "f80", "#", "#12345", "#12g", "#ééé", "#123456789"] {
+            assert!(Color::from_hex(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(Color::from_hex("#12abef80").unwrap().to_hex(), "#12abef80");
+    }
+
+    #[test]
+    fn parses_names() {
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            Color::from_name("RebeccaPurple").map(Color::to_argb),
+            Some([255, 0x66, 0x33, 0x99])
+        );
+        assert_eq!(
+            Color::from_name("aliceblue").map(Color::to_argb),
+            Some([255, 0xf0, 0xf8, 0xff])
+        );
+        assert_eq!(
+            Color::from_name("yellowgreen").map(Color::to_argb),
+            Some([255, 0x9a, 0xcd, 0x32])
+        );
+        assert_eq!(Color::from_name("Transparent"), Some(Color::TRANSPARENT));
+        assert_eq!(Color::from_name("notacolor"), None);
+    }
+
+    #[test]
+    fn parses_strings() {
+        let parse = |s: &str| s.parse::<Color>().unwrap().to_argb();
+        assert_eq!(parse(" red "), [255, 255, 0, 0]);
+        assert_eq!(parse("#0000ff"), [255, 0, 0, 255]);
+        assert_eq!(parse("rgb(255, 128, 0)"), [255, 255, 128, 0]);
+        assert_eq!(parse("rgba(255 0 0 / 50%)"), [128, 255, 0, 0]);
+        assert_eq!(parse("hsl(120deg, 100%, 50%)"), [255, 0, 255, 0]);
+        for bad in ["rgb(1, 2)", "cmyk(1, 2, 3)", "nope", "rgb(a, b, c)"] {
+            assert!(bad.parse::<Color>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn converts_hsv_and_premultiplies() {
+        assert_eq!(Color::from_hsv(240.0, 1.0, 1.0).to_argb(), [255, 0, 0, 255]);
+        assert_eq!(
+            Color::from_hsl(-120.0, 1.0, 0.5).to_argb(),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            Color::RGBA(1.0, 0.5, 0.0, 0.5).to_premultiplied(),
+            [0.5, 0.25, 0.0, 0.5]
+        );
+        let mixed = Color::ARGB(255, 255, 0, 0).mix(Color::TRANSPARENT, 0.5);
+        assert_eq!(mixed.to_argb(), [128, 255, 0, 0]);
+    }
+}
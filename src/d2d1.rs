@@ -8,172 +8,182 @@ use windows::{
     Win32::UI::Animation::*, Win32::UI::WindowsAndMessaging::*,
 };
 
-use crate::surface::Surface;
-use crate::{AcureError, AeResult, AlignMode, Backend, Color, Command, LayoutMode};
+use crate::surface::{Capabilities, DrawState, Surface};
+use crate::{
+    image::pixel_bounds,
+    path::{LineCap, LineJoin, PathSegment},
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, FillRule, Image,
+    LayoutMode, Path, Rect, Stroke, Transform,
+};
 
 impl Surface for D2D1Surface {
     #[inline]
     fn surface_resize(&mut self, width: u32, height: u32) {
-        self.resize_swapchain_bitmap().unwrap();
+        self.resize();
     }
 
     #[inline]
-    fn begin(&mut self) {
-        if self.target.is_none() {
-            let device = create_device().unwrap();
-            let target = create_render_target(&self.factory, &device).unwrap();
-            unsafe { target.SetDpi(self.dpi, self.dpi) };
+    fn capabilities(&self) -> Capabilities {
+        let max_texture_size = match &self.target {
+            Some(target) => unsafe { target.GetMaximumBitmapSize() },
+            None => D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION,
+        };
+        Capabilities {
+            max_texture_size: Some(max_texture_size),
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: true,
+        }
+    }
 
-            let swapchain = create_swapchain(&device, self.handle).unwrap();
-            create_swapchain_bitmap(&swapchain, &target).unwrap();
+    #[inline]
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        self.vsync = vsync;
+        true
+    }
 
-            unsafe { target.SetUnitMode(D2D1_UNIT_MODE_PIXELS) };
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        // The swapchain is B8G8R8A8_UNORM, so Direct2D always blends in sRGB.
+        space == ColorSpace::Srgb
+    }
 
-            self.brush = create_brush(&target).ok();
-            self.target = Some(target);
-            self.swapchain = Some(swapchain);
-            self.create_device_size_resources().unwrap();
+    // A lost device is recreated here, on the next frame.
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        if self.target.is_none() {
+            if let Err(error) = self.create_device_resources() {
+                self.release_device();
+                return Err(AcureError::ContextCreation(Backend::D2D1, error.into()));
+            }
         }
 
-        let target = self.target.as_ref().unwrap();
-        unsafe { target.BeginDraw() };
+        self.state.reset();
+        self.pushed.clear();
+        if let Some(target) = &self.target {
+            unsafe {
+                target.BeginDraw();
+                target.SetTransform(&Matrix3x2::identity());
+            }
+        }
+        Ok(())
     }
 
     #[inline]
-    fn end(&mut self) {
-        let target = self.target.as_ref().unwrap();
-        unsafe {
-            target.EndDraw(None, None).unwrap();
+    fn end(&mut self) -> AeResult<()> {
+        // Everything pushed has to be popped before EndDraw.
+        while let Some(pushed) = self.pushed.last().copied() {
+            self.pop(pushed);
         }
 
-        if let Err(error) = self.present(1, 0) {
-            if error.code() == DXGI_STATUS_OCCLUDED {
-                self.occlusion = unsafe {
-                    self.dxfactory
-                        .RegisterOcclusionStatusWindow(self.handle, WM_USER)
-                        .unwrap()
-                };
-                self.visible = false;
-            } else {
+        let Some(target) = self.target.as_ref() else {
+            return Ok(());
+        };
+        if let Err(error) = unsafe { target.EndDraw(None, None) } {
+            self.release_device();
+            return Err(device_error(error));
+        }
+        if let Err(error) = self.copy_frame() {
+            warn!("Can't keep a copy of the frame: {}", error);
+            self.frame = None;
+        }
+
+        if let Err(error) = self.present(self.vsync as u32, 0) {
+            if error.code() != DXGI_STATUS_OCCLUDED {
                 self.release_device();
+                return Err(device_error(error));
             }
+            self.occlusion = unsafe {
+                self.dxfactory
+                    .RegisterOcclusionStatusWindow(self.handle, WM_USER)
+                    .unwrap_or_default()
+            };
+            self.visible = false;
         }
+        Ok(())
     }
 
     #[inline]
-    fn command(&self, command: &Command, align: AlignMode, layout: LayoutMode) {
-        let target = self.target.as_ref().unwrap();
-        let clock = self.clock.as_ref().unwrap();
-        let dwfactory = &self.dwfactory;
-        let shadow = self.shadow.as_ref().unwrap();
-
-        unsafe {
-            self.manager
-                .Update(get_time(self.frequency).unwrap(), None)
-                .unwrap();
-
-            let previous = target.GetTarget().unwrap();
-            target.SetTarget(clock);
-            target.Clear(None);
-
-            match command {
-                Command::FillRectangle(x, y, width, height, radius, color) => {
-                    let rect = D2D_RECT_F {
-                        left: *x as f32,
-                        top: *y as f32,
-                        right: (width + x) as f32,
-                        bottom: (height + y) as f32,
-                    };
+    fn command(&mut self, command: &Command, align: AlignMode, layout: LayoutMode) -> AeResult<()> {
+        match self.target.clone() {
+            Some(target) => self.draw(&target, command).map_err(device_error),
+            None => Err(AcureError::UnauthorizedOperation),
+        }
+    }
 
-                    let brush = create_brush_from_color(target, *color).unwrap();
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        let Some(target) = self.target.as_ref() else {
+            return;
+        };
+        let color = self.d2d1_color(color);
+        unsafe { target.Clear(Some(&color)) };
+    }
 
-                    if *radius != 0.0 {
-                        let rounded_rect = D2D1_ROUNDED_RECT {
-                            rect,
-                            radiusX: *radius as f32,
-                            radiusY: *radius as f32,
-                        };
-                        target.FillRoundedRectangle(&rounded_rect, &brush);
-                    } else {
-                        target.FillRectangle(&rect, &brush);
-                    }
-                }
-                Command::WriteString(x, y, width, height, color, string) => {
-                    let mut string = string.encode_utf16().collect::<Vec<u16>>();
-                    string.push(0);
-                    let mut font_name = "Yu gothic".encode_utf16().collect::<Vec<u16>>();
-                    font_name.push(0);
-                    let mut lang = "en-us".encode_utf16().collect::<Vec<u16>>();
-                    lang.push(0);
-                    let font_size = (*height as f32) / 1.7;
-                    let format;
-                    unsafe {
-                        format = dwfactory
-                            .CreateTextFormat(
-                                PCWSTR(font_name.as_ptr()),
-                                None,
-                                DWRITE_FONT_WEIGHT_REGULAR,
-                                DWRITE_FONT_STYLE_NORMAL,
-                                DWRITE_FONT_STRETCH_NORMAL,
-                                font_size,
-                                PCWSTR(lang.as_ptr()),
-                            )
-                            .unwrap();
-                        format
-                            .SetTextAlignment(DWRITE_TEXT_ALIGNMENT_CENTER)
-                            .unwrap();
-                        format
-                            .SetParagraphAlignment(DWRITE_PARAGRAPH_ALIGNMENT_CENTER)
-                            .unwrap();
-                    }
-                    let brush = create_brush_from_color(target, *color).unwrap();
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        let (Some(frame), Some(swapchain)) = (&self.frame, &self.swapchain) else {
+            return Err(AcureError::UnauthorizedOperation);
+        };
+        let error = device_error;
 
-                    let layout_rect = D2D_RECT_F {
-                        left: *x as f32,
-                        top: *y as f32,
-                        right: (width + x) as f32,
-                        bottom: (height + y) as f32,
-                    };
+        unsafe {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            frame.GetDesc(&mut desc);
+            let (x, y, width, height) = pixel_bounds(&rect, desc.Width, desc.Height);
+            if width == 0 {
+                return Ok(Image::default());
+            }
 
-                    target.DrawText(
-                        &string,
-                        &format,
-                        &layout_rect,
-                        &brush,
-                        D2D1_DRAW_TEXT_OPTIONS_CLIP,
-                        DWRITE_MEASURING_MODE_NATURAL,
-                    );
-                }
+            let device: ID3D11Device = swapchain.GetDevice().map_err(error)?;
+            let context = device.GetImmediateContext().map_err(error)?;
+            let staging_desc = D3D11_TEXTURE2D_DESC {
+                Width: width,
+                Height: height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: desc.Format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_STAGING,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                ..Default::default()
+            };
+            let mut staging = None;
+            device
+                .CreateTexture2D(&staging_desc, None, Some(&mut staging))
+                .map_err(error)?;
+            let staging = staging.ok_or_else(|| error(E_POINTER.into()))?;
+
+            let region = D3D11_BOX {
+                left: x,
+                top: y,
+                front: 0,
+                right: x + width,
+                bottom: y + height,
+                back: 1,
+            };
+            context.CopySubresourceRegion(&staging, 0, 0, 0, 0, frame, 0, Some(&region));
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            context
+                .Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))
+                .map_err(error)?;
+            // B8G8R8A8 is 0xAARRGGBB in memory order; the swapchain ignores
+            // alpha.
+            let mut pixels = Vec::with_capacity(width as usize * height as usize);
+            for row in 0..height as usize {
+                let data = (mapped.pData as *const u8).add(row * mapped.RowPitch as usize);
+                let data = std::slice::from_raw_parts(data as *const u32, width as usize);
+                pixels.extend(data.iter().map(|pixel| pixel | 0xff000000));
             }
+            context.Unmap(&staging, 0);
 
-            target.SetTarget(&previous);
-
-            // target.DrawImage(
-            //     &shadow.GetOutput().unwrap(),
-            //     None,
-            //     None,
-            //     D2D1_INTERPOLATION_MODE_LINEAR,
-            //     D2D1_COMPOSITE_MODE_SOURCE_OVER,
-            // );
-
-            target.SetTransform(&Matrix3x2::identity());
-
-            target.DrawImage(
-                clock,
-                None,
-                None,
-                D2D1_INTERPOLATION_MODE_LINEAR,
-                D2D1_COMPOSITE_MODE_SOURCE_OVER,
-            );
+            Ok(Image::new(width, height, pixels))
         }
     }
-
-    #[inline]
-    fn clear(&self, color: Color) {
-        let target = self.target.as_ref().unwrap();
-        let color = self.d2d1_color(color);
-        unsafe { target.Clear(Some(&color)) };
-    }
 }
 
 pub struct D2D1Surface {
@@ -190,11 +200,23 @@ pub struct D2D1Surface {
     brush: Option<ID2D1SolidColorBrush>,
     shadow: Option<ID2D1Effect>,
     clock: Option<ID2D1Bitmap1>,
+    // The last presented frame. The flip model discards the back buffer.
+    frame: Option<ID3D11Texture2D>,
     dpi: f32,
     visible: bool,
+    vsync: bool,
     occlusion: u32,
     frequency: i64,
     angles: Angles,
+    state: DrawState,
+    pushed: Vec<Pushed>,
+}
+
+// Clips and layers share one stack in Direct2D.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pushed {
+    Clip,
+    Layer,
 }
 
 #[derive(Default)]
@@ -223,31 +245,29 @@ impl Angles {
 impl D2D1Surface {
     #[inline]
     pub unsafe fn new(hwnd: isize) -> AeResult<Self> {
-        let factory = match create_factory() {
-            Ok(f) => f,
-            Err(e) => return Err(AcureError::BackendError(Backend::D2D1, e.into())),
-        };
-        let dxfactory: IDXGIFactory2 = unsafe { CreateDXGIFactory1().unwrap() };
+        let error = |e: Error| AcureError::BackendError(Backend::D2D1, e.into());
+        let factory = create_factory().map_err(error)?;
+        let dxfactory: IDXGIFactory2 = unsafe { CreateDXGIFactory1().map_err(error)? };
         let dwfactory: IDWriteFactory =
-            unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED).unwrap() };
-        let style = create_style(&factory).unwrap();
+            unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED).map_err(error)? };
+        let style = create_style(&factory).map_err(error)?;
         let manager: IUIAnimationManager =
-            unsafe { CoCreateInstance(&UIAnimationManager, None, CLSCTX_ALL).unwrap() };
-        let transition = create_transition().unwrap();
+            unsafe { CoCreateInstance(&UIAnimationManager, None, CLSCTX_ALL).map_err(error)? };
+        let transition = create_transition().map_err(error)?;
 
         let mut dpi = 0.0;
         let mut dpiy = 0.0;
         unsafe { factory.GetDesktopDpi(&mut dpi, &mut dpiy) };
 
         let mut frequency = 0;
-        unsafe { QueryPerformanceFrequency(&mut frequency).unwrap() };
+        unsafe { QueryPerformanceFrequency(&mut frequency).map_err(error)? };
 
         let variable = unsafe {
-            let variable = manager.CreateAnimationVariable(0.0).unwrap();
+            let variable = manager.CreateAnimationVariable(0.0).map_err(error)?;
 
             manager
-                .ScheduleTransition(&variable, &transition, get_time(frequency).unwrap())
-                .unwrap();
+                .ScheduleTransition(&variable, &transition, get_time(frequency).map_err(error)?)
+                .map_err(error)?;
 
             variable
         };
@@ -265,29 +285,181 @@ impl D2D1Surface {
             brush: None,
             shadow: None,
             clock: None,
+            frame: None,
             dpi,
             visible: false,
+            vsync: true,
             occlusion: 0,
             frequency,
             angles: Angles::now(),
+            state: DrawState::new(),
+            pushed: vec![],
         })
     }
 
     #[inline]
     fn d2d1_color(&self, color: Color) -> D2D1_COLOR_F {
-        match color {
-            Color::ARGB(a, r, g, b) => D2D1_COLOR_F {
-                r: (r as f32) / 255.0,
-                g: (g as f32) / 255.0,
-                b: (b as f32) / 255.0,
-                a: (a as f32) / 255.0,
+        d2d1_color(color)
+    }
+
+    // Drawn straight onto the target so that clips, layers and the
+    // transform apply.
+    fn draw(&mut self, target: &ID2D1DeviceContext, command: &Command) -> Result<()> {
+        let dwfactory = &self.dwfactory;
+        if let Ok(time) = get_time(self.frequency) {
+            let _ = unsafe { self.manager.Update(time, None) };
+        }
+
+        unsafe {
+            match command {
+                Command::FillRectangle(x, y, width, height, radius, color) => {
+                    let rect = D2D_RECT_F {
+                        left: *x as f32,
+                        top: *y as f32,
+                        right: (width + x) as f32,
+                        bottom: (height + y) as f32,
+                    };
+
+                    let brush = create_brush_from_color(target, *color)?;
+
+                    if *radius != 0.0 {
+                        let rounded_rect = D2D1_ROUNDED_RECT {
+                            rect,
+                            radiusX: *radius as f32,
+                            radiusY: *radius as f32,
+                        };
+                        target.FillRoundedRectangle(&rounded_rect, &brush);
+                    } else {
+                        target.FillRectangle(&rect, &brush);
+                    }
+                }
+                Command::WriteString(x, y, width, height, color, string) => {
+                    let mut string = string.encode_utf16().collect::<Vec<u16>>();
+                    string.push(0);
+                    let mut font_name = "Yu gothic".encode_utf16().collect::<Vec<u16>>();
+                    font_name.push(0);
+                    let mut lang = "en-us".encode_utf16().collect::<Vec<u16>>();
+                    lang.push(0);
+                    let font_size = (*height as f32) / 1.7;
+                    let format;
+                    unsafe {
+                        format = dwfactory.CreateTextFormat(
+                            PCWSTR(font_name.as_ptr()),
+                            None,
+                            DWRITE_FONT_WEIGHT_REGULAR,
+                            DWRITE_FONT_STYLE_NORMAL,
+                            DWRITE_FONT_STRETCH_NORMAL,
+                            font_size,
+                            PCWSTR(lang.as_ptr()),
+                        )?;
+                        format.SetTextAlignment(DWRITE_TEXT_ALIGNMENT_CENTER)?;
+                        format.SetParagraphAlignment(DWRITE_PARAGRAPH_ALIGNMENT_CENTER)?;
+                    }
+                    let brush = create_brush_from_color(target, *color)?;
+
+                    let layout_rect = D2D_RECT_F {
+                        left: *x as f32,
+                        top: *y as f32,
+                        right: (width + x) as f32,
+                        bottom: (height + y) as f32,
+                    };
+
+                    target.DrawText(
+                        &string,
+                        &format,
+                        &layout_rect,
+                        &brush,
+                        D2D1_DRAW_TEXT_OPTIONS_CLIP,
+                        DWRITE_MEASURING_MODE_NATURAL,
+                    );
+                }
+                Command::FillPath(path, color) => {
+                    let geometry = create_path_geometry(&self.factory, path)?;
+                    let brush = create_brush_from_color(target, *color)?;
+                    target.FillGeometry(&geometry, &brush, None);
+                }
+                Command::StrokePath(path, stroke, color) => {
+                    let geometry = create_path_geometry(&self.factory, path)?;
+                    let style = create_stroke_style(&self.factory, stroke)?;
+                    let brush = create_brush_from_color(target, *color)?;
+                    target.DrawGeometry(&geometry, &brush, stroke.width, &style);
+                }
+                Command::PushClip(x, y, width, height) => {
+                    let rect = D2D_RECT_F {
+                        left: *x as f32,
+                        top: *y as f32,
+                        right: (width + x) as f32,
+                        bottom: (height + y) as f32,
+                    };
+                    target.PushAxisAlignedClip(&rect, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE);
+                    self.pushed.push(Pushed::Clip);
+                }
+                Command::PushOpacity(opacity) => {
+                    let parameters = D2D1_LAYER_PARAMETERS {
+                        contentBounds: D2D_RECT_F {
+                            left: -f32::MAX,
+                            top: -f32::MAX,
+                            right: f32::MAX,
+                            bottom: f32::MAX,
+                        },
+                        maskTransform: Matrix3x2::identity(),
+                        opacity: opacity.clamp(0.0, 1.0),
+                        ..Default::default()
+                    };
+                    target.PushLayer(&parameters, None::<&ID2D1Layer>);
+                    self.pushed.push(Pushed::Layer);
+                }
+                Command::PopClip => self.pop(Pushed::Clip),
+                Command::PopOpacity => self.pop(Pushed::Layer),
+                Command::PushTransform(_) | Command::PopTransform => {
+                    self.state.apply(command);
+                    target.SetTransform(&matrix(&self.state.transform()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Pops the innermost clip or layer, if it is the kind asked for.
+    fn pop(&mut self, kind: Pushed) {
+        let Some(target) = self.target.as_ref() else {
+            return;
+        };
+        match self.pushed.last() {
+            Some(pushed) if *pushed == kind => unsafe {
+                match kind {
+                    Pushed::Clip => target.PopAxisAlignedClip(),
+                    Pushed::Layer => target.PopLayer(),
+                }
+                self.pushed.pop();
             },
+            Some(pushed) => warn!("Can't pop a {:?} while a {:?} is pushed", kind, pushed),
+            None => {}
         }
     }
 
     #[inline]
     pub fn resize(&mut self) {
-        self.resize_swapchain_bitmap().unwrap();
+        if let Err(error) = self.resize_swapchain_bitmap() {
+            warn!("Can't resize the swapchain: {}", error);
+            self.release_device();
+        }
+    }
+
+    fn create_device_resources(&mut self) -> Result<()> {
+        let device = create_device()?;
+        let target = create_render_target(&self.factory, &device)?;
+        unsafe { target.SetDpi(self.dpi, self.dpi) };
+
+        let swapchain = create_swapchain(&device, self.handle)?;
+        create_swapchain_bitmap(&swapchain, &target)?;
+
+        unsafe { target.SetUnitMode(D2D1_UNIT_MODE_PIXELS) };
+
+        self.brush = create_brush(&target).ok();
+        self.target = Some(target);
+        self.swapchain = Some(swapchain);
+        self.create_device_size_resources()
     }
 
     #[inline]
@@ -302,17 +474,61 @@ impl D2D1Surface {
         self.brush = None;
         self.clock = None;
         self.shadow = None;
+        self.frame = None;
+    }
+
+    // Copies the back buffer into `frame` before it's presented.
+    fn copy_frame(&mut self) -> Result<()> {
+        let Some(swapchain) = self.swapchain.as_ref() else {
+            return Ok(());
+        };
+        unsafe {
+            let buffer: ID3D11Texture2D = swapchain.GetBuffer(0)?;
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            buffer.GetDesc(&mut desc);
+
+            let device: ID3D11Device = swapchain.GetDevice()?;
+            let stale = match &self.frame {
+                Some(frame) => {
+                    let mut frame_desc = D3D11_TEXTURE2D_DESC::default();
+                    frame.GetDesc(&mut frame_desc);
+                    frame_desc.Width != desc.Width || frame_desc.Height != desc.Height
+                }
+                None => true,
+            };
+            if stale {
+                let frame_desc = D3D11_TEXTURE2D_DESC {
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: 0,
+                    CPUAccessFlags: 0,
+                    MiscFlags: 0,
+                    ..desc
+                };
+                let mut frame = None;
+                device.CreateTexture2D(&frame_desc, None, Some(&mut frame))?;
+                self.frame = frame;
+            }
+
+            if let Some(frame) = &self.frame {
+                device.GetImmediateContext()?.CopyResource(frame, &buffer);
+            }
+        }
+        Ok(())
     }
 
     #[inline]
     fn present(&self, sync: u32, flags: u32) -> Result<()> {
-        unsafe { self.swapchain.as_ref().unwrap().Present(sync, flags).ok() }
+        match &self.swapchain {
+            Some(swapchain) => unsafe { swapchain.Present(sync, flags).ok() },
+            None => Ok(()),
+        }
     }
 
     #[inline]
     fn draw_clock(&self) -> Result<()> {
-        let target = self.target.as_ref().unwrap();
-        let brush = self.brush.as_ref().unwrap();
+        let (Some(target), Some(brush)) = (&self.target, &self.brush) else {
+            return Ok(());
+        };
 
         let size = unsafe { target.GetSize() };
 
@@ -326,7 +542,9 @@ impl D2D1Surface {
 
     #[inline]
     fn create_device_size_resources(&mut self) -> Result<()> {
-        let target = self.target.as_ref().unwrap();
+        let Some(target) = self.target.as_ref() else {
+            return Ok(());
+        };
         let clock = self.create_clock(target)?;
         self.shadow = create_shadow(target, &clock).ok();
         self.clock = Some(clock);
@@ -359,8 +577,7 @@ impl D2D1Surface {
 
     #[inline]
     fn resize_swapchain_bitmap(&mut self) -> Result<()> {
-        if let Some(target) = &self.target {
-            let swapchain = self.swapchain.as_ref().unwrap();
+        if let (Some(target), Some(swapchain)) = (&self.target, &self.swapchain) {
             unsafe { target.SetTarget(None) };
 
             if unsafe {
@@ -379,6 +596,16 @@ impl D2D1Surface {
     }
 }
 
+// Lost devices are recreated on the next begin.
+fn device_error(error: Error) -> AcureError {
+    match error.code() {
+        DXGI_ERROR_DEVICE_REMOVED | DXGI_ERROR_DEVICE_RESET | D2DERR_RECREATE_TARGET => {
+            AcureError::DeviceLost(Backend::D2D1)
+        }
+        _ => AcureError::BackendError(Backend::D2D1, error.into()),
+    }
+}
+
 fn get_time(frequency: i64) -> Result<f64> {
     unsafe {
         let mut time = 0;
@@ -408,14 +635,7 @@ fn create_brush_from_color(
     target: &ID2D1DeviceContext,
     color: Color,
 ) -> Result<ID2D1SolidColorBrush> {
-    let color = match color {
-        Color::ARGB(a, r, g, b) => D2D1_COLOR_F {
-            r: (r as f32) / 255.0,
-            g: (g as f32) / 255.0,
-            b: (b as f32) / 255.0,
-            a: (a as f32) / 255.0,
-        },
-    };
+    let color = d2d1_color(color);
 
     let properties = D2D1_BRUSH_PROPERTIES {
         opacity: 1.0,
@@ -425,6 +645,23 @@ fn create_brush_from_color(
     unsafe { target.CreateSolidColorBrush(&color, Some(&properties)) }
 }
 
+#[inline]
+fn matrix(transform: &Transform) -> Matrix3x2 {
+    Matrix3x2 {
+        M11: transform.a,
+        M12: transform.b,
+        M21: transform.c,
+        M22: transform.d,
+        M31: transform.e,
+        M32: transform.f,
+    }
+}
+
+fn d2d1_color(color: Color) -> D2D1_COLOR_F {
+    let [r, g, b, a] = color.to_rgba();
+    D2D1_COLOR_F { r, g, b, a }
+}
+
 fn create_shadow(target: &ID2D1DeviceContext, clock: &ID2D1Bitmap1) -> Result<ID2D1Effect> {
     unsafe {
         let shadow = target.CreateEffect(&CLSID_D2D1Shadow)?;
@@ -454,6 +691,84 @@ fn create_style(factory: &ID2D1Factory1) -> Result<ID2D1StrokeStyle> {
     unsafe { factory.CreateStrokeStyle(&props, None) }
 }
 
+fn create_stroke_style(factory: &ID2D1Factory1, stroke: &Stroke) -> Result<ID2D1StrokeStyle> {
+    let cap = match stroke.cap {
+        LineCap::Butt => D2D1_CAP_STYLE_FLAT,
+        LineCap::Round => D2D1_CAP_STYLE_ROUND,
+        LineCap::Square => D2D1_CAP_STYLE_SQUARE,
+    };
+    let props = D2D1_STROKE_STYLE_PROPERTIES {
+        startCap: cap,
+        endCap: cap,
+        dashCap: cap,
+        lineJoin: match stroke.join {
+            LineJoin::Miter => D2D1_LINE_JOIN_MITER_OR_BEVEL,
+            LineJoin::Round => D2D1_LINE_JOIN_ROUND,
+            LineJoin::Bevel => D2D1_LINE_JOIN_BEVEL,
+        },
+        miterLimit: stroke.miter_limit,
+        ..Default::default()
+    };
+
+    unsafe { factory.CreateStrokeStyle(&props, None) }
+}
+
+fn create_path_geometry(factory: &ID2D1Factory1, path: &Path) -> Result<ID2D1PathGeometry> {
+    let point = |x: f32, y: f32| D2D_POINT_2F { x, y };
+    unsafe {
+        let geometry = factory.CreatePathGeometry()?;
+        let sink = geometry.Open()?;
+        sink.SetFillMode(match path.fill_rule() {
+            FillRule::NonZero => D2D1_FILL_MODE_WINDING,
+            FillRule::EvenOdd => D2D1_FILL_MODE_ALTERNATE,
+        });
+
+        let mut open = false;
+        let mut start = (0.0, 0.0);
+        for segment in path.segments() {
+            if !open && !matches!(segment, PathSegment::MoveTo(..) | PathSegment::Close) {
+                sink.BeginFigure(point(start.0, start.1), D2D1_FIGURE_BEGIN_FILLED);
+                open = true;
+            }
+            match *segment {
+                PathSegment::MoveTo(x, y) => {
+                    if open {
+                        sink.EndFigure(D2D1_FIGURE_END_OPEN);
+                    }
+                    sink.BeginFigure(point(x, y), D2D1_FIGURE_BEGIN_FILLED);
+                    open = true;
+                    start = (x, y);
+                }
+                PathSegment::LineTo(x, y) => sink.AddLine(point(x, y)),
+                PathSegment::QuadTo(x1, y1, x, y) => {
+                    sink.AddQuadraticBezier(&D2D1_QUADRATIC_BEZIER_SEGMENT {
+                        point1: point(x1, y1),
+                        point2: point(x, y),
+                    })
+                }
+                PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                    sink.AddBezier(&D2D1_BEZIER_SEGMENT {
+                        point1: point(x1, y1),
+                        point2: point(x2, y2),
+                        point3: point(x, y),
+                    })
+                }
+                PathSegment::Close => {
+                    if open {
+                        sink.EndFigure(D2D1_FIGURE_END_CLOSED);
+                        open = false;
+                    }
+                }
+            }
+        }
+        if open {
+            sink.EndFigure(D2D1_FIGURE_END_OPEN);
+        }
+        sink.Close()?;
+        Ok(geometry)
+    }
+}
+
 fn create_transition() -> Result<IUIAnimationTransition> {
     unsafe {
         let library: IUIAnimationTransitionLibrary =
@@ -483,7 +798,7 @@ fn create_device_with_type(drive_type: D3D_DRIVER_TYPE) -> Result<ID3D11Device>
             None,
             None,
         )
-        .map(|()| device.unwrap())
+        .and_then(|()| device.ok_or_else(|| E_POINTER.into()))
     }
 }
 
@@ -0,0 +1,276 @@
+use std::f32::consts::SQRT_2;
+
+use crate::{path::LineJoin, surface::DrawState, Color, Command, Rect, Stroke, Transform};
+
+// Past this many rectangles the damage is collapsed into their union.
+const MAX_RECTS: usize = 8;
+
+// A command and the state it was issued in.
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    command: Command,
+    transform: Transform,
+    clip: Option<Rect>,
+    opacity: f32,
+}
+
+struct Frame {
+    background: Color,
+    items: Vec<Item>,
+    // What each item covers in device space. A push or pop covers everything
+    // drawn between the two.
+    bounds: Vec<Rect>,
+}
+
+impl Frame {
+    fn new(background: Color, commands: &[Command]) -> Self {
+        let mut state = DrawState::new();
+        let mut items = Vec::with_capacity(commands.len());
+        let mut bounds = vec![Rect::default(); commands.len()];
+        let mut open = vec![];
+
+        for (i, command) in commands.iter().enumerate() {
+            items.push(Item {
+                command: command.clone(),
+                transform: state.transform(),
+                clip: state.clip(),
+                opacity: state.opacity(),
+            });
+            match command {
+                Command::PushClip(..) | Command::PushTransform(_) | Command::PushOpacity(_) => {
+                    open.push(i)
+                }
+                Command::PopClip | Command::PopTransform | Command::PopOpacity => {
+                    if let Some(push) = open.pop() {
+                        bounds[i] = bounds[push];
+                    }
+                }
+                _ => {
+                    let b = command_bounds(command, &state);
+                    bounds[i] = b;
+                    for push in &open {
+                        bounds[*push] = bounds[*push].union(&b);
+                    }
+                }
+            }
+            state.apply(command);
+        }
+
+        Self {
+            background,
+            items,
+            bounds,
+        }
+    }
+}
+
+// Finds what changed between frames by diffing their commands. Commands that
+// match at the start and end of both lists are left alone; everything in
+// between, from either frame, is damaged.
+#[derive(Default)]
+pub struct DamageTracker {
+    previous: Option<Frame>,
+}
+
+impl DamageTracker {
+    #[inline]
+    pub const fn new() -> Self {
+        Self { previous: None }
+    }
+
+    // The next frame will be drawn in full.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    // The regions that changed since the last call, with whole pixel edges,
+    // or None if everything has to be drawn.
+    pub fn update(&mut self, background: Color, commands: &[Command]) -> Option<Vec<Rect>> {
+        let frame = Frame::new(background, commands);
+        let previous = self.previous.replace(frame)?;
+        let frame = self.previous.as_ref().unwrap();
+        if previous.background != frame.background {
+            return None;
+        }
+
+        let (old, new) = (&previous.items, &frame.items);
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let changed = previous.bounds[prefix..old.len() - suffix]
+            .iter()
+            .chain(&frame.bounds[prefix..new.len() - suffix]);
+        Some(merge(changed.copied()))
+    }
+}
+
+// The device space area `command` can touch when drawn in `state`,
+// antialiasing included. Empty for commands that don't draw.
+pub fn command_bounds(command: &Command, state: &DrawState) -> Rect {
+    let local = match command {
+        Command::FillRectangle(x, y, width, height, _, _) => {
+            Rect::from_u32(*x, *y, *width, *height)
+        }
+        Command::WriteString(x, y, width, height, _, text) => {
+            text_bounds(Rect::from_u32(*x, *y, *width, *height), text)
+        }
+        Command::FillPath(path, _) => path.bounds(),
+        Command::StrokePath(path, stroke, _) => path.bounds().outset(stroke_extent(stroke)),
+        _ => return Rect::default(),
+    };
+
+    let bounds = local.transform(&state.transform()).outset(1.0);
+    match state.clip() {
+        Some(clip) => bounds.intersect(&clip),
+        None => bounds,
+    }
+}
+
+// Text is centered and can overflow its rectangle; assume no glyph is wider
+// than the font size.
+pub(crate) fn text_bounds(rect: Rect, text: &str) -> Rect {
+    let width = text.chars().count() as f32 * rect.height / 1.7;
+    let text = Rect::new(
+        rect.x + (rect.width - width) / 2.0,
+        rect.y,
+        width,
+        rect.height,
+    );
+    rect.union(&text)
+}
+
+// How far a stroke can reach past its path.
+#[inline]
+fn stroke_extent(stroke: &Stroke) -> f32 {
+    let half = stroke.width / 2.0;
+    match stroke.join {
+        LineJoin::Miter => half * stroke.miter_limit.max(SQRT_2),
+        LineJoin::Round | LineJoin::Bevel => half * SQRT_2,
+    }
+}
+
+// Rounds out and merges touching rectangles.
+fn merge<I: Iterator<Item = Rect>>(rects: I) -> Vec<Rect> {
+    let mut out: Vec<Rect> = vec![];
+    for rect in rects.filter(|r| !r.is_empty()) {
+        let mut rect = rect.round_out();
+        while let Some(i) = out.iter().position(|r| r.touches(&rect)) {
+            rect = rect.union(&out.swap_remove(i));
+        }
+        out.push(rect);
+    }
+
+    if out.len() > MAX_RECTS {
+        let union = out.iter().fold(Rect::default(), |u, r| u.union(r));
+        out = vec![union];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(x: u32, y: u32, color: Color) -> Command {
+        Command::FillRectangle(x, y, 10, 10, 0.0, color)
+    }
+
+    #[test]
+    fn redraws_everything_without_a_previous_frame() {
+        let mut tracker = DamageTracker::new();
+        let commands = [fill(0, 0, Color::BLACK)];
+        assert_eq!(tracker.update(Color::WHITE, &commands), None);
+        assert_eq!(tracker.update(Color::WHITE, &commands), Some(vec![]));
+        tracker.invalidate();
+        assert_eq!(tracker.update(Color::WHITE, &commands), None);
+        assert_eq!(tracker.update(Color::BLACK, &commands), None);
+    }
+
+    #[test]
+    fn damages_what_changed_in_both_frames() {
+        let mut tracker = DamageTracker::new();
+        let before = [
+            fill(0, 0, Color::BLACK),
+            fill(50, 0, Color::BLACK),
+            fill(0, 50, Color::BLACK),
+        ];
+        tracker.update(Color::WHITE, &before);
+
+        // The middle one moved: where it was and where it is are damaged,
+        // with a pixel for antialiasing.
+        let after = [
+            fill(0, 0, Color::BLACK),
+            fill(80, 30, Color::BLACK),
+            fill(0, 50, Color::BLACK),
+        ];
+        assert_eq!(
+            tracker.update(Color::WHITE, &after),
+            Some(vec![
+                Rect::new(49.0, -1.0, 12.0, 12.0),
+                Rect::new(79.0, 29.0, 12.0, 12.0),
+            ])
+        );
+
+        // Only the color changed, touching rectangles are merged.
+        let recolored = [
+            fill(0, 0, Color::BLACK),
+            fill(80, 30, Color::WHITE),
+            fill(0, 50, Color::BLACK),
+        ];
+        assert_eq!(
+            tracker.update(Color::WHITE, &recolored),
+            Some(vec![Rect::new(79.0, 29.0, 12.0, 12.0)])
+        );
+    }
+
+    #[test]
+    fn damages_in_device_space_within_the_clip() {
+        let mut tracker = DamageTracker::new();
+        let frame = |color| {
+            [
+                Command::PushClip(0, 0, 25, 100),
+                Command::PushTransform(Transform::translate(10.0, 20.0)),
+                fill(0, 0, color),
+                fill(10, 0, Color::BLACK),
+                Command::PopTransform,
+                Command::PopClip,
+            ]
+        };
+        tracker.update(Color::WHITE, &frame(Color::BLACK));
+        assert_eq!(
+            tracker.update(Color::WHITE, &frame(Color::WHITE)),
+            Some(vec![Rect::new(9.0, 19.0, 12.0, 12.0)])
+        );
+
+        // Changing a group changes everything drawn in it.
+        let moved = [
+            Command::PushClip(0, 0, 25, 100),
+            Command::PushTransform(Transform::translate(10.0, 30.0)),
+            fill(0, 0, Color::WHITE),
+            fill(10, 0, Color::BLACK),
+            Command::PopTransform,
+            Command::PopClip,
+        ];
+        assert_eq!(
+            tracker.update(Color::WHITE, &moved),
+            Some(vec![Rect::new(9.0, 19.0, 16.0, 22.0)])
+        );
+    }
+
+    #[test]
+    fn collapses_many_rectangles() {
+        let mut tracker = DamageTracker::new();
+        let frame = |color| (0..10).map(|i| fill(i * 20, 0, color)).collect::<Vec<_>>();
+        tracker.update(Color::WHITE, &frame(Color::BLACK));
+        assert_eq!(
+            tracker.update(Color::WHITE, &frame(Color::WHITE)),
+            Some(vec![Rect::new(-1.0, -1.0, 192.0, 12.0)])
+        );
+    }
+}
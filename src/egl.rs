@@ -0,0 +1,499 @@
+use std::{
+    os::raw::c_void,
+    ptr::null,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use egl::{Config, Context, Display, Surface};
+
+use crate::{AcureError, AeResult, Backend};
+
+pub use khronos_egl as egl;
+
+// EGL_EXT_buffer_age / EGL_KHR_partial_update
+const BUFFER_AGE: egl::Int = 0x313D;
+// Back buffers older than this are redrawn in full.
+pub(crate) const MAX_BUFFER_AGE: usize = 4;
+// EGL_MESA_platform_surfaceless
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+// EGL_KHR_swap_buffers_with_damage and EGL_KHR_partial_update take
+// x, y, width, height quadruples with the origin at the bottom left.
+type SwapBuffersWithDamage = unsafe extern "system" fn(
+    egl::EGLDisplay,
+    egl::EGLSurface,
+    *const egl::Int,
+    egl::Int,
+) -> egl::Boolean;
+type SetDamageRegion = unsafe extern "system" fn(
+    egl::EGLDisplay,
+    egl::EGLSurface,
+    *const egl::Int,
+    egl::Int,
+) -> egl::Boolean;
+
+// libEGL is loaded at runtime, so that a missing one can be reported.
+type Instance = egl::DynamicInstance<egl::EGL1_4>;
+
+fn load(backend: Backend) -> AeResult<Arc<Instance>> {
+    unsafe { Instance::load_required() }
+        .map(Arc::new)
+        .map_err(|e| AcureError::ContextCreation(backend, e.into()))
+}
+
+// eglGetDisplay returns the same EGLDisplay for the same native display, and
+// terminating it destroys everything created on it. So the displays
+// initialized here are counted, by handle.
+static DISPLAYS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+// An initialized display, terminated once the last reference to it is
+// dropped.
+struct DisplayRef {
+    instance: Arc<Instance>,
+    display: Display,
+}
+
+impl DisplayRef {
+    fn initialize(instance: &Arc<Instance>, display: Display, backend: Backend) -> AeResult<Self> {
+        let mut displays = DISPLAYS.lock().unwrap_or_else(PoisonError::into_inner);
+        instance
+            .initialize(display)
+            .map_err(|e| AcureError::ContextCreation(backend, e.into()))?;
+        let handle = display.as_ptr() as usize;
+        match displays.iter_mut().find(|(d, _)| *d == handle) {
+            Some((_, count)) => *count += 1,
+            None => displays.push((handle, 1)),
+        }
+        Ok(Self {
+            instance: instance.clone(),
+            display,
+        })
+    }
+}
+
+impl Drop for DisplayRef {
+    fn drop(&mut self) {
+        let mut displays = DISPLAYS.lock().unwrap_or_else(PoisonError::into_inner);
+        let handle = self.display.as_ptr() as usize;
+        if let Some(i) = displays.iter().position(|(d, _)| *d == handle) {
+            displays[i].1 -= 1;
+            if displays[i].1 == 0 {
+                displays.remove(i);
+                let _ = self.instance.terminate(self.display);
+            }
+        }
+    }
+}
+
+pub struct Egl {
+    instance: Arc<Instance>,
+    // Reported in errors.
+    backend: Backend,
+    display: Display,
+    config: Config,
+    // None when rendering offscreen.
+    window: Option<egl::NativeWindowType>,
+    // None for surfaceless contexts.
+    surface: Option<Surface>,
+    context: Context,
+    srgb: bool,
+    srgb_failed: bool,
+    buffer_age: bool,
+    swap_buffers_with_damage: Option<SwapBuffersWithDamage>,
+    set_damage_region: Option<SetDamageRegion>,
+    // Dropped after the context and surface.
+    _display: DisplayRef,
+}
+
+impl Egl {
+    // A context drawing to `window`, reporting errors as `backend`.
+    // `native_display` and `window` have to be valid for the platform EGL
+    // detects from the display, and outlive the context.
+    #[inline]
+    pub(crate) unsafe fn init(
+        backend: Backend,
+        native_display: egl::NativeDisplayType,
+        window: egl::NativeWindowType,
+    ) -> AeResult<Self> {
+        let instance = load(backend)?;
+        unsafe {
+            let Some(display) = instance.get_display(native_display) else {
+                return Err(AcureError::ContextCreation(
+                    backend,
+                    anyhow::anyhow!("Can't get an EGLDisplay"),
+                ));
+            };
+            let reference = DisplayRef::initialize(&instance, display, backend)?;
+            let (config, surface, context) =
+                Self::create_window_context(&instance, backend, display, window)?;
+
+            let mut egl = Self {
+                instance,
+                backend,
+                display,
+                config,
+                window: Some(window),
+                surface: Some(surface),
+                context,
+                srgb: false,
+                srgb_failed: false,
+                buffer_age: false,
+                swap_buffers_with_damage: None,
+                set_damage_region: None,
+                _display: reference,
+            };
+            egl.buffer_age = egl.has_extension("EGL_EXT_buffer_age")
+                || egl.has_extension("EGL_KHR_partial_update");
+            egl.swap_buffers_with_damage = if egl.has_extension("EGL_KHR_swap_buffers_with_damage")
+            {
+                egl.load("eglSwapBuffersWithDamageKHR")
+            } else if egl.has_extension("EGL_EXT_swap_buffers_with_damage") {
+                egl.load("eglSwapBuffersWithDamageEXT")
+            } else {
+                None
+            };
+            if egl.has_extension("EGL_KHR_partial_update") {
+                egl.set_damage_region = egl.load("eglSetDamageRegionKHR");
+            }
+            Ok(egl)
+        }
+    }
+
+    unsafe fn create_window_context(
+        instance: &Instance,
+        backend: Backend,
+        display: Display,
+        window: egl::NativeWindowType,
+    ) -> AeResult<(Config, Surface, Context)> {
+        let error = |e: egl::Error| AcureError::ContextCreation(backend, e.into());
+        let attr = vec![
+            egl::BUFFER_SIZE,
+            16,
+            egl::RED_SIZE,
+            8,
+            egl::GREEN_SIZE,
+            8,
+            egl::BLUE_SIZE,
+            8,
+            egl::ALPHA_SIZE,
+            8,
+            egl::STENCIL_SIZE,
+            8,
+            egl::SURFACE_TYPE,
+            egl::WINDOW_BIT,
+            egl::RENDERABLE_TYPE,
+            egl::OPENGL_ES2_BIT,
+            egl::NONE,
+        ];
+
+        let config = instance
+            .choose_first_config(display, &attr)
+            .map_err(error)?
+            .ok_or_else(|| {
+                AcureError::ContextCreation(backend, anyhow::anyhow!("No matching EGLConfig"))
+            })?;
+
+        let surface = instance
+            .create_window_surface(display, config, window, None)
+            .map_err(error)?;
+        let ctx_attr = vec![egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE];
+        let context = instance
+            .create_context(display, config, None, &ctx_attr)
+            .map_err(|e| {
+                // The display may outlive this context.
+                let _ = instance.destroy_surface(display, surface);
+                error(e)
+            })?;
+        Ok((config, surface, context))
+    }
+
+    // A context that isn't tied to a window or an X server, for drawing into
+    // framebuffer objects. Mesa's surfaceless platform is used when it's
+    // there, and a 1x1 pbuffer stands in for displays that can't make a
+    // context current without a surface.
+    pub fn offscreen() -> AeResult<Self> {
+        let instance = load(Backend::EGL)?;
+        let error = |e: egl::Error| AcureError::ContextCreation(Backend::EGL, e.into());
+
+        let display = unsafe {
+            has_extension(&instance, None, "EGL_MESA_platform_surfaceless")
+                .then(|| instance.upcast::<egl::EGL1_5>())
+                .flatten()
+                .and_then(|instance| {
+                    instance
+                        .get_platform_display(
+                            PLATFORM_SURFACELESS_MESA,
+                            egl::DEFAULT_DISPLAY,
+                            &[egl::ATTRIB_NONE],
+                        )
+                        .ok()
+                })
+                .or_else(|| instance.get_display(egl::DEFAULT_DISPLAY))
+        };
+        let Some(display) = display else {
+            return Err(AcureError::ContextCreation(
+                Backend::EGL,
+                anyhow::anyhow!("Can't get an EGLDisplay"),
+            ));
+        };
+        let reference = DisplayRef::initialize(&instance, display, Backend::EGL)?;
+        let surfaceless = has_extension(&instance, Some(display), "EGL_KHR_surfaceless_context");
+
+        let attr = [
+            egl::RED_SIZE,
+            8,
+            egl::GREEN_SIZE,
+            8,
+            egl::BLUE_SIZE,
+            8,
+            egl::ALPHA_SIZE,
+            8,
+            egl::SURFACE_TYPE,
+            if surfaceless { 0 } else { egl::PBUFFER_BIT },
+            egl::RENDERABLE_TYPE,
+            egl::OPENGL_ES2_BIT,
+            egl::NONE,
+        ];
+        let config = instance
+            .choose_first_config(display, &attr)
+            .map_err(error)?
+            .ok_or_else(|| {
+                AcureError::ContextCreation(Backend::EGL, anyhow::anyhow!("No matching EGLConfig"))
+            })?;
+
+        let surface = if surfaceless {
+            None
+        } else {
+            let attr = [egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE];
+            Some(
+                instance
+                    .create_pbuffer_surface(display, config, &attr)
+                    .map_err(error)?,
+            )
+        };
+        let context = instance
+            .create_context(
+                display,
+                config,
+                None,
+                &[egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE],
+            )
+            .map_err(|e| {
+                if let Some(surface) = surface {
+                    let _ = instance.destroy_surface(display, surface);
+                }
+                error(e)
+            })?;
+
+        let egl = Self {
+            instance,
+            backend: Backend::EGL,
+            display,
+            config,
+            window: None,
+            surface,
+            context,
+            srgb: false,
+            srgb_failed: false,
+            buffer_age: false,
+            swap_buffers_with_damage: None,
+            set_damage_region: None,
+            _display: reference,
+        };
+        egl.make_current()?;
+        Ok(egl)
+    }
+
+    #[inline]
+    unsafe fn load<F>(&self, name: &str) -> Option<F> {
+        self.instance
+            .get_proc_address(name)
+            .map(|f| std::mem::transmute_copy(&f))
+    }
+
+    // How many frames ago the back buffer was drawn, or 0 if its contents
+    // are undefined.
+    #[inline]
+    pub fn buffer_age(&self) -> usize {
+        let Some(surface) = self.surface.filter(|_| self.buffer_age) else {
+            return 0;
+        };
+        self.instance
+            .query_surface(self.display, surface, BUFFER_AGE)
+            .unwrap_or(0)
+            .max(0) as usize
+    }
+
+    // Tells the driver only `rects` will be drawn to this frame. Has to be
+    // called before drawing.
+    #[inline]
+    pub fn set_damage_region(&self, rects: &[egl::Int]) {
+        if let (Some(set_damage_region), Some(surface)) = (self.set_damage_region, self.surface) {
+            unsafe {
+                set_damage_region(
+                    self.display.as_ptr(),
+                    surface.as_ptr(),
+                    rects.as_ptr(),
+                    (rects.len() / 4) as egl::Int,
+                );
+            }
+        }
+    }
+
+    #[inline]
+    pub fn swap_buffers_with_damage(&self, rects: &[egl::Int]) -> AeResult<()> {
+        match (self.swap_buffers_with_damage, self.surface) {
+            (Some(swap_buffers_with_damage), Some(surface)) => {
+                let swapped = unsafe {
+                    swap_buffers_with_damage(
+                        self.display.as_ptr(),
+                        surface.as_ptr(),
+                        rects.as_ptr(),
+                        (rects.len() / 4) as egl::Int,
+                    )
+                };
+                match self.instance.get_error() {
+                    Some(e) if swapped != egl::TRUE => Err(self.error(e)),
+                    _ => Ok(()),
+                }
+            }
+            _ => self.swap_buffers(),
+        }
+    }
+
+    #[inline]
+    pub fn has_extension(&self, name: &str) -> bool {
+        has_extension(&self.instance, Some(self.display), name)
+    }
+
+    // Recreates the window surface with an sRGB (or linear) colorspace, so
+    // that blending happens in linear light and writes are encoded to sRGB.
+    pub fn set_srgb(&mut self, srgb: bool) -> bool {
+        if self.srgb == srgb {
+            return true;
+        }
+        let Some(window) = self.window else {
+            return false;
+        };
+        if srgb && (self.srgb_failed || !self.has_extension("EGL_KHR_gl_colorspace")) {
+            return false;
+        }
+
+        let colorspace = if srgb {
+            egl::GL_COLORSPACE_SRGB
+        } else {
+            egl::GL_COLORSPACE_LINEAR
+        };
+        let attr = [egl::GL_COLORSPACE, colorspace, egl::NONE];
+
+        // A native window can only have one EGLSurface at a time.
+        let _ = self.instance.make_current(self.display, None, None, None);
+        if let Some(surface) = self.surface {
+            let _ = self.instance.destroy_surface(self.display, surface);
+        }
+
+        let surface = unsafe {
+            self.instance
+                .create_window_surface(self.display, self.config, window, Some(&attr))
+        };
+        let result = match surface {
+            Ok(surface) => {
+                self.surface = Some(surface);
+                self.srgb = srgb;
+                true
+            }
+            Err(e) => {
+                warn!("Can't create an sRGB EGLSurface: {:?}", e);
+                self.srgb_failed = true;
+                self.surface = unsafe {
+                    self.instance
+                        .create_window_surface(self.display, self.config, window, None)
+                        .ok()
+                };
+                self.srgb = false;
+                false
+            }
+        };
+        let _ = self.make_current();
+        result
+    }
+
+    #[inline]
+    pub fn make_current(&self) -> AeResult<()> {
+        self.instance
+            .make_current(self.display, self.surface, self.surface, Some(self.context))
+            .map_err(|e| self.error(e))
+    }
+
+    #[inline]
+    pub fn swap_intervals(&self, interval: bool) {
+        let _ = self.instance.swap_interval(self.display, interval as i32);
+    }
+
+    #[inline]
+    pub fn swap_buffers(&self) -> AeResult<()> {
+        if let Some(surface) = self.surface {
+            self.instance
+                .swap_buffers(self.display, surface)
+                .map_err(|e| self.error(e))?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn error(&self, error: egl::Error) -> AcureError {
+        match error {
+            egl::Error::ContextLost => AcureError::DeviceLost(self.backend),
+            e => AcureError::BackendError(self.backend, e.into()),
+        }
+    }
+
+    // Null for functions the driver doesn't have.
+    #[inline]
+    pub fn get_proc_address(&self, procname: &str) -> *const c_void {
+        self.instance
+            .get_proc_address(procname)
+            .map_or(null(), |f| f as *const c_void)
+    }
+}
+
+impl Drop for Egl {
+    fn drop(&mut self) {
+        let _ = self.instance.destroy_context(self.display, self.context);
+        if let Some(surface) = self.surface {
+            let _ = self.instance.destroy_surface(self.display, surface);
+        }
+    }
+}
+
+#[inline]
+fn has_extension(instance: &Instance, display: Option<Display>, name: &str) -> bool {
+    match instance.query_string(display, egl::EXTENSIONS) {
+        Ok(extensions) => extensions
+            .to_string_lossy()
+            .split_whitespace()
+            .any(|e| e == name),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropping_a_context_keeps_the_shared_display() {
+        let Ok(first) = Egl::offscreen() else {
+            return;
+        };
+        let second = Egl::offscreen().unwrap();
+        assert_eq!(first.display, second.display);
+        drop(first);
+        // A terminated display only shows once the context is made current
+        // again.
+        let _ = second
+            .instance
+            .make_current(second.display, None, None, None);
+        second.make_current().unwrap();
+    }
+}
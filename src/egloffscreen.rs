@@ -0,0 +1,226 @@
+const FRAGMENT: &str = include_str!("shader/shader.frag");
+const VERTEX: &str = include_str!("shader/shader.vert");
+
+use crate::{
+    egl::Egl,
+    gl::{
+        capabilities, clear, compile_shader, create_program, enable_blending, max_viewport_size,
+        read_pixels, Batch,
+    },
+    surface::{Capabilities, DrawState},
+    AeResult, Color, ColorSpace, Image, Rect,
+};
+
+// Renders into a framebuffer object, without a window or an X server.
+pub struct EglOffscreenSurface {
+    batch: Batch,
+    framebuffer: u32,
+    // Color, then stencil.
+    renderbuffers: [u32; 2],
+    width: u32,
+    height: u32,
+    max_size: u32,
+    state: DrawState,
+    // Dropped last, the context has to outlive the GL objects.
+    egl: Egl,
+}
+
+impl EglOffscreenSurface {
+    pub fn new(width: u32, height: u32) -> AeResult<Self> {
+        let egl = Egl::offscreen()?;
+        gl::load_with(|s| egl.get_proc_address(s));
+
+        let vertex = compile_shader(gl::VERTEX_SHADER, VERTEX)?;
+        let fragment = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT)?;
+        let program = create_program(&[vertex, fragment]);
+        enable_blending();
+        let mut surface = unsafe {
+            gl::DeleteShader(vertex);
+            gl::DeleteShader(fragment);
+            let program = program?;
+
+            let mut framebuffer = 0;
+            gl::GenFramebuffers(1, &mut framebuffer);
+            let mut renderbuffers = [0; 2];
+            gl::GenRenderbuffers(2, renderbuffers.as_mut_ptr());
+            let mut max_renderbuffer_size = 0;
+            gl::GetIntegerv(gl::MAX_RENDERBUFFER_SIZE, &mut max_renderbuffer_size);
+
+            Self {
+                batch: Batch::new(program),
+                framebuffer,
+                renderbuffers,
+                width: 0,
+                height: 0,
+                max_size: max_viewport_size().min(max_renderbuffer_size.max(0) as u32),
+                state: DrawState::new(),
+                egl,
+            }
+        };
+        crate::Surface::surface_resize(&mut surface, width, height);
+        Ok(surface)
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl crate::Surface for EglOffscreenSurface {
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        let _ = self.egl.make_current();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            for (renderbuffer, format, attachment) in [
+                (self.renderbuffers[0], gl::RGBA8, gl::COLOR_ATTACHMENT0),
+                (
+                    self.renderbuffers[1],
+                    gl::STENCIL_INDEX8,
+                    gl::STENCIL_ATTACHMENT,
+                ),
+            ] {
+                gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+                gl::RenderbufferStorage(
+                    gl::RENDERBUFFER,
+                    format,
+                    self.width as i32,
+                    self.height as i32,
+                );
+                gl::FramebufferRenderbuffer(
+                    gl::FRAMEBUFFER,
+                    attachment,
+                    gl::RENDERBUFFER,
+                    renderbuffer,
+                );
+            }
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                warn!("Incomplete framebuffer: {:#x}", status);
+            }
+        }
+        self.batch
+            .set_viewport(self.width as f32, self.height as f32);
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        capabilities(self.max_size)
+    }
+
+    // Renderbuffers are 8 bit per channel and can't be sRGB encoded.
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        space == ColorSpace::Srgb
+    }
+
+    fn begin(&mut self) -> AeResult<()> {
+        self.egl.make_current()?;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+        self.state.reset();
+        self.batch.set_scissor(None);
+        Ok(())
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        clear(&color, false);
+    }
+
+    #[inline]
+    fn command(
+        &mut self,
+        command: &crate::Command,
+        _align: crate::AlignMode,
+        _layout: crate::LayoutMode,
+    ) -> AeResult<()> {
+        self.batch.command(&mut self.state, command);
+        Ok(())
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        self.batch.flush();
+        unsafe {
+            gl::Flush();
+        }
+        Ok(())
+    }
+
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        self.egl.make_current()?;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        }
+        Ok(read_pixels(&rect, self.width, self.height))
+    }
+}
+
+impl Drop for EglOffscreenSurface {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current();
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteRenderbuffers(2, self.renderbuffers.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AlignMode, Command, LayoutMode, Path, Surface};
+
+    fn draw(command: Command) -> Option<Image> {
+        let mut surface = EglOffscreenSurface::new(64, 32).ok()?;
+        surface.begin().unwrap();
+        surface.clear(Color::BLACK);
+        surface
+            .command(&command, AlignMode::CenterAligned, LayoutMode::NoCare)
+            .unwrap();
+        surface.end().unwrap();
+        surface.read_pixels(Rect::new(0.0, 0.0, 64.0, 32.0)).ok()
+    }
+
+    #[test]
+    fn fills_a_polygon_with_a_full_batch_of_points() {
+        let mut path = Path::new();
+        let count = u16::MAX as usize + 1;
+        for i in 0..count {
+            let angle = i as f32 / count as f32 * std::f32::consts::TAU;
+            let (x, y) = (16.0 + angle.cos() * 12.0, 16.0 + angle.sin() * 12.0);
+            if i == 0 {
+                path.move_to(x, y);
+            } else {
+                path.line_to(x, y);
+            }
+        }
+        path.close();
+        let Some(image) = draw(Command::FillPath(path, Color::WHITE)) else {
+            return;
+        };
+        assert_eq!(image.pixel(16, 16), Some(0xffffffff));
+        assert_eq!(image.pixel(1, 1), Some(0xff000000));
+    }
+
+    #[test]
+    fn writes_text() {
+        if crate::font::system_font().is_none() {
+            return;
+        }
+        let text = Command::WriteString(0, 0, 64, 32, Color::WHITE, "HH".to_string());
+        let Some(image) = draw(text) else {
+            return;
+        };
+        assert!(image.pixels().contains(&0xffffffff));
+    }
+}
@@ -0,0 +1,578 @@
+use std::{
+    ffi::c_ulong,
+    fs::{File, OpenOptions},
+    os::fd::AsRawFd,
+};
+
+use memmap2::{MmapMut, MmapOptions};
+
+use crate::{
+    image::pixel_bounds,
+    software::SoftwareSurface,
+    surface::{Capabilities, Surface},
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, Image, LayoutMode, Rect,
+};
+
+// From linux/fb.h.
+const FBIOGET_VSCREENINFO: c_ulong = 0x4600;
+const FBIOPUT_VSCREENINFO: c_ulong = 0x4601;
+const FBIOGET_FSCREENINFO: c_ulong = 0x4602;
+const FBIOPAN_DISPLAY: c_ulong = 0x4606;
+const FBIO_WAITFORVSYNC: c_ulong = 0x40044620;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct FbFixScreeninfo {
+    id: [u8; 16],
+    smem_start: c_ulong,
+    smem_len: u32,
+    kind: u32,
+    kind_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    // 5 bits of red, 6 of green and 5 of blue in a native-endian u16.
+    Rgb565,
+    // 0xXXRRGGBB in a native-endian u32.
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    #[inline]
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Xrgb8888 => 4,
+        }
+    }
+}
+
+// Draws with the software rasterizer into a Linux framebuffer device, for
+// machines without a display server. Where the driver can pan, frames are
+// drawn below the visible one and flipped to.
+pub struct FbdevSurface {
+    file: File,
+    memory: MmapMut,
+    format: PixelFormat,
+    // Bytes per row.
+    stride: usize,
+    // The mode, or None if the file isn't a framebuffer device.
+    var: Option<FbVarScreeninfo>,
+    // The mode to restore on drop, if the virtual size was changed for a
+    // second buffer.
+    original: Option<FbVarScreeninfo>,
+    // 2 if the buffers are stacked vertically and panned between.
+    buffers: u32,
+    // The buffer on screen.
+    front: u32,
+    vsync: bool,
+    inner: SoftwareSurface,
+    // The part of the frame that changed, or None for all of it.
+    damage: Option<Rect>,
+    // What the frame before changed, which the back buffer doesn't have
+    // yet. None for all of it.
+    previous: Option<Rect>,
+}
+
+impl FbdevSurface {
+    // Opens e.g. /dev/fb0 in its current mode.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> AeResult<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let fd = file.as_raw_fd();
+        let mut var = FbVarScreeninfo::default();
+        let mut fix = FbFixScreeninfo::default();
+        unsafe {
+            if libc::ioctl(fd, FBIOGET_VSCREENINFO as _, &mut var) < 0
+                || libc::ioctl(fd, FBIOGET_FSCREENINFO as _, &mut fix) < 0
+            {
+                return Err(error(anyhow::anyhow!(
+                    "{} isn't a framebuffer device: {}",
+                    path.display(),
+                    std::io::Error::last_os_error()
+                )));
+            }
+        }
+
+        let format = match (
+            var.bits_per_pixel,
+            (var.red.offset, var.red.length),
+            (var.green.offset, var.green.length),
+            (var.blue.offset, var.blue.length),
+        ) {
+            (16, (11, 5), (5, 6), (0, 5)) => PixelFormat::Rgb565,
+            (32, (16, 8), (8, 8), (0, 8)) => PixelFormat::Xrgb8888,
+            _ => {
+                return Err(error(anyhow::anyhow!(
+                    "Unsupported pixel format of {} bits per pixel",
+                    var.bits_per_pixel
+                )))
+            }
+        };
+
+        // Asks for room for a second buffer below the first one, if the
+        // driver can pan to it.
+        let mut original = None;
+        if fix.ypanstep != 0 && var.yres_virtual < var.yres * 2 {
+            let mut double = var;
+            double.yres_virtual = var.yres * 2;
+            double.yoffset = 0;
+            unsafe {
+                if libc::ioctl(fd, FBIOPUT_VSCREENINFO as _, &mut double) == 0
+                    && libc::ioctl(fd, FBIOGET_FSCREENINFO as _, &mut fix) == 0
+                {
+                    original = Some(var);
+                    var = double;
+                }
+            }
+        }
+        let stride = fix.line_length as usize;
+        let frame = stride * var.yres as usize;
+        let buffers = if fix.ypanstep != 0
+            && var.yres_virtual >= var.yres * 2
+            && fix.smem_len as usize >= frame * 2
+        {
+            2
+        } else {
+            1
+        };
+        let front = if buffers == 2 && var.yoffset == var.yres {
+            1
+        } else {
+            0
+        };
+
+        let memory = unsafe {
+            MmapOptions::new()
+                .len(frame * buffers as usize)
+                .map_mut(&file)?
+        };
+        Ok(Self {
+            file,
+            memory,
+            format,
+            stride,
+            var: Some(var),
+            original,
+            buffers,
+            front,
+            vsync: false,
+            inner: SoftwareSurface::new(var.xres, var.yres),
+            damage: None,
+            previous: None,
+        })
+    }
+
+    // For a file that isn't a framebuffer device, e.g. to test with. It has
+    // to hold `stride * height` bytes, or twice that to have two buffers
+    // flipped between like a device that can pan.
+    pub fn with_mode<P: AsRef<std::path::Path>>(
+        path: P,
+        width: u32,
+        height: u32,
+        stride: usize,
+        format: PixelFormat,
+    ) -> AeResult<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = stride * height as usize;
+        if stride < width as usize * format.bytes_per_pixel() || file.metadata()?.len() < len as u64
+        {
+            return Err(error(anyhow::anyhow!(
+                "The file is too small for {}x{} pixels with a stride of {}",
+                width,
+                height,
+                stride
+            )));
+        }
+        let buffers = if file.metadata()?.len() >= len as u64 * 2 {
+            2
+        } else {
+            1
+        };
+        let memory = unsafe {
+            MmapOptions::new()
+                .len(len * buffers as usize)
+                .map_mut(&file)?
+        };
+        Ok(Self {
+            file,
+            memory,
+            format,
+            stride,
+            var: None,
+            original: None,
+            buffers,
+            front: 0,
+            vsync: false,
+            inner: SoftwareSurface::new(width, height),
+            damage: None,
+            previous: None,
+        })
+    }
+
+    #[inline]
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    // The first row on screen, of the buffers stacked vertically.
+    #[inline]
+    pub fn yoffset(&self) -> u32 {
+        self.front * self.inner.height()
+    }
+
+    // The premultiplied pixels are what they look like over black.
+    fn copy(&mut self, buffer: u32, rect: &Rect) {
+        let (width, height) = (self.inner.width(), self.inner.height());
+        let (x, y, w, h) = pixel_bounds(rect, width, height);
+        let bytes = self.format.bytes_per_pixel();
+        let base = buffer as usize * self.stride * height as usize;
+        let pixels = self.inner.pixels();
+        for row in y as usize..(y + h) as usize {
+            let from = &pixels[row * width as usize + x as usize..][..w as usize];
+            let offset = base + row * self.stride + x as usize * bytes;
+            let to = &mut self.memory[offset..offset + w as usize * bytes];
+            match self.format {
+                PixelFormat::Rgb565 => {
+                    for (to, pixel) in to.chunks_exact_mut(2).zip(from) {
+                        to.copy_from_slice(&rgb565(*pixel).to_ne_bytes());
+                    }
+                }
+                PixelFormat::Xrgb8888 => {
+                    for (to, pixel) in to.chunks_exact_mut(4).zip(from) {
+                        to.copy_from_slice(&pixel.to_ne_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    // A plain file has nothing to pan, only which buffer is in front
+    // changes.
+    fn pan(&mut self, buffer: u32) -> AeResult<()> {
+        if let Some(var) = &mut self.var {
+            var.xoffset = 0;
+            var.yoffset = buffer * var.yres;
+            let fd = self.file.as_raw_fd();
+            unsafe {
+                // Not every driver can wait, and then the flip may tear.
+                if self.vsync {
+                    let mut screen = 0u32;
+                    libc::ioctl(fd, FBIO_WAITFORVSYNC as _, &mut screen);
+                }
+                if libc::ioctl(fd, FBIOPAN_DISPLAY as _, var as *mut FbVarScreeninfo) < 0 {
+                    return Err(error(anyhow::anyhow!(
+                        "Can't pan to buffer {}: {}",
+                        buffer,
+                        std::io::Error::last_os_error()
+                    )));
+                }
+            }
+        }
+        self.front = buffer;
+        Ok(())
+    }
+}
+
+impl Surface for FbdevSurface {
+    // The size is the mode's.
+    #[inline]
+    fn surface_resize(&mut self, _width: u32, _height: u32) {}
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        self.inner.set_color_space(space)
+    }
+
+    // Only flips wait for vertical blanks.
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        self.vsync = vsync;
+        self.buffers == 2
+    }
+
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        self.damage = None;
+        self.inner.begin()
+    }
+
+    // Anything else drawing to the framebuffer, e.g. the console, makes
+    // `Acure::invalidate` necessary.
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.inner.set_damage(damage) {
+            return false;
+        }
+        self.damage = Some(damage.iter().fold(Rect::default(), |b, r| b.union(r)));
+        true
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        self.inner.clear(color);
+    }
+
+    #[inline]
+    fn command(&mut self, command: &Command, align: AlignMode, layout: LayoutMode) -> AeResult<()> {
+        self.inner.command(command, align, layout)
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        self.inner.end()?;
+        let full = Rect::from_u32(0, 0, self.inner.width(), self.inner.height());
+        let damage = self.damage.unwrap_or(full);
+        if self.buffers == 1 {
+            self.copy(0, &damage);
+            return Ok(());
+        }
+
+        // The back buffer still has the frame before the last one.
+        let back = 1 - self.front;
+        let rect = match self.previous {
+            Some(previous) => damage.union(&previous),
+            None => full,
+        };
+        self.copy(back, &rect);
+        let panned = self.pan(back);
+        self.previous = panned.is_ok().then_some(damage);
+        panned
+    }
+
+    #[inline]
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        self.inner.read_pixels(rect)
+    }
+}
+
+impl Drop for FbdevSurface {
+    // Leaves the console where it was.
+    fn drop(&mut self) {
+        if self.front != 0 {
+            let _ = self.pan(0);
+        }
+        if let Some(mut original) = self.original {
+            unsafe {
+                libc::ioctl(
+                    self.file.as_raw_fd(),
+                    FBIOPUT_VSCREENINFO as _,
+                    &mut original,
+                );
+            }
+        }
+    }
+}
+
+#[inline]
+fn rgb565(pixel: u32) -> u16 {
+    (((pixel >> 8) & 0xf800) | ((pixel >> 5) & 0x07e0) | ((pixel >> 3) & 0x001f)) as u16
+}
+
+fn error(error: anyhow::Error) -> AcureError {
+    AcureError::BackendError(Backend::Fbdev, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // A file of `len` 0xaa bytes, removed on drop.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(len: usize) -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "acure-fbdev-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::write(&path, vec![0xaa; len]).unwrap();
+            Self(path)
+        }
+
+        fn read(&self) -> Vec<u8> {
+            std::fs::read(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn draw(surface: &mut FbdevSurface, damage: Option<Rect>, color: Color) {
+        surface.begin().unwrap();
+        if let Some(damage) = damage {
+            assert!(surface.set_damage(&[damage]));
+        }
+        surface.clear(color);
+        surface.end().unwrap();
+    }
+
+    // The rows of buffer `buffer`, without the padding.
+    fn rows(data: &[u8], buffer: usize, width: usize, height: usize, stride: usize) -> Vec<u8> {
+        let base = buffer * stride * height;
+        (0..height)
+            .flat_map(|row| &data[base + row * stride..][..width * 4])
+            .copied()
+            .collect()
+    }
+
+    fn xrgb(surface: &mut FbdevSurface) -> Vec<u8> {
+        let (width, height) = (surface.inner.width(), surface.inner.height());
+        surface
+            .read_pixels(Rect::from_u32(0, 0, width, height))
+            .unwrap()
+            .pixels()
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn writes_xrgb8888_rows_and_keeps_the_padding() {
+        let file = TempFile::new(20 * 3);
+        let mut surface =
+            FbdevSurface::with_mode(&file.0, 4, 3, 20, PixelFormat::Xrgb8888).unwrap();
+        draw(&mut surface, None, Color::ARGB(255, 0x12, 0x34, 0x56));
+
+        let data = file.read();
+        for row in data.chunks(20) {
+            for pixel in row[..16].chunks(4) {
+                assert_eq!(u32::from_ne_bytes(pixel.try_into().unwrap()), 0xff123456);
+            }
+            assert_eq!(row[16..], [0xaa; 4]);
+        }
+    }
+
+    #[test]
+    fn writes_rgb565() {
+        let file = TempFile::new(2 * 4 * 2);
+        let mut surface = FbdevSurface::with_mode(&file.0, 4, 2, 8, PixelFormat::Rgb565).unwrap();
+        draw(&mut surface, None, Color::ARGB(255, 255, 0, 0));
+        draw(
+            &mut surface,
+            Some(Rect::from_u32(0, 1, 4, 1)),
+            Color::ARGB(255, 0, 0, 255),
+        );
+
+        let data = file.read();
+        let pixels = data
+            .chunks(2)
+            .map(|pixel| u16::from_ne_bytes(pixel.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pixels,
+            [0xf800, 0xf800, 0xf800, 0xf800, 0x1f, 0x1f, 0x1f, 0x1f]
+        );
+        assert_eq!(rgb565(0xff00ff00), 0x07e0);
+        assert_eq!(rgb565(0xffffffff), 0xffff);
+    }
+
+    #[test]
+    fn flips_between_two_buffers() {
+        let (width, height, stride) = (8, 4, 40);
+        let file = TempFile::new(stride * height * 2);
+        let mut surface = FbdevSurface::with_mode(
+            &file.0,
+            width as u32,
+            height as u32,
+            stride,
+            PixelFormat::Xrgb8888,
+        )
+        .unwrap();
+        assert_eq!(surface.yoffset(), 0);
+
+        let frames = [
+            (None, Color::ARGB(255, 255, 0, 0)),
+            (
+                Some(Rect::from_u32(1, 1, 2, 2)),
+                Color::ARGB(255, 0, 255, 0),
+            ),
+            (
+                Some(Rect::from_u32(4, 0, 3, 1)),
+                Color::ARGB(255, 0, 0, 255),
+            ),
+            (None, Color::ARGB(255, 255, 255, 255)),
+        ];
+        for (i, (damage, color)) in frames.into_iter().enumerate() {
+            draw(&mut surface, damage, color);
+            let front = (i + 1) % 2;
+            assert_eq!(surface.yoffset(), (front * height) as u32);
+            let data = file.read();
+            assert_eq!(
+                rows(&data, front, width, height, stride),
+                xrgb(&mut surface),
+                "frame {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_file_too_small_for_the_mode() {
+        let file = TempFile::new(10);
+        assert!(FbdevSurface::with_mode(&file.0, 4, 3, 16, PixelFormat::Xrgb8888).is_err());
+        assert!(FbdevSurface::with_mode(&file.0, 4, 1, 8, PixelFormat::Xrgb8888).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::Path;
+
+// Font data for the backends that lay out text themselves.
+pub(crate) struct Font {
+    pub data: Vec<u8>,
+    pub index: u32,
+}
+
+// Looks for the font the other backends use, then any sans-serif, then
+// whatever is installed. fontdb's sans-serif is Arial unless configured.
+pub(crate) fn system_font() -> Option<Font> {
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+    let query = fontdb::Query {
+        families: &[fontdb::Family::Name("Yu Gothic"), fontdb::Family::SansSerif],
+        ..Default::default()
+    };
+    let id = db.query(&query).or_else(|| {
+        db.faces()
+            .find(|face| {
+                face.style == fontdb::Style::Normal
+                    && face.weight == fontdb::Weight::NORMAL
+                    && face.families.iter().any(|(name, _)| name.contains("Sans"))
+            })
+            .or_else(|| db.faces().next())
+            .map(|face| face.id)
+    });
+    let font = id.and_then(|id| {
+        db.with_face_data(id, |data, index| Font {
+            data: data.to_vec(),
+            index,
+        })
+    });
+    if font.is_none() {
+        warn!("No font found, text will not be drawn");
+    }
+    font
+}
+
+// A line of text centered in a box, for the backends without a text API.
+pub(crate) struct Line {
+    // Pixels per em.
+    pub size: f32,
+    // Where the first glyph starts, on the baseline.
+    pub origin: (f32, f32),
+    // Each glyph with its distance from the origin.
+    pub glyphs: Vec<(ttf_parser::GlyphId, f32)>,
+}
+
+// Sets `text` at a size that fits the box's height, without shaping.
+pub(crate) fn layout(
+    face: &ttf_parser::Face,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    text: &str,
+) -> Line {
+    let size = height / 1.7;
+    let scale = size / face.units_per_em() as f32;
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut advance = 0.0;
+    for c in text.chars() {
+        let glyph = face.glyph_index(c).unwrap_or_default();
+        glyphs.push((glyph, advance));
+        advance += face.glyph_hor_advance(glyph).unwrap_or(0) as f32 * scale;
+    }
+    let baseline =
+        y + height / 2.0 + (face.ascender() as f32 + face.descender() as f32) / 2.0 * scale;
+    Line {
+        size,
+        origin: (x + (width - advance) / 2.0, baseline),
+        glyphs,
+    }
+}
+
+// Collects a glyph's outline in font units, which point up.
+#[allow(dead_code)]
+pub(crate) struct Outline(pub Path);
+
+impl ttf_parser::OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.cubic_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+}
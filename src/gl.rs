@@ -1,36 +1,56 @@
 use std::{
-    ffi::{c_void, CStr, CString},
+    collections::HashMap,
+    ffi::{c_void, CString},
     ptr::{null, null_mut},
 };
 
-use gl::types::{GLfloat, GLsizeiptr};
+use gl::types::{GLchar, GLenum, GLsizeiptr};
+
+use crate::{
+    font::{layout, system_font, Font, Outline},
+    image::pixel_bounds,
+    surface::{Capabilities, DrawState},
+    AcureError, AeResult, Color, Command, FillRule, Image, Path, Rect, Transform,
+};
+
+// Maximum distance in pixels between a curve and its flattened polyline.
+const TOLERANCE: f32 = 0.25;
+
+// Vertices are x, y in device space followed by r, g, b, a.
+const STRIDE: usize = 6;
+// Indices are 16 bits for GLES 2; the batch is flushed early when it has
+// this many vertices.
+const MAX_VERTICES: usize = u16::MAX as usize + 1;
 
 #[inline]
-pub fn compile_shader(shader_type: u32, source: &str) -> u32 {
-    let mut result = 0;
+pub fn compile_shader(shader_type: u32, source: &str) -> AeResult<u32> {
+    let source =
+        CString::new(source).map_err(|e| AcureError::ShaderCompile { log: e.to_string() })?;
 
     unsafe {
         let shader = gl::CreateShader(shader_type);
-        gl::ShaderSource(shader, 1, &CString::new(source).unwrap().as_ptr(), null());
+        gl::ShaderSource(shader, 1, &source.as_ptr(), null());
         gl::CompileShader(shader);
 
+        let mut result = 0;
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut result);
         if (result as u8) == gl::FALSE {
             let mut length = 0;
             gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
-            let mut log = Vec::with_capacity(length as usize);
-            gl::GetShaderInfoLog(shader, length, null_mut(), log.as_mut_ptr());
-            let log = CStr::from_ptr(log.as_ptr());
-            let log_str = log.to_str().unwrap();
-            panic!("{:#?}", log_str);
+            let mut log = vec![0u8; length.max(0) as usize];
+            gl::GetShaderInfoLog(shader, length, null_mut(), log.as_mut_ptr() as *mut GLchar);
+            gl::DeleteShader(shader);
+            return Err(AcureError::ShaderCompile {
+                log: info_log(&log),
+            });
         }
 
-        shader
+        Ok(shader)
     }
 }
 
 #[inline]
-pub fn create_program(shaders: &[u32]) -> u32 {
+pub fn create_program(shaders: &[u32]) -> AeResult<u32> {
     unsafe {
         let program = gl::CreateProgram();
         for i in shaders {
@@ -46,21 +66,154 @@ pub fn create_program(shaders: &[u32]) -> u32 {
         if (result as u8) == gl::FALSE {
             let mut length = 0;
             gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
-            let mut log = Vec::with_capacity(length as usize);
-            gl::GetProgramInfoLog(program, length, null_mut(), log.as_mut_ptr());
-            let log = CStr::from_ptr(log.as_ptr());
-            let log_str = log.to_str().unwrap();
-            panic!("{:#?}", log_str);
+            let mut log = vec![0u8; length.max(0) as usize];
+            gl::GetProgramInfoLog(program, length, null_mut(), log.as_mut_ptr() as *mut GLchar);
+            gl::DeleteProgram(program);
+            return Err(AcureError::ShaderCompile {
+                log: info_log(&log),
+            });
+        }
+
+        Ok(program)
+    }
+}
+
+// Info logs are NUL terminated and not necessarily UTF-8.
+#[inline]
+fn info_log(log: &[u8]) -> String {
+    let end = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+    String::from_utf8_lossy(&log[..end]).trim_end().to_owned()
+}
+
+// Mirrors a clip with the scissor test. GL counts rows from the bottom,
+// hence the surface height.
+pub fn set_scissor(clip: Option<Rect>, height: f32) {
+    unsafe {
+        match clip {
+            Some(clip) => {
+                let clip = clip.round_out();
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(
+                    clip.x as i32,
+                    (height - clip.bottom()) as i32,
+                    clip.width as i32,
+                    clip.height as i32,
+                );
+            }
+            None => gl::Disable(gl::SCISSOR_TEST),
         }
+    }
+}
+
+// Straight alpha colors are blended into premultiplied pixels, alpha
+// included, so translucent surfaces composite correctly.
+#[inline]
+pub fn enable_blending() {
+    unsafe {
+        gl::Enable(gl::BLEND);
+        gl::BlendFuncSeparate(
+            gl::SRC_ALPHA,
+            gl::ONE_MINUS_SRC_ALPHA,
+            gl::ONE,
+            gl::ONE_MINUS_SRC_ALPHA,
+        );
+    }
+}
+
+// Clears the color and stencil buffers, premultiplied like blending leaves
+// the pixels.
+pub fn clear(color: &Color, linear: bool) {
+    let [r, g, b, a] = if linear {
+        color.to_linear_rgba()
+    } else {
+        color.to_rgba()
+    };
+    unsafe {
+        gl::ClearColor(r * a, g * a, b * a, a);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+    }
+}
+
+// The batch has no multisampling.
+#[inline]
+pub fn capabilities(max_size: u32) -> Capabilities {
+    Capabilities {
+        max_texture_size: Some(max_size),
+        text: true,
+        antialiasing: false,
+        clip: true,
+        readback: true,
+    }
+}
 
-        program
+// The largest viewport the current context can draw to.
+#[inline]
+pub fn max_viewport_size() -> u32 {
+    let mut dims = [0; 2];
+    unsafe {
+        gl::GetIntegerv(gl::MAX_VIEWPORT_DIMS, dims.as_mut_ptr());
+    }
+    dims[0].min(dims[1]).max(0) as u32
+}
+
+// Reads `rect` of the bound framebuffer, `width` x `height` pixels big.
+pub fn read_pixels(rect: &Rect, width: u32, height: u32) -> Image {
+    let (x, y, width, rows) = pixel_bounds(rect, width, height);
+    let mut rgba = vec![0u8; width as usize * rows as usize * 4];
+    if !rgba.is_empty() {
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                x as i32,
+                (height - y - rows) as i32,
+                width as i32,
+                rows as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                rgba.as_mut_ptr() as *mut c_void,
+            );
+        }
     }
+
+    // GL's rows start at the bottom.
+    let pixels = rgba
+        .chunks_exact(width.max(1) as usize * 4)
+        .rev()
+        .flat_map(|row| row.chunks_exact(4))
+        .map(|p| u32::from_be_bytes([p[3], p[0], p[1], p[2]]))
+        .collect();
+    Image::new(width, rows, pixels)
 }
 
+// Maps device space, y down, to clip space.
+#[inline]
+pub fn projection(width: f32, height: f32) -> [f32; 16] {
+    [
+        2.0 / width,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        -2.0 / height,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+        -1.0,
+        1.0,
+        0.0,
+        1.0,
+    ]
+}
+
+#[cfg(target_os = "windows")]
 pub struct Vao {
     vao: u32,
 }
 
+#[cfg(target_os = "windows")]
 impl Vao {
     pub fn new(size: usize) -> Self {
         let mut vao = 0;
@@ -73,6 +226,7 @@ impl Vao {
     }
 }
 
+#[cfg(target_os = "windows")]
 impl Drop for Vao {
     fn drop(&mut self) {
         unsafe {
@@ -81,31 +235,371 @@ impl Drop for Vao {
     }
 }
 
-pub struct Vbo {
-    vbo: u32,
+// A buffer object that's refilled every time it's drawn from.
+pub struct Buffer {
+    buffer: u32,
+    target: GLenum,
 }
 
-impl Vbo {
-    pub fn gen(data: &[f32]) -> Self {
-        let mut vbo = 0;
+impl Buffer {
+    pub fn new(target: GLenum) -> Self {
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+        }
+        Self { buffer, target }
+    }
+
+    pub fn upload<T>(&self, data: &[T]) {
         unsafe {
-            gl::GenBuffers(1, &mut vbo);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BindBuffer(self.target, self.buffer);
             gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (data.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
-                std::mem::transmute::<&f32, *const c_void>(&data[0]) as *const c_void,
-                gl::STATIC_DRAW,
+                self.target,
+                std::mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const c_void,
+                gl::STREAM_DRAW,
+            );
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pass {
+    Color,
+    // Counts the winding of the triangles in the stencil buffer.
+    Stencil(FillRule),
+    // Draws where the stencil is set, clearing it on the way.
+    Cover,
+}
+
+struct Draw {
+    pass: Pass,
+    scissor: Option<Rect>,
+    // Range in the index buffer.
+    start: usize,
+    count: usize,
+}
+
+// Collects a frame's triangles into one vertex and index buffer and draws
+// them on flush. Consecutive draws in the same pass and clip share a draw
+// call; the order of everything else is kept.
+pub struct Batch {
+    program: u32,
+    position: u32,
+    color: u32,
+    projection: i32,
+    linear: i32,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    vertices: Vec<f32>,
+    indices: Vec<u16>,
+    draws: Vec<Draw>,
+    scissor: Option<Rect>,
+    height: f32,
+    font: Option<Font>,
+    font_searched: bool,
+    // Glyph outlines in font units.
+    outlines: HashMap<u16, Path>,
+}
+
+impl Batch {
+    // `program` takes `aPos` and `aColor`, and has the `projectionMatrix`
+    // and `linearColor` uniforms.
+    pub fn new(program: u32) -> Self {
+        unsafe {
+            let location = |name: &str| {
+                gl::GetAttribLocation(program, CString::new(name).unwrap().as_ptr()) as u32
+            };
+            let uniform =
+                |name: &str| gl::GetUniformLocation(program, CString::new(name).unwrap().as_ptr());
+            Self {
+                program,
+                position: location("aPos"),
+                color: location("aColor"),
+                projection: uniform("projectionMatrix"),
+                linear: uniform("linearColor"),
+                vertex_buffer: Buffer::new(gl::ARRAY_BUFFER),
+                index_buffer: Buffer::new(gl::ELEMENT_ARRAY_BUFFER),
+                vertices: vec![],
+                indices: vec![],
+                draws: vec![],
+                scissor: None,
+                height: 0.0,
+                font: None,
+                font_searched: false,
+                outlines: HashMap::new(),
+            }
+        }
+    }
+
+    pub fn set_viewport(&mut self, width: f32, height: f32) {
+        self.height = height;
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::UseProgram(self.program);
+            gl::UniformMatrix4fv(
+                self.projection,
+                1,
+                gl::FALSE,
+                projection(width, height).as_ptr(),
             );
+        }
+    }
+
+    pub fn set_linear(&self, linear: bool) {
+        unsafe {
+            gl::UseProgram(self.program);
+            gl::Uniform1f(self.linear, linear as u8 as f32);
+        }
+    }
+
+    // Applies to everything added from now on.
+    #[inline]
+    pub fn set_scissor(&mut self, clip: Option<Rect>) {
+        self.scissor = clip;
+    }
+
+    // Adds what `command` draws, or applies it to `state`.
+    pub fn command(&mut self, state: &mut DrawState, command: &Command) {
+        let transform = state.transform();
+        let color = |color: &Color| {
+            let [r, g, b, a] = color.to_rgba();
+            [r, g, b, a * state.opacity()]
+        };
+        match command {
+            Command::FillRectangle(x, y, width, height, radius, c) => {
+                let mut path = Path::new();
+                path.rounded_rect(
+                    *x as f32,
+                    *y as f32,
+                    *width as f32,
+                    *height as f32,
+                    *radius as f32,
+                    *radius as f32,
+                );
+                for contour in path.fill_contours(&transform, TOLERANCE) {
+                    self.fill_convex(&contour, color(c));
+                }
+            }
+            Command::FillPath(path, c) => {
+                let contours = path.fill_contours(&transform, TOLERANCE);
+                self.fill_contours(&contours, path.fill_rule(), color(c));
+            }
+            Command::StrokePath(path, stroke, c) => {
+                let contours = path.stroke_contours(stroke, &transform, TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, color(c));
+            }
+            Command::WriteString(x, y, width, height, c, text) => self.write_string(
+                &transform,
+                (*x as f32, *y as f32, *width as f32, *height as f32),
+                color(c),
+                text,
+            ),
+            _ => {
+                if state.apply(command) {
+                    self.set_scissor(state.clip());
+                }
+            }
+        }
+    }
+
+    // Glyphs are filled as paths, so text goes through the stencil like
+    // any other fill.
+    fn write_string(
+        &mut self,
+        transform: &Transform,
+        (x, y, width, height): (f32, f32, f32, f32),
+        color: [f32; 4],
+        text: &str,
+    ) {
+        if !self.font_searched {
+            self.font_searched = true;
+            self.font = system_font();
+        }
+        let Some(font) = &self.font else {
+            return;
+        };
+        let Ok(face) = ttf_parser::Face::parse(&font.data, font.index) else {
+            return;
+        };
+
+        let line = layout(&face, x, y, width, height, text);
+        let scale = line.size / face.units_per_em() as f32;
+        let mut contours = vec![];
+        for (glyph, offset) in line.glyphs {
+            let outline = self.outlines.entry(glyph.0).or_insert_with(|| {
+                let mut outline = Outline(Path::new());
+                face.outline_glyph(glyph, &mut outline);
+                outline.0
+            });
+            // Font units point up.
+            let placed = Transform::scale(scale, -scale)
+                .then(Transform::translate(line.origin.0 + offset, line.origin.1))
+                .then(*transform);
+            contours.extend(outline.fill_contours(&placed, TOLERANCE));
+        }
+        self.fill_contours(&contours, FillRule::NonZero, color);
+    }
+
+    // Fills a convex polygon.
+    pub fn fill_convex(&mut self, points: &[(f32, f32)], color: [f32; 4]) {
+        self.push_fan(Pass::Color, points, color);
+    }
+
+    // Stencil, then cover: the contours' winding is counted in the stencil
+    // buffer, then their bounds are drawn where the rule says "inside".
+    pub fn fill_contours(&mut self, contours: &[Vec<(f32, f32)>], rule: FillRule, color: [f32; 4]) {
+        let mut points = contours.iter().flatten();
+        let Some(&(mut x0, mut y0)) = points.next() else {
+            return;
         };
-        Self { vbo }
+        let (mut x1, mut y1) = (x0, y0);
+        for (x, y) in points {
+            x0 = x0.min(*x);
+            y0 = y0.min(*y);
+            x1 = x1.max(*x);
+            y1 = y1.max(*y);
+        }
+
+        for contour in contours {
+            self.push_fan(Pass::Stencil(rule), contour, color);
+        }
+        self.push_fan(
+            Pass::Cover,
+            &[(x0, y0), (x0, y1), (x1, y1), (x1, y0)],
+            color,
+        );
+    }
+
+    fn push_fan(&mut self, pass: Pass, points: &[(f32, f32)], color: [f32; 4]) {
+        if points.len() < 3 {
+            return;
+        }
+        if points.len() > MAX_VERTICES {
+            warn!("Skipping a polygon with {} points", points.len());
+            return;
+        }
+        if self.vertices.len() / STRIDE + points.len() > MAX_VERTICES {
+            self.flush();
+        }
+
+        // In usize until here, a full batch's last index doesn't fit in u16.
+        let base = self.vertices.len() / STRIDE;
+        for (x, y) in points {
+            self.vertices.extend_from_slice(&[*x, *y]);
+            self.vertices.extend_from_slice(&color);
+        }
+        let start = self.indices.len();
+        for i in 1..points.len() - 1 {
+            self.indices
+                .extend([base, base + i, base + i + 1].map(|index| index as u16));
+        }
+        let count = self.indices.len() - start;
+
+        match self.draws.last_mut() {
+            Some(draw)
+                if draw.pass == pass
+                    && pass != Pass::Cover
+                    && draw.scissor == self.scissor
+                    && draw.start + draw.count == start =>
+            {
+                draw.count += count
+            }
+            _ => self.draws.push(Draw {
+                pass,
+                scissor: self.scissor,
+                start,
+                count,
+            }),
+        }
+    }
+
+    // Draws and forgets everything added so far.
+    pub fn flush(&mut self) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        unsafe {
+            gl::UseProgram(self.program);
+            self.vertex_buffer.upload(&self.vertices);
+            self.index_buffer.upload(&self.indices);
+
+            let stride = (STRIDE * std::mem::size_of::<f32>()) as i32;
+            gl::VertexAttribPointer(self.position, 2, gl::FLOAT, gl::FALSE, stride, null());
+            gl::EnableVertexAttribArray(self.position);
+            gl::VertexAttribPointer(
+                self.color,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (2 * std::mem::size_of::<f32>()) as *const c_void,
+            );
+            gl::EnableVertexAttribArray(self.color);
+
+            let mut scissor = None;
+            set_scissor(scissor, self.height);
+            for draw in &self.draws {
+                if draw.scissor != scissor {
+                    scissor = draw.scissor;
+                    set_scissor(scissor, self.height);
+                }
+                match draw.pass {
+                    Pass::Color => {}
+                    Pass::Stencil(rule) => {
+                        gl::Enable(gl::STENCIL_TEST);
+                        gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+                        gl::StencilMask(0xff);
+                        gl::StencilFunc(gl::ALWAYS, 0, 0xff);
+                        match rule {
+                            FillRule::NonZero => {
+                                gl::StencilOpSeparate(gl::FRONT, gl::KEEP, gl::KEEP, gl::INCR_WRAP);
+                                gl::StencilOpSeparate(gl::BACK, gl::KEEP, gl::KEEP, gl::DECR_WRAP);
+                            }
+                            FillRule::EvenOdd => gl::StencilOp(gl::KEEP, gl::KEEP, gl::INVERT),
+                        }
+                    }
+                    Pass::Cover => {
+                        gl::Enable(gl::STENCIL_TEST);
+                        gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                        gl::StencilFunc(gl::NOTEQUAL, 0, 0xff);
+                        gl::StencilOp(gl::ZERO, gl::ZERO, gl::ZERO);
+                    }
+                }
+                gl::DrawElements(
+                    gl::TRIANGLES,
+                    draw.count as i32,
+                    gl::UNSIGNED_SHORT,
+                    (draw.start * std::mem::size_of::<u16>()) as *const c_void,
+                );
+                if draw.pass != Pass::Color {
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                    gl::Disable(gl::STENCIL_TEST);
+                }
+            }
+            // Leave the scissor as the next draws expect it.
+            set_scissor(self.scissor, self.height);
+        }
+
+        self.vertices.clear();
+        self.indices.clear();
+        self.draws.clear();
     }
 }
 
-impl Drop for Vbo {
+impl Drop for Batch {
     fn drop(&mut self) {
         unsafe {
-            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteProgram(self.program);
         }
     }
 }
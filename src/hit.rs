@@ -0,0 +1,197 @@
+use crate::{damage::text_bounds, surface::DrawState, Command, FillRule, Path, Rect};
+
+const TOLERANCE: f32 = 0.25;
+
+// The topmost command under a point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    // Position of the drawing command in the buffer.
+    pub index: usize,
+    // The command's own tag, or else that of the innermost tagged group
+    // around it.
+    pub tag: Option<u64>,
+}
+
+// Finds the last drawn command covering (x, y), in surface coordinates.
+// `tags` runs alongside `commands`; a tag on a Push command applies to
+// everything up to its Pop.
+pub fn hit_test(commands: &[Command], tags: &[Option<u64>], x: f32, y: f32) -> Option<Hit> {
+    let mut state = DrawState::new();
+    let mut groups: Vec<Option<u64>> = vec![];
+    let mut hit = None;
+
+    for (index, command) in commands.iter().enumerate() {
+        let group = groups.last().copied().flatten();
+        let tag = tags.get(index).copied().flatten().or(group);
+        match command {
+            Command::PushClip(..) | Command::PushTransform(_) | Command::PushOpacity(_) => {
+                groups.push(tag)
+            }
+            Command::PopClip | Command::PopTransform | Command::PopOpacity => {
+                groups.pop();
+            }
+            _ => {
+                if state.clip().is_none_or(|clip| clip.contains(x, y))
+                    && covers(command, &state, x, y)
+                {
+                    hit = Some(Hit { index, tag });
+                }
+            }
+        }
+        state.apply(command);
+    }
+    hit
+}
+
+fn covers(command: &Command, state: &DrawState, x: f32, y: f32) -> bool {
+    let transform = state.transform();
+    let (contours, rule) = match command {
+        Command::FillRectangle(rx, ry, width, height, radius, _) => {
+            let mut path = Path::new();
+            path.rounded_rect(
+                *rx as f32,
+                *ry as f32,
+                *width as f32,
+                *height as f32,
+                *radius as f32,
+                *radius as f32,
+            );
+            (path.fill_contours(&transform, TOLERANCE), FillRule::NonZero)
+        }
+        Command::WriteString(rx, ry, width, height, _, text) => {
+            let rect = text_bounds(Rect::from_u32(*rx, *ry, *width, *height), text);
+            let mut path = Path::new();
+            path.rect(rect.x, rect.y, rect.width, rect.height);
+            (path.fill_contours(&transform, TOLERANCE), FillRule::NonZero)
+        }
+        Command::FillPath(path, _) => (path.fill_contours(&transform, TOLERANCE), path.fill_rule()),
+        Command::StrokePath(path, stroke, _) => (
+            path.stroke_contours(stroke, &transform, TOLERANCE),
+            FillRule::NonZero,
+        ),
+        _ => return false,
+    };
+
+    let winding = winding(&contours, x, y);
+    match rule {
+        FillRule::NonZero => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+    }
+}
+
+// Winding number of closed polygons around (x, y).
+fn winding(contours: &[Vec<(f32, f32)>], x: f32, y: f32) -> i32 {
+    let mut winding = 0;
+    for contour in contours {
+        for (i, &(x0, y0)) in contour.iter().enumerate() {
+            let (x1, y1) = contour[(i + 1) % contour.len()];
+            let side = (x1 - x0) * (y - y0) - (x - x0) * (y1 - y0);
+            if y0 <= y && y1 > y && side > 0.0 {
+                winding += 1;
+            } else if y1 <= y && y0 > y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+    }
+    winding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Stroke, Transform};
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Command {
+        Command::FillRectangle(x, y, width, height, 0.0, Color::BLACK)
+    }
+
+    #[test]
+    fn finds_the_topmost_command() {
+        let commands = [rect(0, 0, 100, 100), rect(20, 20, 10, 10)];
+        let tags = [Some(1), None];
+        assert_eq!(
+            hit_test(&commands, &tags, 25.0, 25.0),
+            Some(Hit {
+                index: 1,
+                tag: None
+            })
+        );
+        assert_eq!(
+            hit_test(&commands, &tags, 50.0, 50.0),
+            Some(Hit {
+                index: 0,
+                tag: Some(1)
+            })
+        );
+        assert_eq!(hit_test(&commands, &tags, 150.0, 50.0), None);
+
+        // The corners of a rounded rectangle are left out.
+        let rounded = [Command::FillRectangle(0, 0, 20, 20, 8.0, Color::BLACK)];
+        assert_eq!(hit_test(&rounded, &[], 1.0, 1.0), None);
+        assert!(hit_test(&rounded, &[], 10.0, 1.0).is_some());
+    }
+
+    #[test]
+    fn follows_clips_and_transforms() {
+        let commands = [
+            Command::PushTransform(
+                Transform::scale(2.0, 2.0).then(Transform::translate(10.0, 0.0)),
+            ),
+            Command::PushClip(0, 0, 10, 10),
+            rect(0, 0, 50, 50),
+            Command::PopClip,
+            Command::PopTransform,
+            Command::PushTransform(Transform::rotate(90.0)),
+            rect(0, 0, 10, 10),
+            Command::PopTransform,
+        ];
+        // Inside the scaled and translated clip.
+        assert_eq!(
+            hit_test(&commands, &[], 25.0, 15.0).map(|h| h.index),
+            Some(2)
+        );
+        // The rectangle reaches there, but its clip doesn't.
+        assert_eq!(hit_test(&commands, &[], 40.0, 15.0), None);
+        assert_eq!(hit_test(&commands, &[], 5.0, 5.0), None);
+        // Rotated onto negative x.
+        assert_eq!(
+            hit_test(&commands, &[], -5.0, 5.0).map(|h| h.index),
+            Some(6)
+        );
+    }
+
+    #[test]
+    fn inherits_the_innermost_group_tag() {
+        let commands = [
+            Command::PushOpacity(0.5),
+            Command::PushClip(0, 0, 100, 100),
+            rect(0, 0, 10, 10),
+            rect(20, 0, 10, 10),
+            Command::PopClip,
+            Command::PopOpacity,
+            rect(40, 0, 10, 10),
+        ];
+        let tags = [Some(1), Some(2), None, Some(3), None, None, None];
+        let tag = |x| hit_test(&commands, &tags, x, 5.0).and_then(|h| h.tag);
+        assert_eq!(tag(5.0), Some(2));
+        assert_eq!(tag(25.0), Some(3));
+        assert_eq!(tag(45.0), None);
+    }
+
+    #[test]
+    fn uses_the_fill_rule_and_stroke_outline() {
+        let mut path = Path::new();
+        path.rect(0.0, 0.0, 30.0, 30.0);
+        path.rect(10.0, 10.0, 10.0, 10.0);
+        let nonzero = [Command::FillPath(path.clone(), Color::BLACK)];
+        assert!(hit_test(&nonzero, &[], 15.0, 15.0).is_some());
+        path.set_fill_rule(FillRule::EvenOdd);
+        let evenodd = [Command::FillPath(path.clone(), Color::BLACK)];
+        assert_eq!(hit_test(&evenodd, &[], 15.0, 15.0), None);
+        assert!(hit_test(&evenodd, &[], 5.0, 5.0).is_some());
+
+        let stroked = [Command::StrokePath(path, Stroke::new(4.0), Color::BLACK)];
+        assert!(hit_test(&stroked, &[], 1.0, 15.0).is_some());
+        assert_eq!(hit_test(&stroked, &[], 5.0, 15.0), None);
+    }
+}
@@ -0,0 +1,106 @@
+// Premultiplied 0xAARRGGBB pixels, top row first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+}
+
+impl Image {
+    // `pixels` has to hold exactly width * height pixels.
+    #[inline]
+    pub fn new(width: u32, height: u32, pixels: Vec<u32>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub fn into_pixels(self) -> Vec<u32> {
+        self.pixels
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> Option<u32> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    // From straight alpha RGBA bytes.
+    pub fn from_rgba8(width: u32, height: u32, rgba: &[u8]) -> Self {
+        let pixels = rgba
+            .chunks_exact(4)
+            .map(|p| {
+                let a = p[3] as u32;
+                let multiply = |c: u8| (c as u32 * a + 127) / 255;
+                a << 24 | multiply(p[0]) << 16 | multiply(p[1]) << 8 | multiply(p[2])
+            })
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    // Straight alpha RGBA bytes, as most image encoders want them.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels {
+            let [a, r, g, b] = pixel.to_be_bytes();
+            let unmultiply = |c: u8| match a {
+                0 => 0,
+                255 => c,
+                _ => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+            };
+            rgba.extend_from_slice(&[unmultiply(r), unmultiply(g), unmultiply(b), a]);
+        }
+        rgba
+    }
+}
+
+// `rect` rounded out to whole pixels and limited to a width x height
+// surface, as x, y, width, height.
+#[cfg(any(
+    feature = "software",
+    all(target_os = "linux", feature = "x11"),
+    all(target_os = "windows", feature = "d2d1"),
+    all(
+        feature = "gl",
+        any(
+            all(target_os = "windows", feature = "wgl"),
+            all(target_os = "linux", feature = "wayland")
+        )
+    )
+))]
+pub(crate) fn pixel_bounds(rect: &crate::Rect, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let rect = rect
+        .round_out()
+        .intersect(&crate::Rect::from_u32(0, 0, width, height));
+    if rect.is_empty() {
+        return (0, 0, 0, 0);
+    }
+    (
+        rect.x as u32,
+        rect.y as u32,
+        rect.width as u32,
+        rect.height as u32,
+    )
+}
@@ -1,37 +1,112 @@
 #[macro_use]
 extern crate log;
 
+pub mod builder;
+pub mod color;
+pub mod damage;
+pub mod geometry;
+pub mod hit;
+pub mod image;
+pub mod path;
+pub mod record;
+pub mod scene;
 pub mod surface;
 
+#[cfg(any(
+    feature = "pdf",
+    feature = "software",
+    feature = "xcb",
+    all(
+        feature = "gl",
+        any(
+            all(target_os = "windows", feature = "wgl"),
+            all(target_os = "linux", feature = "x11_egl"),
+            all(target_os = "linux", feature = "wayland")
+        )
+    )
+))]
+mod font;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+#[cfg(feature = "software")]
+pub mod software;
+#[cfg(feature = "svg")]
+pub mod svg;
+#[cfg(feature = "testing")]
+pub mod testing;
+
 #[cfg(target_os = "windows")]
 #[cfg(feature = "d2d1")]
 pub mod d2d1;
 #[cfg(target_os = "linux")]
+#[cfg(any(feature = "x11_egl", feature = "wayland"))]
+pub mod egl;
+#[cfg(target_os = "linux")]
+#[cfg(any(feature = "x11_egl", feature = "wayland"))]
+pub mod egloffscreen;
+#[cfg(target_os = "linux")]
+#[cfg(feature = "fbdev")]
+pub mod fbdev;
+#[cfg(target_os = "linux")]
+#[cfg(any(feature = "x11", feature = "xcb"))]
+mod trapezoid;
+#[cfg(target_os = "linux")]
+#[cfg(feature = "wayland")]
+pub mod wayland;
+#[cfg(target_os = "linux")]
 #[cfg(feature = "x11")]
 pub mod x11;
 #[cfg(target_os = "linux")]
 #[cfg(feature = "x11_egl")]
 pub mod x11egl;
+#[cfg(target_os = "linux")]
+#[cfg(all(feature = "x11", feature = "software"))]
+pub mod x11soft;
+#[cfg(target_os = "linux")]
+#[cfg(feature = "xcb")]
+pub mod xcb;
 
 #[cfg(target_os = "windows")]
 #[cfg(feature = "wgl")]
 pub mod wgl;
 
 #[cfg(feature = "gl")]
+#[cfg(any(
+    all(target_os = "windows", feature = "wgl"),
+    all(target_os = "linux", feature = "x11_egl"),
+    all(target_os = "linux", feature = "wayland")
+))]
 pub(crate) mod gl;
 
-use std::{fmt::Display, sync::Mutex};
+use std::fmt::Display;
 
+use damage::DamageTracker;
+use hit::Hit;
+use record::{Frame, Recorder};
+use scene::{NodeId, Scene};
 use surface::Surface;
 use thiserror::Error;
 
+pub use builder::AcureBuilder;
+pub use color::{Color, ColorSpace};
+pub use geometry::{Rect, Transform};
+pub use image::Image;
+pub use path::{FillRule, Path, Stroke};
+
 pub type AeResult<T> = Result<T, AcureError>;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Backend {
     D2D1,
     WGL,
+    X11,
+    XCB,
     X11EGL,
+    WaylandEGL,
+    EGL,
+    Fbdev,
+    PDF,
+    Software,
 }
 
 impl Display for Backend {
@@ -49,27 +124,59 @@ pub enum AcureError {
     NullPtrError(String),
     #[error("Backend: '{0}'\n'{1}'")]
     BackendError(Backend, anyhow::Error),
+    #[error("Can't open display: '{0}'")]
+    DisplayUnavailable(String),
+    #[error("Shader compilation failed:\n'{log}'")]
+    ShaderCompile { log: String },
+    #[error("Can't create a context: '{0}'\n'{1}'")]
+    ContextCreation(Backend, anyhow::Error),
+    #[error("Device lost: '{0}'")]
+    DeviceLost(Backend),
+    #[error("No backend can draw to this window.")]
+    NoSuitableBackend,
+    #[error(transparent)]
+    WindowHandle(#[from] raw_window_handle::HandleError),
+    #[error("Invalid color: '{0}'")]
+    InvalidColor(String),
+    #[error("Invalid recording: '{0}'")]
+    InvalidRecording(String),
+    #[error("Invalid SVG: '{0}'")]
+    InvalidSvg(String),
+    #[error("Image mismatch: '{0}'")]
+    ImageMismatch(String),
+    #[error("No such node: '{0:?}'")]
+    InvalidNode(NodeId),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum Color {
-    ARGB(u8, u8, u8, u8),
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
     // X,Y,Width,Height,Radius,Color
     FillRectangle(u32, u32, u32, u32, f64, Color),
     WriteString(u32, u32, u32, u32, Color, String),
+    FillPath(Path, Color),
+    StrokePath(Path, Stroke, Color),
+    // X,Y,Width,Height
+    PushClip(u32, u32, u32, u32),
+    PopClip,
+    PushTransform(Transform),
+    PopTransform,
+    // 0.0-1.0, applied to everything up to the matching PopOpacity
+    PushOpacity(f32),
+    PopOpacity,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LayoutMode {
     NoCare,
     AdjustSize,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AlignMode {
     CenterAligned,
     RightAligned,
@@ -85,15 +192,29 @@ enum ContextState {
     End,
 }
 
-pub struct AcureBuilder {}
-
 pub struct Acure {
     buffer: Vec<Command>,
+    // Runs alongside `buffer`, for hit testing.
+    tags: Vec<Option<u64>>,
     bgr: Color,
+    color_space: ColorSpace,
+    color_space_checked: bool,
     align: AlignMode,
     layout: LayoutMode,
     state: ContextState,
+    #[allow(dead_code)]
     thickness: u32,
+    recorder: Option<Recorder>,
+    tracker: Option<DamageTracker>,
+    // None if the last frame was drawn in full.
+    damage: Option<Vec<Rect>>,
+}
+
+impl Default for Acure {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Acure {
@@ -101,11 +222,17 @@ impl Acure {
     pub const fn new() -> Self {
         Self {
             buffer: vec![],
-            bgr: Color::ARGB(0, 0, 0, 0),
+            tags: vec![],
+            bgr: Color::TRANSPARENT,
+            color_space: ColorSpace::Srgb,
+            color_space_checked: false,
             align: AlignMode::Flex,
             layout: LayoutMode::NoCare,
             state: ContextState::End,
             thickness: 1,
+            recorder: None,
+            tracker: Some(DamageTracker::new()),
+            damage: None,
         }
     }
 
@@ -114,58 +241,320 @@ impl Acure {
         self.bgr = color;
     }
 
+    #[inline]
+    pub fn set_color_space(&mut self, space: ColorSpace) {
+        if self.color_space != space {
+            self.color_space = space;
+            self.color_space_checked = false;
+            self.invalidate();
+        }
+    }
+
     #[inline]
     pub fn set_align_mode(&mut self, mode: AlignMode) {
-        self.align = mode;
+        if self.align != mode {
+            self.align = mode;
+            self.invalidate();
+        }
     }
 
     #[inline]
     pub fn set_layout_mode(&mut self, mode: LayoutMode) {
-        self.layout = mode;
+        if self.layout != mode {
+            self.layout = mode;
+            self.invalidate();
+        }
+    }
+
+    // With damage tracking (the default), each frame is diffed against the
+    // previous one and surfaces that keep their contents only redraw what
+    // changed.
+    #[inline]
+    pub fn set_damage_tracking(&mut self, enabled: bool) {
+        self.tracker = enabled.then(DamageTracker::new);
+    }
+
+    // Draws the next frame in full, e.g. after the window was exposed or the
+    // surface was drawn by something else.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        if let Some(tracker) = &mut self.tracker {
+            tracker.invalidate();
+        }
+    }
+
+    // What the last frame redrew, or None if it was drawn in full.
+    #[inline]
+    pub fn damage(&self) -> Option<&[Rect]> {
+        self.damage.as_deref()
     }
 
     #[inline]
     pub fn clear(&mut self) {
         if !self.buffer.is_empty() {
             self.buffer.clear();
+            self.tags.clear();
         }
     }
 
     #[inline]
-    pub fn begin<T>(&mut self, surface: &mut T)
+    pub fn begin<T>(&mut self, surface: &mut T) -> AeResult<()>
     where
-        T: Surface,
+        T: Surface + ?Sized,
     {
+        if !surface.set_color_space(self.color_space) && !self.color_space_checked {
+            warn!(
+                "{:?} blending is not supported by this surface, falling back to sRGB",
+                self.color_space
+            );
+        }
+        self.color_space_checked = true;
+        surface.begin()?;
         self.state = ContextState::Begin;
-        surface.begin();
+        Ok(())
     }
 
     #[inline]
     pub fn push(&mut self, command: Command) {
         self.buffer.push(command);
+        self.tags.push(None);
+    }
+
+    // Pushes a command that hit tests report `tag` for. A tag on a Push
+    // command is reported for everything drawn up to its Pop.
+    #[inline]
+    pub fn push_tagged(&mut self, command: Command, tag: u64) {
+        self.buffer.push(command);
+        self.tags.push(Some(tag));
+    }
+
+    // Appends the scene's commands, flattening it first if it changed.
+    #[inline]
+    pub fn push_scene(&mut self, scene: &mut Scene) {
+        self.buffer.extend_from_slice(scene.commands());
+        self.tags.resize(self.buffer.len(), None);
     }
 
     #[inline]
     pub fn write<T>(&mut self, surface: &mut T) -> AeResult<()>
     where
-        T: Surface,
+        T: Surface + ?Sized,
     {
         if self.state == ContextState::Begin {
-            surface.clear(self.bgr);
-            for i in &self.buffer {
-                surface.command(i, self.align, self.layout);
+            self.damage = match &mut self.tracker {
+                Some(tracker) => tracker
+                    .update(self.bgr, &self.buffer)
+                    .filter(|damage| surface.set_damage(damage)),
+                None => None,
+            };
+            let mut drawn = Ok(());
+            if self.damage.as_ref().is_none_or(|damage| !damage.is_empty()) {
+                surface.clear(self.bgr);
+                drawn = self
+                    .buffer
+                    .iter()
+                    .try_for_each(|i| surface.command(i, self.align, self.layout));
             }
 
-            surface.end();
+            // The surface has to finish the frame even if a command failed.
+            let ended = surface.end();
             self.state = ContextState::End;
+            if let Err(e) = drawn.and(ended) {
+                // What's on screen is unknown now.
+                self.invalidate();
+                return Err(e);
+            }
+
+            if let Some(recorder) = &mut self.recorder {
+                recorder.write_frame(&Frame {
+                    background: self.bgr,
+                    color_space: self.color_space,
+                    align: self.align,
+                    layout: self.layout,
+                    commands: self.buffer.clone(),
+                })?;
+            }
             return Ok(());
         }
 
-        return Err(AcureError::UnauthorizedOperation);
+        Err(AcureError::UnauthorizedOperation)
+    }
+
+    // The topmost command at (x, y) in surface coordinates, taking clips,
+    // transforms and rounded corners into account.
+    #[inline]
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<Hit> {
+        hit::hit_test(&self.buffer, &self.tags, x, y)
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.buffer.is_empty()
     }
+
+    // Snapshot of the current state and buffer.
+    #[inline]
+    pub fn frame(&self) -> Frame {
+        Frame {
+            background: self.bgr,
+            color_space: self.color_space,
+            align: self.align,
+            layout: self.layout,
+            commands: self.buffer.clone(),
+        }
+    }
+
+    // Every frame written from now on goes to `path`, which is replaced if
+    // it exists.
+    #[inline]
+    pub fn record_to<P: AsRef<std::path::Path>>(&mut self, path: P) -> AeResult<()> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    // Draws a recorded frame onto `surface`, replacing the current buffer
+    // and drawing state.
+    pub fn replay<T>(&mut self, frame: &Frame, surface: &mut T) -> AeResult<()>
+    where
+        T: Surface + ?Sized,
+    {
+        self.set_background_color(frame.background);
+        self.set_color_space(frame.color_space);
+        self.set_align_mode(frame.align);
+        self.set_layout_mode(frame.layout);
+        self.buffer.clone_from(&frame.commands);
+        self.tags.clear();
+        self.tags.resize(self.buffer.len(), None);
+
+        self.begin(surface)?;
+        self.write(surface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::Capabilities;
+
+    // Keeps its contents between frames and fails the command at `fail_at`.
+    #[derive(Default)]
+    struct TestSurface {
+        fail_at: Option<usize>,
+        drawn: usize,
+        ended: usize,
+    }
+
+    impl Surface for TestSurface {
+        fn surface_resize(&mut self, _width: u32, _height: u32) {}
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        fn set_color_space(&mut self, _space: ColorSpace) -> bool {
+            true
+        }
+
+        fn begin(&mut self) -> AeResult<()> {
+            self.drawn = 0;
+            Ok(())
+        }
+
+        fn set_damage(&mut self, _damage: &[Rect]) -> bool {
+            true
+        }
+
+        fn clear(&mut self, _color: Color) {}
+
+        fn command(&mut self, _command: &Command, _: AlignMode, _: LayoutMode) -> AeResult<()> {
+            if self.fail_at == Some(self.drawn) {
+                return Err(AcureError::DeviceLost(Backend::EGL));
+            }
+            self.drawn += 1;
+            Ok(())
+        }
+
+        fn end(&mut self) -> AeResult<()> {
+            self.ended += 1;
+            Ok(())
+        }
+    }
+
+    fn frame(acure: &mut Acure, surface: &mut TestSurface) -> AeResult<()> {
+        acure.begin(surface)?;
+        acure.write(surface)
+    }
+
+    #[test]
+    fn writes_only_after_begin() {
+        let mut acure = Acure::new();
+        let mut surface = TestSurface::default();
+        acure.push(Command::FillRectangle(0, 0, 1, 1, 0.0, Color::BLACK));
+        assert!(matches!(
+            acure.write(&mut surface),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+
+        frame(&mut acure, &mut surface).unwrap();
+        assert_eq!((surface.drawn, surface.ended), (1, 1));
+        assert_eq!(acure.damage(), None);
+        assert!(matches!(
+            acure.write(&mut surface),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+
+        // Nothing changed, so nothing is drawn.
+        frame(&mut acure, &mut surface).unwrap();
+        assert_eq!((surface.drawn, surface.ended), (0, 2));
+        assert_eq!(acure.damage(), Some(&[][..]));
+    }
+
+    #[test]
+    fn ends_and_invalidates_a_failed_frame() {
+        let mut acure = Acure::new();
+        let mut surface = TestSurface::default();
+        acure.push(Command::FillRectangle(0, 0, 1, 1, 0.0, Color::BLACK));
+        acure.push(Command::FillRectangle(2, 0, 1, 1, 0.0, Color::BLACK));
+        frame(&mut acure, &mut surface).unwrap();
+
+        acure.clear();
+        acure.push(Command::FillRectangle(0, 0, 1, 1, 0.0, Color::WHITE));
+        acure.push(Command::FillRectangle(2, 0, 1, 1, 0.0, Color::WHITE));
+        surface.fail_at = Some(1);
+        assert!(matches!(
+            frame(&mut acure, &mut surface),
+            Err(AcureError::DeviceLost(Backend::EGL))
+        ));
+        assert_eq!((surface.drawn, surface.ended), (1, 2));
+
+        // Even though the buffer is the same, the next frame is drawn in full.
+        surface.fail_at = None;
+        frame(&mut acure, &mut surface).unwrap();
+        assert_eq!(surface.drawn, 2);
+        assert_eq!(acure.damage(), None);
+    }
+
+    #[test]
+    fn redraws_a_replayed_frame_with_other_modes_in_full() {
+        let mut acure = Acure::new();
+        let mut surface = TestSurface::default();
+        acure.push(Command::WriteString(0, 0, 10, 10, Color::BLACK, "a".into()));
+        frame(&mut acure, &mut surface).unwrap();
+
+        let mut replayed = acure.frame();
+        acure.replay(&replayed, &mut surface).unwrap();
+        assert_eq!(acure.damage(), Some(&[][..]));
+
+        replayed.align = AlignMode::LeftAligned;
+        acure.replay(&replayed, &mut surface).unwrap();
+        assert_eq!(acure.damage(), None);
+        replayed.layout = LayoutMode::AdjustSize;
+        acure.replay(&replayed, &mut surface).unwrap();
+        assert_eq!(acure.damage(), None);
+    }
 }
@@ -0,0 +1,815 @@
+use std::f32::consts::PI;
+
+use crate::{Rect, Transform};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FillRule {
+    #[default]
+    NonZero,
+    EvenOdd,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineCap {
+    #[default]
+    Butt,
+    Round,
+    Square,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineJoin {
+    #[default]
+    Miter,
+    Round,
+    Bevel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stroke {
+    pub width: f32,
+    pub cap: LineCap,
+    pub join: LineJoin,
+    pub miter_limit: f32,
+}
+
+impl Stroke {
+    #[inline]
+    pub const fn new(width: f32) -> Self {
+        Self {
+            width,
+            cap: LineCap::Butt,
+            join: LineJoin::Miter,
+            miter_limit: 4.0,
+        }
+    }
+}
+
+impl Default for Stroke {
+    #[inline]
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathSegment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    // Control point, end point
+    QuadTo(f32, f32, f32, f32),
+    // Two control points, end point
+    CubicTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Path {
+    segments: Vec<PathSegment>,
+    fill_rule: FillRule,
+}
+
+impl Path {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn from_segments(segments: Vec<PathSegment>, fill_rule: FillRule) -> Self {
+        Self {
+            segments,
+            fill_rule,
+        }
+    }
+
+    #[inline]
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    #[inline]
+    pub fn fill_rule(&self) -> FillRule {
+        self.fill_rule
+    }
+
+    #[inline]
+    pub fn set_fill_rule(&mut self, rule: FillRule) {
+        self.fill_rule = rule;
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    #[inline]
+    pub fn move_to(&mut self, x: f32, y: f32) {
+        self.segments.push(PathSegment::MoveTo(x, y));
+    }
+
+    #[inline]
+    pub fn line_to(&mut self, x: f32, y: f32) {
+        self.segments.push(PathSegment::LineTo(x, y));
+    }
+
+    #[inline]
+    pub fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.segments.push(PathSegment::QuadTo(x1, y1, x, y));
+    }
+
+    #[inline]
+    pub fn cubic_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.segments
+            .push(PathSegment::CubicTo(x1, y1, x2, y2, x, y));
+    }
+
+    #[inline]
+    pub fn close(&mut self) {
+        self.segments.push(PathSegment::Close);
+    }
+
+    // The point the next segment starts from.
+    pub fn current_point(&self) -> (f32, f32) {
+        match self.segments.last() {
+            Some(
+                PathSegment::MoveTo(x, y)
+                | PathSegment::LineTo(x, y)
+                | PathSegment::QuadTo(_, _, x, y)
+                | PathSegment::CubicTo(_, _, _, _, x, y),
+            ) => (*x, *y),
+            // Back to the start of the closed subpath.
+            Some(PathSegment::Close) => self
+                .segments
+                .iter()
+                .rev()
+                .find_map(|segment| match segment {
+                    PathSegment::MoveTo(x, y) => Some((*x, *y)),
+                    _ => None,
+                })
+                .unwrap_or_default(),
+            None => (0.0, 0.0),
+        }
+    }
+
+    // An elliptical arc to (x, y), with the flags of SVG's `A` command.
+    // Approximated with cubics.
+    #[allow(clippy::too_many_arguments)]
+    pub fn arc_to(
+        &mut self,
+        rx: f32,
+        ry: f32,
+        rotation: f32,
+        large_arc: bool,
+        sweep: bool,
+        x: f32,
+        y: f32,
+    ) {
+        let (x0, y0) = self.current_point();
+        let (mut rx, mut ry) = (rx.abs(), ry.abs());
+        if rx == 0.0 || ry == 0.0 || (x0 == x && y0 == y) {
+            self.line_to(x, y);
+            return;
+        }
+
+        // Endpoint to center parameterization, SVG 1.1 appendix F.6.5.
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        let dx = (x0 - x) / 2.0;
+        let dy = (y0 - y) / 2.0;
+        let x1 = cos * dx + sin * dy;
+        let y1 = -sin * dx + cos * dy;
+
+        let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+        if lambda > 1.0 {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+
+        let num = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let den = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let mut coef = (num / den).max(0.0).sqrt();
+        if large_arc == sweep {
+            coef = -coef;
+        }
+        let cx1 = coef * rx * y1 / ry;
+        let cy1 = -coef * ry * x1 / rx;
+        let cx = cos * cx1 - sin * cy1 + (x0 + x) / 2.0;
+        let cy = sin * cx1 + cos * cy1 + (y0 + y) / 2.0;
+
+        let angle = |ux: f32, uy: f32, vx: f32, vy: f32| {
+            let a = (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+            if a.is_nan() {
+                0.0
+            } else {
+                a
+            }
+        };
+        let ux = (x1 - cx1) / rx;
+        let uy = (y1 - cy1) / ry;
+        let vx = (-x1 - cx1) / rx;
+        let vy = (-y1 - cy1) / ry;
+        let start = angle(1.0, 0.0, ux, uy);
+        let mut delta = angle(ux, uy, vx, vy);
+        if !sweep && delta > 0.0 {
+            delta -= 2.0 * PI;
+        } else if sweep && delta < 0.0 {
+            delta += 2.0 * PI;
+        }
+
+        // At most a quarter turn per cubic.
+        let count = (delta.abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+        let step = delta / count as f32;
+        let k = 4.0 / 3.0 * (step / 4.0).tan();
+        let point = |t: f32| {
+            let (s, c) = t.sin_cos();
+            (
+                cx + rx * c * cos - ry * s * sin,
+                cy + rx * c * sin + ry * s * cos,
+            )
+        };
+        let derivative = |t: f32| {
+            let (s, c) = t.sin_cos();
+            (-rx * s * cos - ry * c * sin, -rx * s * sin + ry * c * cos)
+        };
+
+        for i in 0..count {
+            let t0 = start + step * i as f32;
+            let t1 = t0 + step;
+            let (px0, py0) = point(t0);
+            let (dx0, dy0) = derivative(t0);
+            let (px1, py1) = if i + 1 == count { (x, y) } else { point(t1) };
+            let (dx1, dy1) = derivative(t1);
+            self.cubic_to(
+                px0 + k * dx0,
+                py0 + k * dy0,
+                px1 - k * dx1,
+                py1 - k * dy1,
+                px1,
+                py1,
+            );
+        }
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.move_to(x, y);
+        self.line_to(x + width, y);
+        self.line_to(x + width, y + height);
+        self.line_to(x, y + height);
+        self.close();
+    }
+
+    pub fn rounded_rect(&mut self, x: f32, y: f32, width: f32, height: f32, rx: f32, ry: f32) {
+        let rx = rx.min(width / 2.0);
+        let ry = ry.min(height / 2.0);
+        if rx <= 0.0 || ry <= 0.0 {
+            self.rect(x, y, width, height);
+            return;
+        }
+
+        let (right, bottom) = (x + width, y + height);
+        self.move_to(x + rx, y);
+        self.line_to(right - rx, y);
+        self.arc_to(rx, ry, 0.0, false, true, right, y + ry);
+        self.line_to(right, bottom - ry);
+        self.arc_to(rx, ry, 0.0, false, true, right - rx, bottom);
+        self.line_to(x + rx, bottom);
+        self.arc_to(rx, ry, 0.0, false, true, x, bottom - ry);
+        self.line_to(x, y + ry);
+        self.arc_to(rx, ry, 0.0, false, true, x + rx, y);
+        self.close();
+    }
+
+    pub fn ellipse(&mut self, cx: f32, cy: f32, rx: f32, ry: f32) {
+        self.move_to(cx + rx, cy);
+        self.arc_to(rx, ry, 0.0, false, true, cx - rx, cy);
+        self.arc_to(rx, ry, 0.0, false, true, cx + rx, cy);
+        self.close();
+    }
+
+    pub fn transform(&self, t: &Transform) -> Path {
+        let segments = self
+            .segments
+            .iter()
+            .map(|segment| match *segment {
+                PathSegment::MoveTo(x, y) => {
+                    let (x, y) = t.apply(x, y);
+                    PathSegment::MoveTo(x, y)
+                }
+                PathSegment::LineTo(x, y) => {
+                    let (x, y) = t.apply(x, y);
+                    PathSegment::LineTo(x, y)
+                }
+                PathSegment::QuadTo(x1, y1, x, y) => {
+                    let (x1, y1) = t.apply(x1, y1);
+                    let (x, y) = t.apply(x, y);
+                    PathSegment::QuadTo(x1, y1, x, y)
+                }
+                PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                    let (x1, y1) = t.apply(x1, y1);
+                    let (x2, y2) = t.apply(x2, y2);
+                    let (x, y) = t.apply(x, y);
+                    PathSegment::CubicTo(x1, y1, x2, y2, x, y)
+                }
+                PathSegment::Close => PathSegment::Close,
+            })
+            .collect();
+        Path {
+            segments,
+            fill_rule: self.fill_rule,
+        }
+    }
+
+    // The bounds of all points, control points included.
+    pub fn bounds(&self) -> Rect {
+        let mut points = self.segments.iter().flat_map(|segment| match *segment {
+            PathSegment::MoveTo(x, y) | PathSegment::LineTo(x, y) => vec![(x, y)],
+            PathSegment::QuadTo(x1, y1, x, y) => vec![(x1, y1), (x, y)],
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => vec![(x1, y1), (x2, y2), (x, y)],
+            PathSegment::Close => vec![],
+        });
+        let Some((mut x0, mut y0)) = points.next() else {
+            return Rect::default();
+        };
+        let (mut x1, mut y1) = (x0, y0);
+        for (x, y) in points {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+        Rect::new(x0, y0, x1 - x0, y1 - y0)
+    }
+
+    // Every subpath as a polyline, and whether it was closed. Curves are
+    // flattened to within `tolerance`.
+    pub fn flatten(&self, tolerance: f32) -> Vec<(Vec<(f32, f32)>, bool)> {
+        let tolerance = tolerance.max(1e-3);
+        let mut polylines = vec![];
+        let mut points: Vec<(f32, f32)> = vec![];
+        let mut start = (0.0, 0.0);
+        let mut current = (0.0, 0.0);
+
+        for segment in &self.segments {
+            if points.is_empty() && !matches!(segment, PathSegment::MoveTo(..)) {
+                points.push(current);
+            }
+            match *segment {
+                PathSegment::MoveTo(x, y) => {
+                    if points.len() > 1 {
+                        polylines.push((std::mem::take(&mut points), false));
+                    }
+                    points.clear();
+                    points.push((x, y));
+                    start = (x, y);
+                    current = (x, y);
+                }
+                PathSegment::LineTo(x, y) => {
+                    points.push((x, y));
+                    current = (x, y);
+                }
+                PathSegment::QuadTo(x1, y1, x, y) => {
+                    let (x0, y0) = current;
+                    let dd = (x0 - 2.0 * x1 + x).hypot(y0 - 2.0 * y1 + y);
+                    let count = (dd / (4.0 * tolerance)).sqrt().ceil().clamp(1.0, 256.0) as usize;
+                    for i in 1..=count {
+                        let t = i as f32 / count as f32;
+                        let mt = 1.0 - t;
+                        points.push((
+                            mt * mt * x0 + 2.0 * mt * t * x1 + t * t * x,
+                            mt * mt * y0 + 2.0 * mt * t * y1 + t * t * y,
+                        ));
+                    }
+                    current = (x, y);
+                }
+                PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                    let (x0, y0) = current;
+                    let dd = (x0 - 2.0 * x1 + x2)
+                        .hypot(y0 - 2.0 * y1 + y2)
+                        .max((x1 - 2.0 * x2 + x).hypot(y1 - 2.0 * y2 + y));
+                    let count = (0.75 * dd / tolerance).sqrt().ceil().clamp(1.0, 256.0) as usize;
+                    for i in 1..=count {
+                        let t = i as f32 / count as f32;
+                        let mt = 1.0 - t;
+                        let (a, b, c, d) =
+                            (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+                        points.push((
+                            a * x0 + b * x1 + c * x2 + d * x,
+                            a * y0 + b * y1 + c * y2 + d * y,
+                        ));
+                    }
+                    current = (x, y);
+                }
+                PathSegment::Close => {
+                    polylines.push((std::mem::take(&mut points), true));
+                    current = start;
+                }
+            }
+        }
+        if points.len() > 1 {
+            polylines.push((points, false));
+        }
+        polylines
+    }
+
+    // Closed polygons covering the filled path in device space, to be filled
+    // with the path's fill rule.
+    pub fn fill_contours(&self, transform: &Transform, tolerance: f32) -> Vec<Vec<(f32, f32)>> {
+        self.transform(transform)
+            .flatten(tolerance)
+            .into_iter()
+            .map(|(points, _)| points)
+            .filter(|points| points.len() > 2)
+            .collect()
+    }
+
+    // Closed polygons covering the stroked path in device space, to be filled
+    // with the non-zero rule. The stroke is built in user space, so it is
+    // transformed along with the path.
+    pub fn stroke_contours(
+        &self,
+        stroke: &Stroke,
+        transform: &Transform,
+        tolerance: f32,
+    ) -> Vec<Vec<(f32, f32)>> {
+        let tolerance = tolerance / transform.scale_factor().max(1e-6);
+        let mut contours = vec![];
+        for (points, closed) in self.flatten(tolerance) {
+            stroke_polyline(&points, closed, stroke, tolerance, &mut contours);
+        }
+        for contour in &mut contours {
+            for point in contour.iter_mut() {
+                *point = transform.apply(point.0, point.1);
+            }
+        }
+        contours
+    }
+}
+
+// Outlines a polyline as a union of segment quads, joins and caps, all wound
+// the same way so that the non-zero rule merges them.
+fn stroke_polyline(
+    points: &[(f32, f32)],
+    closed: bool,
+    stroke: &Stroke,
+    tolerance: f32,
+    out: &mut Vec<Vec<(f32, f32)>>,
+) {
+    let hw = stroke.width / 2.0;
+    if hw <= 0.0 {
+        return;
+    }
+
+    let mut points: Vec<(f32, f32)> = points.to_vec();
+    points.dedup();
+    if closed && points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+
+    let mut push = |mut polygon: Vec<(f32, f32)>| {
+        if signed_area(&polygon) < 0.0 {
+            polygon.reverse();
+        }
+        out.push(polygon);
+    };
+
+    if points.len() == 1 {
+        let (x, y) = points[0];
+        match stroke.cap {
+            LineCap::Butt => {}
+            LineCap::Round => push(circle(x, y, hw, tolerance)),
+            LineCap::Square => push(vec![
+                (x - hw, y - hw),
+                (x + hw, y - hw),
+                (x + hw, y + hw),
+                (x - hw, y + hw),
+            ]),
+        }
+        return;
+    }
+
+    let count = if closed {
+        points.len()
+    } else {
+        points.len() - 1
+    };
+    let direction = |i: usize| {
+        let (x0, y0) = points[i % points.len()];
+        let (x1, y1) = points[(i + 1) % points.len()];
+        let length = (x1 - x0).hypot(y1 - y0);
+        ((x1 - x0) / length, (y1 - y0) / length)
+    };
+
+    for i in 0..count {
+        let (x0, y0) = points[i];
+        let (x1, y1) = points[(i + 1) % points.len()];
+        let (dx, dy) = direction(i);
+        let (nx, ny) = (-dy * hw, dx * hw);
+        push(vec![
+            (x0 + nx, y0 + ny),
+            (x1 + nx, y1 + ny),
+            (x1 - nx, y1 - ny),
+            (x0 - nx, y0 - ny),
+        ]);
+    }
+
+    // Joins between segment i - 1 and i.
+    let joins = if closed { 0..count } else { 1..count };
+    for i in joins {
+        let (x, y) = points[i];
+        let (d0x, d0y) = direction((i + count - 1) % count);
+        let (d1x, d1y) = direction(i);
+        let cross = d0x * d1y - d0y * d1x;
+        let dot = d0x * d1x + d0y * d1y;
+        if cross.abs() < 1e-6 && dot > 0.0 {
+            continue;
+        }
+
+        // The outer side of the turn.
+        let side = if cross > 0.0 { -hw } else { hw };
+        let (n0x, n0y) = (-d0y * side, d0x * side);
+        let (n1x, n1y) = (-d1y * side, d1x * side);
+
+        match stroke.join {
+            LineJoin::Round => push(circle(x, y, hw, tolerance)),
+            LineJoin::Miter | LineJoin::Bevel => {
+                // 1 / cos of half the angle between the segments.
+                let ratio = 1.0 / ((1.0 + dot) / 2.0).max(0.0).sqrt();
+                if stroke.join == LineJoin::Miter && ratio <= stroke.miter_limit {
+                    let (mx, my) = (n0x + n1x, n0y + n1y);
+                    let length = mx.hypot(my);
+                    let scale = hw * ratio / length;
+                    push(vec![
+                        (x, y),
+                        (x + n0x, y + n0y),
+                        (x + mx * scale, y + my * scale),
+                        (x + n1x, y + n1y),
+                    ]);
+                } else {
+                    push(vec![(x, y), (x + n0x, y + n0y), (x + n1x, y + n1y)]);
+                }
+            }
+        }
+    }
+
+    if !closed {
+        let last = points.len() - 1;
+        let (d0x, d0y) = direction(0);
+        let (d1x, d1y) = direction(last - 1);
+        for ((x, y), (dx, dy)) in [(points[0], (-d0x, -d0y)), (points[last], (d1x, d1y))] {
+            let (nx, ny) = (-dy * hw, dx * hw);
+            match stroke.cap {
+                LineCap::Butt => {}
+                LineCap::Round => push(circle(x, y, hw, tolerance)),
+                LineCap::Square => push(vec![
+                    (x + nx, y + ny),
+                    (x + nx + dx * hw, y + ny + dy * hw),
+                    (x - nx + dx * hw, y - ny + dy * hw),
+                    (x - nx, y - ny),
+                ]),
+            }
+        }
+    }
+}
+
+fn circle(x: f32, y: f32, radius: f32, tolerance: f32) -> Vec<(f32, f32)> {
+    let step = 2.0 * (1.0 - tolerance / radius).clamp(-1.0, 1.0).acos();
+    let count = (2.0 * PI / step.max(1e-3)).ceil().clamp(8.0, 256.0) as usize;
+    (0..count)
+        .map(|i| {
+            let (s, c) = (2.0 * PI * i as f32 / count as f32).sin_cos();
+            (x + radius * c, y + radius * s)
+        })
+        .collect()
+}
+
+#[inline]
+fn signed_area(points: &[(f32, f32)]) -> f32 {
+    let mut area = 0.0;
+    for (i, (x0, y0)) in points.iter().enumerate() {
+        let (x1, y1) = points[(i + 1) % points.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(
+        points: &[(f32, f32)],
+        closed: bool,
+        cap: LineCap,
+        join: LineJoin,
+    ) -> Vec<Vec<(f32, f32)>> {
+        let stroke = Stroke {
+            cap,
+            join,
+            ..Stroke::new(2.0)
+        };
+        let mut contours = vec![];
+        stroke_polyline(points, closed, &stroke, 0.1, &mut contours);
+        contours
+    }
+
+    fn bounds(contours: &[Vec<(f32, f32)>]) -> Rect {
+        let mut path = Path::new();
+        for (x, y) in contours.iter().flatten() {
+            path.line_to(*x, *y);
+        }
+        path.bounds()
+    }
+
+    fn near(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).hypot(a.1 - b.1) < 1e-3
+    }
+
+    #[test]
+    fn flattens_subpaths() {
+        let mut path = Path::new();
+        path.line_to(4.0, 0.0);
+        path.move_to(1.0, 1.0);
+        path.line_to(2.0, 1.0);
+        path.line_to(2.0, 2.0);
+        path.close();
+        path.line_to(5.0, 5.0);
+        assert_eq!(
+            path.flatten(0.1),
+            [
+                (vec![(0.0, 0.0), (4.0, 0.0)], false),
+                (vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0)], true),
+                // Drawing on after a close starts from the subpath's start.
+                (vec![(1.0, 1.0), (5.0, 5.0)], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn flattens_curves_within_the_tolerance() {
+        let quad = |t: f32| {
+            let mt = 1.0 - t;
+            (2.0 * mt * t * 50.0 + t * t * 100.0, 2.0 * mt * t * 100.0)
+        };
+        let mut path = Path::new();
+        path.move_to(0.0, 0.0);
+        path.quad_to(50.0, 100.0, 100.0, 0.0);
+
+        let mut previous = 0;
+        for tolerance in [1.0, 0.25, 0.05] {
+            let polylines = path.flatten(tolerance);
+            let points = &polylines[0].0;
+            assert!(points.len() > previous);
+            previous = points.len();
+            assert_eq!(points.last(), Some(&(100.0, 0.0)));
+
+            // Midway along each chord the curve is within the tolerance.
+            let count = (points.len() - 1) as f32;
+            for (i, pair) in points.windows(2).enumerate() {
+                let (x, y) = quad((i as f32 + 0.5) / count);
+                let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                let distance =
+                    ((x1 - x0) * (y0 - y) - (x0 - x) * (y1 - y0)).abs() / (x1 - x0).hypot(y1 - y0);
+                assert!(distance <= tolerance, "{} > {}", distance, tolerance);
+            }
+        }
+
+        let mut cubic = Path::new();
+        cubic.move_to(0.0, 0.0);
+        cubic.cubic_to(0.0, 100.0, 100.0, 100.0, 100.0, 0.0);
+        let coarse = cubic.flatten(1.0)[0].0.len();
+        let fine = cubic.flatten(0.01)[0].0.len();
+        assert!(coarse < fine && fine <= 257);
+    }
+
+    #[test]
+    fn ends_arcs_where_asked() {
+        // A quarter, then three quarters of a circle of radius 10 around the
+        // origin.
+        for (large_arc, quarters) in [(false, 1), (true, 3)] {
+            let mut path = Path::new();
+            path.move_to(10.0, 0.0);
+            path.arc_to(10.0, 10.0, 0.0, large_arc, !large_arc, 0.0, 10.0);
+            assert_eq!(path.current_point(), (0.0, 10.0));
+            assert_eq!(path.segments().len(), 1 + quarters);
+
+            let points = &path.flatten(0.01)[0].0;
+            for (x, y) in points {
+                assert!((x.hypot(*y) - 10.0).abs() < 0.02, "{} {}", x, y);
+            }
+            let reaches_left = points.iter().any(|p| near(*p, (-10.0, 0.0)));
+            assert_eq!(reaches_left, large_arc);
+        }
+
+        // Radii too small to reach are scaled up to a half circle.
+        let mut path = Path::new();
+        path.move_to(0.0, 0.0);
+        path.arc_to(1.0, 1.0, 0.0, false, true, 10.0, 0.0);
+        assert_eq!(path.current_point(), (10.0, 0.0));
+        let points = &path.flatten(0.01)[0].0;
+        assert!(points.iter().any(|p| near(*p, (5.0, -5.0))));
+
+        // Without a radius it's a line.
+        let mut path = Path::new();
+        path.arc_to(0.0, 5.0, 0.0, false, true, 3.0, 4.0);
+        assert_eq!(path.segments(), [PathSegment::LineTo(3.0, 4.0)]);
+    }
+
+    #[test]
+    fn caps_open_strokes() {
+        let line = [(0.0, 0.0), (10.0, 0.0)];
+
+        let butt = stroke(&line, false, LineCap::Butt, LineJoin::Miter);
+        assert_eq!(butt.len(), 1);
+        assert_eq!(bounds(&butt), Rect::new(0.0, -1.0, 10.0, 2.0));
+
+        let square = stroke(&line, false, LineCap::Square, LineJoin::Miter);
+        assert_eq!(square.len(), 3);
+        assert_eq!(bounds(&square), Rect::new(-1.0, -1.0, 12.0, 2.0));
+        let area = square.iter().map(|c| signed_area(c)).sum::<f32>();
+        assert!((area - 24.0).abs() < 1e-4);
+
+        let round = stroke(&line, false, LineCap::Round, LineJoin::Miter);
+        assert_eq!(round.len(), 3);
+        assert_eq!(bounds(&round), Rect::new(-1.0, -1.0, 12.0, 2.0));
+        for (x, y) in round[1..].iter().flatten() {
+            let center = if *x < 5.0 { 0.0 } else { 10.0 };
+            assert!(((x - center).hypot(*y) - 1.0).abs() < 1e-4);
+        }
+
+        // A lone point only shows with caps that extend past it.
+        assert!(stroke(&[(1.0, 1.0)], false, LineCap::Butt, LineJoin::Miter).is_empty());
+        let dot = stroke(&[(1.0, 1.0)], false, LineCap::Square, LineJoin::Miter);
+        assert_eq!(bounds(&dot), Rect::new(0.0, 0.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn joins_segments() {
+        let corner = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        let outer = (11.0, -1.0);
+
+        // Two quads and the join, all wound the same way.
+        let miter = stroke(&corner, false, LineCap::Butt, LineJoin::Miter);
+        assert_eq!(miter.len(), 3);
+        assert!(miter.iter().all(|c| signed_area(c) > 0.0));
+        assert!(miter[2].iter().any(|p| near(*p, outer)));
+
+        let bevel = stroke(&corner, false, LineCap::Butt, LineJoin::Bevel);
+        assert_eq!(bevel[2].len(), 3);
+        assert!(bevel[2].iter().any(|p| near(*p, (10.0, -1.0))));
+        assert!(bevel[2].iter().any(|p| near(*p, (11.0, 0.0))));
+
+        // A right angle needs a miter limit of at least sqrt(2).
+        let mut limited = vec![];
+        let stroke_with_limit = Stroke {
+            miter_limit: 1.4,
+            ..Stroke::new(2.0)
+        };
+        stroke_polyline(&corner, false, &stroke_with_limit, 0.1, &mut limited);
+        assert_eq!(limited, bevel);
+
+        let round = stroke(&corner, false, LineCap::Butt, LineJoin::Round);
+        for (x, y) in &round[2] {
+            assert!(((x - 10.0).hypot(*y) - 1.0).abs() < 1e-4);
+        }
+
+        // Straight continuations need no join.
+        let straight = [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)];
+        assert_eq!(
+            stroke(&straight, false, LineCap::Butt, LineJoin::Miter).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn joins_closed_strokes_all_around() {
+        let square = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ];
+        let contours = stroke(&square, true, LineCap::Square, LineJoin::Miter);
+        // Four sides and four joins, without caps.
+        assert_eq!(contours.len(), 8);
+        assert_eq!(bounds(&contours), Rect::new(-1.0, -1.0, 12.0, 12.0));
+
+        let mut path = Path::new();
+        path.rect(0.0, 0.0, 10.0, 10.0);
+        let scaled = path.stroke_contours(&Stroke::new(2.0), &Transform::scale(2.0, 2.0), 0.1);
+        assert_eq!(bounds(&scaled), Rect::new(-2.0, -2.0, 24.0, 24.0));
+
+        assert!(stroke(&square, true, LineCap::Butt, LineJoin::Miter)
+            .iter()
+            .all(|c| signed_area(c) > 0.0));
+        let mut none = vec![];
+        stroke_polyline(&square, true, &Stroke::new(0.0), 0.1, &mut none);
+        assert!(none.is_empty());
+    }
+}
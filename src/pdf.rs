@@ -0,0 +1,783 @@
+use std::{collections::BTreeMap, fs};
+
+use pdf_writer::{
+    types::{CidFontType, FontFlags, LineCapStyle, LineJoinStyle, SystemInfo, UnicodeCmap},
+    writers::Resources,
+    Content, Filter, Finish, Name, Pdf, Rect as PdfRect, Ref, Str,
+};
+
+use crate::{
+    font::{layout, system_font, Font},
+    path::{LineCap, LineJoin, PathSegment},
+    surface::{Capabilities, DrawState, Surface},
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, FillRule, LayoutMode,
+    Path, Rect, Stroke,
+};
+
+const FONT_NAME: Name<'static> = Name(b"F0");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Group {
+    Clip,
+    Transform,
+    // The form's bounding box, in the space it's drawn in.
+    Opacity(f32, PdfRect),
+}
+
+// Writes a multi-page PDF document: every begin/end pair is a page, with one
+// pixel mapped to one point. Text is set in the font given to `set_font`, or
+// a system font otherwise, and only the glyphs used are embedded.
+pub struct PdfSurface {
+    width: u32,
+    height: u32,
+    pdf: Pdf,
+    next_ref: i32,
+    catalog: Ref,
+    page_tree: Ref,
+    resources: Ref,
+    pages: Vec<Ref>,
+    // The page's content, then one per open opacity group.
+    contents: Vec<Content>,
+    groups: Vec<Group>,
+    state: DrawState,
+    // Graphics states setting both alphas, named G0, G1, ...
+    alphas: Vec<(f32, Ref)>,
+    // Transparency groups, named X0, X1, ...
+    forms: Vec<Ref>,
+    font: Option<Font>,
+    font_searched: bool,
+    // Glyph ids used so far, with the character they were mapped from.
+    glyphs: BTreeMap<u16, char>,
+}
+
+impl PdfSurface {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut surface = Self {
+            width,
+            height,
+            pdf: Pdf::new(),
+            next_ref: 1,
+            catalog: Ref::new(1),
+            page_tree: Ref::new(1),
+            resources: Ref::new(1),
+            pages: vec![],
+            contents: vec![],
+            groups: vec![],
+            state: DrawState::new(),
+            alphas: vec![],
+            forms: vec![],
+            font: None,
+            font_searched: false,
+            glyphs: BTreeMap::new(),
+        };
+        surface.catalog = surface.alloc();
+        surface.page_tree = surface.alloc();
+        surface.resources = surface.alloc();
+        surface
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    // TrueType or OpenType data; `index` selects a face in a collection.
+    // Must be set before the first `WriteString`.
+    pub fn set_font(&mut self, data: Vec<u8>, index: u32) -> AeResult<()> {
+        if !self.glyphs.is_empty() {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        if let Err(e) = ttf_parser::Face::parse(&data, index) {
+            return Err(AcureError::BackendError(Backend::PDF, e.into()));
+        }
+        self.font = Some(Font { data, index });
+        self.font_searched = true;
+        Ok(())
+    }
+
+    // Finishes the document. Call after the last page's `end`.
+    pub fn finish(mut self) -> AeResult<Vec<u8>> {
+        let font = if self.glyphs.is_empty() {
+            None
+        } else {
+            Some(self.write_font()?)
+        };
+
+        let mut resources = self.pdf.indirect(self.resources).start::<Resources>();
+        if let Some(font) = font {
+            resources.fonts().pair(FONT_NAME, font);
+        }
+        let mut states = resources.ext_g_states();
+        for (i, (_, id)) in self.alphas.iter().enumerate() {
+            states.pair(Name(format!("G{}", i).as_bytes()), *id);
+        }
+        states.finish();
+        let mut objects = resources.x_objects();
+        for (i, id) in self.forms.iter().enumerate() {
+            objects.pair(Name(format!("X{}", i).as_bytes()), *id);
+        }
+        objects.finish();
+        resources.finish();
+
+        for (alpha, id) in &self.alphas {
+            self.pdf
+                .ext_graphics(*id)
+                .non_stroking_alpha(*alpha)
+                .stroking_alpha(*alpha);
+        }
+        self.pdf
+            .pages(self.page_tree)
+            .kids(self.pages.iter().copied())
+            .count(self.pages.len() as i32);
+        self.pdf.catalog(self.catalog).pages(self.page_tree);
+        Ok(self.pdf.finish())
+    }
+
+    #[inline]
+    pub fn save<P: AsRef<std::path::Path>>(self, path: P) -> AeResult<()> {
+        fs::write(path, self.finish()?)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn alloc(&mut self) -> Ref {
+        let id = Ref::new(self.next_ref);
+        self.next_ref += 1;
+        id
+    }
+
+    #[inline]
+    fn content(&mut self) -> &mut Content {
+        self.contents
+            .last_mut()
+            .expect("PdfSurface: command outside begin/end")
+    }
+
+    // The name of a graphics state with this fill and stroke alpha.
+    fn alpha_state(&mut self, alpha: f32) -> String {
+        let i = match self.alphas.iter().position(|(a, _)| *a == alpha) {
+            Some(i) => i,
+            None => {
+                let id = self.alloc();
+                self.alphas.push((alpha, id));
+                self.alphas.len() - 1
+            }
+        };
+        format!("G{}", i)
+    }
+
+    // Sets the color, and an alpha if it isn't opaque. Balanced by `end_paint`.
+    fn begin_paint(&mut self, color: Color, stroke: bool) {
+        let [r, g, b, a] = color.to_rgba();
+        let state = (a < 1.0).then(|| self.alpha_state(a.max(0.0)));
+        let content = self.content();
+        content.save_state();
+        if let Some(state) = state {
+            content.set_parameters(Name(state.as_bytes()));
+        }
+        if stroke {
+            content.set_stroke_rgb(r, g, b);
+        } else {
+            content.set_fill_rgb(r, g, b);
+        }
+    }
+
+    #[inline]
+    fn end_paint(&mut self) {
+        self.content().restore_state();
+    }
+
+    fn fill_path(&mut self, path: &Path, color: Color) {
+        self.begin_paint(color, false);
+        let content = self.content();
+        write_path(content, path);
+        match path.fill_rule() {
+            FillRule::NonZero => content.fill_nonzero(),
+            FillRule::EvenOdd => content.fill_even_odd(),
+        };
+        self.end_paint();
+    }
+
+    fn stroke_path(&mut self, path: &Path, stroke: &Stroke, color: Color) {
+        self.begin_paint(color, true);
+        let content = self.content();
+        content
+            .set_line_width(stroke.width)
+            .set_line_cap(match stroke.cap {
+                LineCap::Butt => LineCapStyle::ButtCap,
+                LineCap::Round => LineCapStyle::RoundCap,
+                LineCap::Square => LineCapStyle::ProjectingSquareCap,
+            })
+            .set_line_join(match stroke.join {
+                LineJoin::Miter => LineJoinStyle::MiterJoin,
+                LineJoin::Round => LineJoinStyle::RoundJoin,
+                LineJoin::Bevel => LineJoinStyle::BevelJoin,
+            })
+            .set_miter_limit(stroke.miter_limit);
+        write_path(content, path);
+        content.stroke();
+        self.end_paint();
+    }
+
+    // Laid out like the Direct2D surface: centered in the rectangle.
+    fn write_string(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color, text: &str) {
+        if !self.font_searched {
+            self.font_searched = true;
+            self.font = system_font();
+        }
+        let Some(font) = &self.font else {
+            return;
+        };
+        let Ok(face) = ttf_parser::Face::parse(&font.data, font.index) else {
+            return;
+        };
+
+        let line = layout(&face, x, y, width, height, text);
+        let mut glyphs = Vec::with_capacity(text.len() * 2);
+        for (c, (glyph, _)) in text.chars().zip(&line.glyphs) {
+            self.glyphs.entry(glyph.0).or_insert(c);
+            glyphs.extend_from_slice(&glyph.0.to_be_bytes());
+        }
+        let (size, (x, baseline)) = (line.size, line.origin);
+
+        self.begin_paint(color, false);
+        self.content()
+            .begin_text()
+            .set_font(FONT_NAME, size)
+            // The page is flipped, so the text has to be flipped back.
+            .set_text_matrix([1.0, 0.0, 0.0, -1.0, x, baseline])
+            .show(Str(&glyphs))
+            .end_text();
+        self.end_paint();
+    }
+
+    // A Type0 font with Identity-H encoding, so strings are glyph ids.
+    fn write_font(&mut self) -> AeResult<Ref> {
+        let type0 = self.alloc();
+        let cid = self.alloc();
+        let descriptor = self.alloc();
+        let file = self.alloc();
+        let cmap = self.alloc();
+
+        let font = self
+            .font
+            .as_ref()
+            .ok_or(AcureError::UnauthorizedOperation)?;
+        let face = ttf_parser::Face::parse(&font.data, font.index)
+            .map_err(|e| AcureError::BackendError(Backend::PDF, e.into()))?;
+        let glyphs = self.glyphs.keys().copied().collect::<Vec<_>>();
+        let subset = subsetter::subset(&font.data, font.index, subsetter::Profile::pdf(&glyphs))
+            .map_err(|e| AcureError::BackendError(Backend::PDF, e.into()))?;
+        let cff = face
+            .raw_face()
+            .table(ttf_parser::Tag::from_bytes(b"CFF "))
+            .is_some();
+
+        // Subsets are tagged with six letters derived from the glyph set.
+        let hash = glyphs.iter().fold(0x811c9dc5u32, |h, g| {
+            (h ^ *g as u32).wrapping_mul(0x01000193)
+        });
+        let tag = (0..6)
+            .map(|i| (b'A' + (hash >> (i * 5) & 31) as u8 % 26) as char)
+            .collect::<String>();
+        let postscript = face
+            .names()
+            .into_iter()
+            .filter(|n| n.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .find_map(|n| n.to_string())
+            .unwrap_or_else(|| "Font".to_owned());
+        let base_font = format!("{}+{}", tag, postscript);
+        let base_font = Name(base_font.as_bytes());
+
+        let to_pdf = 1000.0 / face.units_per_em() as f32;
+        let system_info = SystemInfo {
+            registry: Str(b"Adobe"),
+            ordering: Str(b"Identity"),
+            supplement: 0,
+        };
+
+        self.pdf
+            .type0_font(type0)
+            .base_font(base_font)
+            .encoding_predefined(Name(b"Identity-H"))
+            .descendant_font(cid)
+            .to_unicode(cmap);
+
+        let mut cid_font = self.pdf.cid_font(cid);
+        cid_font
+            .subtype(if cff {
+                CidFontType::Type0
+            } else {
+                CidFontType::Type2
+            })
+            .base_font(base_font)
+            .system_info(system_info)
+            .font_descriptor(descriptor)
+            .default_width(0.0);
+        if !cff {
+            cid_font.cid_to_gid_map_predefined(Name(b"Identity"));
+        }
+        let mut widths = cid_font.widths();
+        for glyph in &glyphs {
+            let advance = face
+                .glyph_hor_advance(ttf_parser::GlyphId(*glyph))
+                .unwrap_or(0);
+            widths.consecutive(*glyph, [advance as f32 * to_pdf]);
+        }
+        widths.finish();
+        cid_font.finish();
+
+        let bbox = face.global_bounding_box();
+        let mut flags = FontFlags::SYMBOLIC;
+        if face.is_italic() {
+            flags |= FontFlags::ITALIC;
+        }
+        if face.is_monospaced() {
+            flags |= FontFlags::FIXED_PITCH;
+        }
+        let mut font_descriptor = self.pdf.font_descriptor(descriptor);
+        font_descriptor
+            .name(base_font)
+            .flags(flags)
+            .bbox(PdfRect::new(
+                bbox.x_min as f32 * to_pdf,
+                bbox.y_min as f32 * to_pdf,
+                bbox.x_max as f32 * to_pdf,
+                bbox.y_max as f32 * to_pdf,
+            ))
+            .italic_angle(face.italic_angle().unwrap_or(0.0))
+            .ascent(face.ascender() as f32 * to_pdf)
+            .descent(face.descender() as f32 * to_pdf)
+            .cap_height(face.capital_height().unwrap_or(face.ascender()) as f32 * to_pdf)
+            .stem_v(80.0);
+        if cff {
+            font_descriptor.font_file3(file);
+        } else {
+            font_descriptor.font_file2(file);
+        }
+        font_descriptor.finish();
+
+        let subset = deflate(&subset);
+        let mut stream = self.pdf.stream(file, &subset);
+        stream.filter(Filter::FlateDecode);
+        if cff {
+            stream.pair(Name(b"Subtype"), Name(b"OpenType"));
+        }
+        stream.finish();
+
+        let mut unicode = UnicodeCmap::new(Name(b"Custom"), system_info);
+        for (glyph, c) in &self.glyphs {
+            unicode.pair(*glyph, *c);
+        }
+        self.pdf.cmap(cmap, &unicode.finish());
+
+        Ok(type0)
+    }
+
+    fn close_group(&mut self, group: Group) {
+        let open = match self.groups.pop() {
+            Some(open) => open,
+            None => {
+                warn!("Pop{:?} without a matching push", group);
+                return;
+            }
+        };
+        if std::mem::discriminant(&open) != std::mem::discriminant(&group) {
+            warn!("Pop{:?} closes a Push{:?} group", group, open);
+        }
+
+        match open {
+            Group::Clip | Group::Transform => {
+                self.content().restore_state();
+            }
+            Group::Opacity(opacity, bbox) => {
+                let content = deflate(&self.contents.pop().unwrap().finish());
+                let id = self.alloc();
+                let mut form = self.pdf.form_xobject(id, &content);
+                form.filter(Filter::FlateDecode);
+                form.bbox(bbox);
+                form.group().transparency();
+                form.pair(Name(b"Resources"), self.resources);
+                form.finish();
+                self.forms.push(id);
+
+                let name = format!("X{}", self.forms.len() - 1);
+                let state = self.alpha_state(opacity);
+                self.content()
+                    .save_state()
+                    .set_parameters(Name(state.as_bytes()))
+                    .x_object(Name(name.as_bytes()))
+                    .restore_state();
+            }
+        }
+    }
+}
+
+impl Surface for PdfSurface {
+    // Takes effect from the next page.
+    #[inline]
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    // Pages can be at most 14400 units on a side.
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_texture_size: Some(14400),
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: false,
+        }
+    }
+
+    // Blending is up to the viewer, which is sRGB.
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        space == ColorSpace::Srgb
+    }
+
+    fn begin(&mut self) -> AeResult<()> {
+        self.groups.clear();
+        self.state.reset();
+        let mut content = Content::new();
+        // Top-left origin, y down, like the other surfaces.
+        content.transform([1.0, 0.0, 0.0, -1.0, 0.0, self.height as f32]);
+        self.contents = vec![content];
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Color) {
+        if self.contents.is_empty() {
+            warn!("PdfSurface: clear outside begin/end");
+            return;
+        }
+        if color.to_rgba()[3] > 0.0 {
+            let mut path = Path::new();
+            path.rect(0.0, 0.0, self.width as f32, self.height as f32);
+            self.fill_path(&path, color);
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: &Command,
+        _align: AlignMode,
+        _layout: LayoutMode,
+    ) -> AeResult<()> {
+        if self.contents.is_empty() {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        match command {
+            Command::FillRectangle(x, y, width, height, radius, color) => {
+                let mut path = Path::new();
+                let radius = *radius as f32;
+                path.rounded_rect(
+                    *x as f32,
+                    *y as f32,
+                    *width as f32,
+                    *height as f32,
+                    radius,
+                    radius,
+                );
+                self.fill_path(&path, *color);
+            }
+            Command::WriteString(x, y, width, height, color, text) => self.write_string(
+                *x as f32,
+                *y as f32,
+                *width as f32,
+                *height as f32,
+                *color,
+                text,
+            ),
+            Command::FillPath(path, color) => self.fill_path(path, *color),
+            Command::StrokePath(path, stroke, color) => self.stroke_path(path, stroke, *color),
+            Command::PushClip(x, y, width, height) => {
+                self.state.apply(command);
+                self.content()
+                    .save_state()
+                    .rect(*x as f32, *y as f32, *width as f32, *height as f32)
+                    .clip_nonzero()
+                    .end_path();
+                self.groups.push(Group::Clip);
+            }
+            Command::PopClip => {
+                self.state.apply(command);
+                self.close_group(Group::Clip);
+            }
+            Command::PushTransform(t) => {
+                self.state.apply(command);
+                self.content()
+                    .save_state()
+                    .transform([t.a, t.b, t.c, t.d, t.e, t.f]);
+                self.groups.push(Group::Transform);
+            }
+            Command::PopTransform => {
+                self.state.apply(command);
+                self.close_group(Group::Transform);
+            }
+            // Drawn into a transparency group, which is then painted with
+            // the opacity as its alpha.
+            Command::PushOpacity(opacity) => {
+                let page = Rect::new(0.0, 0.0, self.width as f32, self.height as f32);
+                let bbox = match self.state.transform().invert() {
+                    Some(inverse) => page.transform(&inverse),
+                    None => page,
+                };
+                self.state.apply(command);
+                self.groups.push(Group::Opacity(
+                    opacity.clamp(0.0, 1.0),
+                    PdfRect::new(bbox.x, bbox.y, bbox.right(), bbox.bottom()),
+                ));
+                self.contents.push(Content::new());
+            }
+            Command::PopOpacity => {
+                self.state.apply(command);
+                self.close_group(Group::Opacity(0.0, PdfRect::new(0.0, 0.0, 0.0, 0.0)));
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        while let Some(group) = self.groups.last().copied() {
+            self.close_group(group);
+        }
+        let Some(content) = self.contents.pop() else {
+            return Err(AcureError::UnauthorizedOperation);
+        };
+        let content = deflate(&content.finish());
+
+        let page = self.alloc();
+        let id = self.alloc();
+        self.pdf.stream(id, &content).filter(Filter::FlateDecode);
+        let mut writer = self.pdf.page(page);
+        writer
+            .media_box(PdfRect::new(
+                0.0,
+                0.0,
+                self.width as f32,
+                self.height as f32,
+            ))
+            .parent(self.page_tree)
+            .contents(id);
+        writer.pair(Name(b"Resources"), self.resources);
+        writer.finish();
+        self.pages.push(page);
+        Ok(())
+    }
+}
+
+#[inline]
+fn deflate(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+}
+
+// PDF has no quadratic curves, so they're raised to cubics.
+fn write_path(content: &mut Content, path: &Path) {
+    let (mut x0, mut y0) = (0.0, 0.0);
+    let (mut start_x, mut start_y) = (0.0, 0.0);
+    for segment in path.segments() {
+        match *segment {
+            PathSegment::MoveTo(x, y) => {
+                content.move_to(x, y);
+                (start_x, start_y) = (x, y);
+                (x0, y0) = (x, y);
+            }
+            PathSegment::LineTo(x, y) => {
+                content.line_to(x, y);
+                (x0, y0) = (x, y);
+            }
+            PathSegment::QuadTo(x1, y1, x, y) => {
+                content.cubic_to(
+                    x0 + (x1 - x0) * 2.0 / 3.0,
+                    y0 + (y1 - y0) * 2.0 / 3.0,
+                    x + (x1 - x) * 2.0 / 3.0,
+                    y + (y1 - y) * 2.0 / 3.0,
+                    x,
+                    y,
+                );
+                (x0, y0) = (x, y);
+            }
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                content.cubic_to(x1, y1, x2, y2, x, y);
+                (x0, y0) = (x, y);
+            }
+            PathSegment::Close => {
+                content.close_path();
+                (x0, y0) = (start_x, start_y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The decompressed streams, in the order they were written.
+    fn streams(pdf: &[u8]) -> Vec<Vec<u8>> {
+        let mut streams = vec![];
+        let mut rest = pdf;
+        while let Some(start) = find(rest, b"stream\n") {
+            rest = &rest[start + 7..];
+            let end = find(rest, b"\nendstream").unwrap();
+            if let Ok(data) = miniz_oxide::inflate::decompress_to_vec_zlib(&rest[..end]) {
+                streams.push(data);
+            }
+            rest = &rest[end + 10..];
+        }
+        streams
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).position(|w| w == needle)
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        find(haystack, needle.as_bytes()).is_some()
+    }
+
+    fn draw(surface: &mut PdfSurface, commands: &[Command]) {
+        surface.begin().unwrap();
+        for command in commands {
+            surface
+                .command(command, AlignMode::CenterAligned, LayoutMode::NoCare)
+                .unwrap();
+        }
+        surface.end().unwrap();
+    }
+
+    #[test]
+    fn writes_a_page_per_frame() {
+        let mut surface = PdfSurface::new(200, 100);
+        draw(
+            &mut surface,
+            &[Command::FillRectangle(
+                10,
+                20,
+                30,
+                40,
+                0.0,
+                Color::ARGB(255, 255, 0, 0),
+            )],
+        );
+        surface.surface_resize(300, 150);
+        draw(&mut surface, &[]);
+        assert_eq!(surface.page_count(), 2);
+
+        let pdf = surface.finish().unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(contains(&pdf, "/Count 2"));
+        assert!(contains(&pdf, "/MediaBox [0 0 200 100]"));
+        assert!(contains(&pdf, "/MediaBox [0 0 300 150]"));
+        // No text, no font.
+        assert!(!contains(&pdf, "/FontFile"));
+
+        let streams = streams(&pdf);
+        assert_eq!(streams.len(), 2);
+        // Flipped to a top-left origin.
+        assert!(contains(&streams[0], "1 0 0 -1 0 100 cm"));
+        assert!(contains(&streams[0], "1 0 0 rg"));
+        assert!(contains(&streams[0], "10 20 m"));
+        assert!(contains(&streams[1], "1 0 0 -1 0 150 cm"));
+    }
+
+    #[test]
+    fn writes_clips_alphas_and_opacity_groups() {
+        let mut path = Path::new();
+        path.move_to(0.0, 0.0);
+        path.quad_to(3.0, 3.0, 6.0, 0.0);
+        path.set_fill_rule(FillRule::EvenOdd);
+        let mut surface = PdfSurface::new(100, 100);
+        draw(
+            &mut surface,
+            &[
+                Command::PushClip(1, 2, 3, 4),
+                Command::PushOpacity(0.5),
+                Command::FillPath(path, Color::RGBA(0.0, 0.0, 1.0, 0.25)),
+                Command::PopOpacity,
+                Command::PopClip,
+            ],
+        );
+        let pdf = surface.finish().unwrap();
+        assert!(contains(&pdf, "/CA 0.5"));
+        assert!(contains(&pdf, "/CA 0.25"));
+        assert!(contains(&pdf, "/Type /XObject"));
+
+        let streams = streams(&pdf);
+        // The group's form first, then the page.
+        let (form, page) = (&streams[0], &streams[1]);
+        assert!(contains(page, "1 2 3 4 re\nW\nn"));
+        // The fill's alpha is named first, when the form is drawn.
+        assert!(contains(form, "/G0 gs"));
+        assert!(contains(page, "/G1 gs\n/X0 Do"));
+        // The quadratic raised to a cubic.
+        assert!(contains(form, "2 2 4 2 6 0 c"));
+        assert!(contains(form, "f*"));
+    }
+
+    #[test]
+    fn embeds_a_subset_of_the_font() {
+        let Some(font) = system_font() else {
+            return;
+        };
+        let mut surface = PdfSurface::new(100, 100);
+        surface.set_font(font.data, font.index).unwrap();
+        draw(
+            &mut surface,
+            &[Command::WriteString(
+                0,
+                0,
+                100,
+                40,
+                Color::BLACK,
+                "Hi".to_owned(),
+            )],
+        );
+        assert!(matches!(
+            surface.set_font(vec![], 0),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        let pdf = surface.finish().unwrap();
+        assert!(contains(&pdf, "/Identity-H"));
+        assert!(contains(&pdf, "/ToUnicode"));
+        assert!(contains(&pdf, "/FontFile"));
+        assert!(streams(&pdf).iter().any(|s| contains(s, "Tj")));
+    }
+
+    #[test]
+    fn rejects_misuse() {
+        let mut surface = PdfSurface::new(10, 10);
+        assert!(matches!(
+            surface.set_font(b"not a font".to_vec(), 0),
+            Err(AcureError::BackendError(Backend::PDF, _))
+        ));
+        assert!(matches!(
+            surface.command(
+                &Command::PopClip,
+                AlignMode::CenterAligned,
+                LayoutMode::NoCare
+            ),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        assert!(matches!(
+            surface.end(),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        surface.clear(Color::WHITE);
+        assert_eq!(surface.page_count(), 0);
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    path::{LineCap, LineJoin, PathSegment},
+    AcureError, AeResult, AlignMode, Color, ColorSpace, Command, FillRule, LayoutMode, Path,
+    Stroke, Transform,
+};
+
+const MAGIC: &[u8; 4] = b"ACRF";
+// Version 2 added the clip, transform and opacity commands, version 3 paths.
+pub const FORMAT_VERSION: u16 = 3;
+
+// Everything needed to draw one frame exactly as it was drawn.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    pub background: Color,
+    pub color_space: ColorSpace,
+    pub align: AlignMode,
+    pub layout: LayoutMode,
+    pub commands: Vec<Command>,
+}
+
+impl Frame {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        write_color(out, self.background);
+        out.push(match self.color_space {
+            ColorSpace::Srgb => 0,
+            ColorSpace::Linear => 1,
+        });
+        out.push(match self.align {
+            AlignMode::CenterAligned => 0,
+            AlignMode::RightAligned => 1,
+            AlignMode::LeftAligned => 2,
+            AlignMode::TopAligned => 3,
+            AlignMode::BottomAligned => 4,
+            AlignMode::Flex => 5,
+        });
+        out.push(match self.layout {
+            LayoutMode::NoCare => 0,
+            LayoutMode::AdjustSize => 1,
+        });
+        write_varint(out, self.commands.len() as u32);
+        for command in &self.commands {
+            write_command(out, command);
+        }
+    }
+
+    #[inline]
+    pub fn decode(data: &[u8]) -> AeResult<Self> {
+        Self::decode_version(data, FORMAT_VERSION)
+    }
+
+    // Commands newer than `version` are rejected.
+    fn decode_version(data: &[u8], version: u16) -> AeResult<Self> {
+        let mut reader = Reader {
+            data,
+            pos: 0,
+            version,
+        };
+        let background = reader.color()?;
+        let color_space = match reader.u8()? {
+            0 => ColorSpace::Srgb,
+            1 => ColorSpace::Linear,
+            v => return Err(invalid(format!("unknown color space {}", v))),
+        };
+        let align = match reader.u8()? {
+            0 => AlignMode::CenterAligned,
+            1 => AlignMode::RightAligned,
+            2 => AlignMode::LeftAligned,
+            3 => AlignMode::TopAligned,
+            4 => AlignMode::BottomAligned,
+            5 => AlignMode::Flex,
+            v => return Err(invalid(format!("unknown align mode {}", v))),
+        };
+        let layout = match reader.u8()? {
+            0 => LayoutMode::NoCare,
+            1 => LayoutMode::AdjustSize,
+            v => return Err(invalid(format!("unknown layout mode {}", v))),
+        };
+
+        let count = reader.varint()?;
+        let mut commands = Vec::with_capacity(count.min(4096) as usize);
+        for _ in 0..count {
+            commands.push(reader.command()?);
+        }
+
+        if reader.pos != data.len() {
+            return Err(invalid("trailing bytes after frame".to_owned()));
+        }
+
+        Ok(Self {
+            background,
+            color_space,
+            align,
+            layout,
+            commands,
+        })
+    }
+}
+
+// Writes frames to a new recording file.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    buffer: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> AeResult<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            buffer: vec![],
+        })
+    }
+
+    // Each frame is flushed, so a crash still leaves a readable recording.
+    pub fn write_frame(&mut self, frame: &Frame) -> AeResult<()> {
+        self.buffer.clear();
+        frame.encode(&mut self.buffer);
+        self.writer
+            .write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// A recording read back from a file.
+pub struct Recording {
+    version: u16,
+    frames: Vec<Frame>,
+}
+
+impl Recording {
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> AeResult<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> AeResult<Self> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an acure recording".to_owned()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+
+        // A frame cut short by a crash ends the recording, the ones before
+        // it are kept. Reading through `take` only allocates what the file
+        // actually holds, whatever the length says.
+        let mut frames = vec![];
+        let mut data = vec![];
+        loop {
+            let mut len = [0; 4];
+            match reader.read_exact(&mut len) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let len = u32::from_le_bytes(len) as usize;
+            data.clear();
+            reader.by_ref().take(len as u64).read_to_end(&mut data)?;
+            if data.len() < len {
+                break;
+            }
+            frames.push(Frame::decode_version(&data, version)?);
+        }
+
+        Ok(Self { version, frames })
+    }
+
+    #[inline]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    #[inline]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+#[inline]
+fn invalid(message: String) -> AcureError {
+    AcureError::InvalidRecording(message)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_color(out: &mut Vec<u8>, color: Color) {
+    match color {
+        Color::ARGB(a, r, g, b) => out.extend_from_slice(&[0, a, r, g, b]),
+        Color::RGBA(r, g, b, a) => {
+            out.push(1);
+            for c in [r, g, b, a] {
+                out.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+    }
+}
+
+fn write_rect(out: &mut Vec<u8>, x: u32, y: u32, width: u32, height: u32) {
+    for v in [x, y, width, height] {
+        write_varint(out, v);
+    }
+}
+
+fn write_command(out: &mut Vec<u8>, command: &Command) {
+    match command {
+        Command::FillRectangle(x, y, width, height, radius, color) => {
+            out.push(0);
+            write_rect(out, *x, *y, *width, *height);
+            out.extend_from_slice(&radius.to_le_bytes());
+            write_color(out, *color);
+        }
+        Command::WriteString(x, y, width, height, color, text) => {
+            out.push(1);
+            write_rect(out, *x, *y, *width, *height);
+            write_color(out, *color);
+            write_varint(out, text.len() as u32);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Command::PushClip(x, y, width, height) => {
+            out.push(2);
+            write_rect(out, *x, *y, *width, *height);
+        }
+        Command::PopClip => out.push(3),
+        Command::PushTransform(t) => {
+            out.push(4);
+            for v in [t.a, t.b, t.c, t.d, t.e, t.f] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        Command::PopTransform => out.push(5),
+        Command::PushOpacity(opacity) => {
+            out.push(6);
+            out.extend_from_slice(&opacity.to_le_bytes());
+        }
+        Command::PopOpacity => out.push(7),
+        Command::FillPath(path, color) => {
+            out.push(8);
+            write_path(out, path);
+            write_color(out, *color);
+        }
+        Command::StrokePath(path, stroke, color) => {
+            out.push(9);
+            write_path(out, path);
+            out.extend_from_slice(&stroke.width.to_le_bytes());
+            out.push(match stroke.cap {
+                LineCap::Butt => 0,
+                LineCap::Round => 1,
+                LineCap::Square => 2,
+            });
+            out.push(match stroke.join {
+                LineJoin::Miter => 0,
+                LineJoin::Round => 1,
+                LineJoin::Bevel => 2,
+            });
+            out.extend_from_slice(&stroke.miter_limit.to_le_bytes());
+            write_color(out, *color);
+        }
+    }
+}
+
+fn write_path(out: &mut Vec<u8>, path: &Path) {
+    out.push(match path.fill_rule() {
+        FillRule::NonZero => 0,
+        FillRule::EvenOdd => 1,
+    });
+    write_varint(out, path.segments().len() as u32);
+    for segment in path.segments() {
+        let (tag, values): (u8, &[f32]) = match segment {
+            PathSegment::MoveTo(x, y) => (0, &[*x, *y]),
+            PathSegment::LineTo(x, y) => (1, &[*x, *y]),
+            PathSegment::QuadTo(x1, y1, x, y) => (2, &[*x1, *y1, *x, *y]),
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => (3, &[*x1, *y1, *x2, *y2, *x, *y]),
+            PathSegment::Close => (4, &[]),
+        };
+        out.push(tag);
+        for v in values {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: u16,
+}
+
+impl<'a> Reader<'a> {
+    #[inline]
+    fn bytes(&mut self, len: usize) -> AeResult<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid("unexpected end of frame".to_owned()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    #[inline]
+    fn u8(&mut self) -> AeResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    #[inline]
+    fn f32(&mut self) -> AeResult<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    #[inline]
+    fn f64(&mut self) -> AeResult<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> AeResult<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long".to_owned()))
+    }
+
+    fn color(&mut self) -> AeResult<Color> {
+        match self.u8()? {
+            0 => {
+                let c = self.bytes(4)?;
+                Ok(Color::ARGB(c[0], c[1], c[2], c[3]))
+            }
+            1 => Ok(Color::RGBA(
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+            )),
+            v => Err(invalid(format!("unknown color tag {}", v))),
+        }
+    }
+
+    fn rect(&mut self) -> AeResult<(u32, u32, u32, u32)> {
+        Ok((
+            self.varint()?,
+            self.varint()?,
+            self.varint()?,
+            self.varint()?,
+        ))
+    }
+
+    fn path(&mut self) -> AeResult<Path> {
+        let fill_rule = match self.u8()? {
+            0 => FillRule::NonZero,
+            1 => FillRule::EvenOdd,
+            v => return Err(invalid(format!("unknown fill rule {}", v))),
+        };
+        let count = self.varint()?;
+        let mut segments = Vec::with_capacity(count.min(4096) as usize);
+        for _ in 0..count {
+            segments.push(match self.u8()? {
+                0 => PathSegment::MoveTo(self.f32()?, self.f32()?),
+                1 => PathSegment::LineTo(self.f32()?, self.f32()?),
+                2 => PathSegment::QuadTo(self.f32()?, self.f32()?, self.f32()?, self.f32()?),
+                3 => PathSegment::CubicTo(
+                    self.f32()?,
+                    self.f32()?,
+                    self.f32()?,
+                    self.f32()?,
+                    self.f32()?,
+                    self.f32()?,
+                ),
+                4 => PathSegment::Close,
+                v => return Err(invalid(format!("unknown path segment {}", v))),
+            });
+        }
+        Ok(Path::from_segments(segments, fill_rule))
+    }
+
+    fn command(&mut self) -> AeResult<Command> {
+        let tag = self.u8()?;
+        let last = match self.version {
+            1 => 1,
+            2 => 7,
+            _ => 9,
+        };
+        if tag > last {
+            return Err(invalid(format!("unknown command tag {}", tag)));
+        }
+        match tag {
+            0 => {
+                let (x, y, width, height) = self.rect()?;
+                let radius = self.f64()?;
+                let color = self.color()?;
+                Ok(Command::FillRectangle(x, y, width, height, radius, color))
+            }
+            1 => {
+                let (x, y, width, height) = self.rect()?;
+                let color = self.color()?;
+                let len = self.varint()? as usize;
+                let text = std::str::from_utf8(self.bytes(len)?)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_owned();
+                Ok(Command::WriteString(x, y, width, height, color, text))
+            }
+            2 => {
+                let (x, y, width, height) = self.rect()?;
+                Ok(Command::PushClip(x, y, width, height))
+            }
+            3 => Ok(Command::PopClip),
+            4 => Ok(Command::PushTransform(Transform::new(
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+                self.f32()?,
+            ))),
+            5 => Ok(Command::PopTransform),
+            6 => Ok(Command::PushOpacity(self.f32()?)),
+            7 => Ok(Command::PopOpacity),
+            8 => {
+                let path = self.path()?;
+                Ok(Command::FillPath(path, self.color()?))
+            }
+            9 => {
+                let path = self.path()?;
+                let width = self.f32()?;
+                let cap = match self.u8()? {
+                    0 => LineCap::Butt,
+                    1 => LineCap::Round,
+                    2 => LineCap::Square,
+                    v => return Err(invalid(format!("unknown line cap {}", v))),
+                };
+                let join = match self.u8()? {
+                    0 => LineJoin::Miter,
+                    1 => LineJoin::Round,
+                    2 => LineJoin::Bevel,
+                    v => return Err(invalid(format!("unknown line join {}", v))),
+                };
+                let stroke = Stroke {
+                    width,
+                    cap,
+                    join,
+                    miter_limit: self.f32()?,
+                };
+                Ok(Command::StrokePath(path, stroke, self.color()?))
+            }
+            v => Err(invalid(format!("unknown command tag {}", v))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn frame() -> Frame {
+        let mut path = Path::new();
+        path.move_to(1.0, 2.0);
+        path.quad_to(3.0, 4.0, 5.0, 6.0);
+        path.cubic_to(7.0, 8.0, 9.0, 10.0, 11.0, 12.0);
+        path.close();
+        Frame {
+            background: Color::RGBA(0.1, 0.2, 0.3, 0.4),
+            color_space: ColorSpace::Linear,
+            align: AlignMode::LeftAligned,
+            layout: LayoutMode::AdjustSize,
+            commands: vec![
+                Command::FillRectangle(1, 2, 300, 400, 5.5, Color::ARGB(1, 2, 3, 4)),
+                Command::WriteString(0, 0, 10, 10, Color::BLACK, "héllo".to_owned()),
+                Command::PushClip(1, 2, 3, 4),
+                Command::PushTransform(Transform::new(1.0, 2.0, 3.0, 4.0, 5.0, 6.0)),
+                Command::PushOpacity(0.5),
+                Command::FillPath(path.clone(), Color::WHITE),
+                Command::StrokePath(
+                    path,
+                    Stroke {
+                        width: 2.0,
+                        cap: LineCap::Round,
+                        join: LineJoin::Bevel,
+                        miter_limit: 3.0,
+                    },
+                    Color::BLACK,
+                ),
+                Command::PopOpacity,
+                Command::PopTransform,
+                Command::PopClip,
+            ],
+        }
+    }
+
+    fn recording(frames: &[Frame]) -> Vec<u8> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "acure-record-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut recorder = Recorder::create(&path).unwrap();
+        for frame in frames {
+            recorder.write_frame(frame).unwrap();
+        }
+        drop(recorder);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut second = frame();
+        second.commands.truncate(1);
+        let frames = [frame(), second];
+        let recording = Recording::read(&recording(&frames)[..]).unwrap();
+        assert_eq!(recording.version(), FORMAT_VERSION);
+        assert_eq!(recording.frames(), frames);
+    }
+
+    #[test]
+    fn truncated_frame_is_dropped() {
+        let data = recording(&[frame(), frame()]);
+        let recording = Recording::read(&data[..data.len() - 3]).unwrap();
+        assert_eq!(recording.frames(), [frame()]);
+    }
+
+    #[test]
+    fn oversized_length_ends_the_recording() {
+        let mut data = recording(&[frame()]);
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        assert_eq!(Recording::read(&data[..]).unwrap().frames(), [frame()]);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut data = recording(&[frame()]);
+        for version in [0, FORMAT_VERSION + 1] {
+            data[4..6].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(
+                Recording::read(&data[..]),
+                Err(AcureError::InvalidRecording(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn commands_newer_than_the_version_are_rejected() {
+        let mut data = recording(&[frame()]);
+        data[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(Recording::read(&data[..]).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+
+use crate::{AcureError, AeResult, Color, Command, Path, Stroke, Transform};
+
+// Never reused, so an id held on to after its node was removed can't end up
+// pointing at a different node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(u64);
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Content {
+    // Only draws its children.
+    Group,
+    // Width,Height,Radius,Color
+    Rectangle(u32, u32, f64, Color),
+    // Width,Height,Color,Text
+    Text(u32, u32, Color, String),
+    Path {
+        path: Path,
+        fill: Option<Color>,
+        stroke: Option<(Stroke, Color)>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    content: Content,
+    // Relative to the parent, applied after `transform`.
+    x: f32,
+    y: f32,
+    transform: Transform,
+    opacity: f32,
+    // Width and height of a clip around the node and its children.
+    clip: Option<(u32, u32)>,
+    visible: bool,
+}
+
+impl Node {
+    #[inline]
+    fn new(parent: Option<NodeId>, content: Content) -> Self {
+        Self {
+            parent,
+            children: vec![],
+            content,
+            x: 0.0,
+            y: 0.0,
+            transform: Transform::IDENTITY,
+            opacity: 1.0,
+            clip: None,
+            visible: true,
+        }
+    }
+}
+
+// A retained tree of nodes, drawn parents first and siblings in order. It's
+// flattened into commands again only after something changed.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    nodes: HashMap<NodeId, Node>,
+    root: NodeId,
+    next_id: u64,
+    commands: Vec<Command>,
+    dirty: bool,
+}
+
+impl Default for Scene {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        let root = NodeId(0);
+        let mut nodes = HashMap::new();
+        nodes.insert(root, Node::new(None, Content::Group));
+        Self {
+            nodes,
+            root,
+            next_id: 1,
+            commands: vec![],
+            dirty: true,
+        }
+    }
+
+    // The group everything else is added under. It can't be removed.
+    #[inline]
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    #[inline]
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Only the root is left.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    // Adds a node as the last child of `parent`, drawn above its siblings.
+    pub fn insert(&mut self, parent: NodeId, content: Content) -> AeResult<NodeId> {
+        let id = NodeId(self.next_id);
+        self.node_mut(parent)?.children.push(id);
+        self.next_id += 1;
+        self.nodes.insert(id, Node::new(Some(parent), content));
+        Ok(id)
+    }
+
+    // Removes the node and everything under it.
+    pub fn remove(&mut self, id: NodeId) -> AeResult<()> {
+        if id == self.root {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        let node = self.nodes.remove(&id).ok_or(AcureError::InvalidNode(id))?;
+        if let Some(parent) = node.parent.and_then(|p| self.nodes.get_mut(&p)) {
+            parent.children.retain(|c| *c != id);
+        }
+        let mut stack = node.children;
+        while let Some(child) = stack.pop() {
+            if let Some(node) = self.nodes.remove(&child) {
+                stack.extend(node.children);
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Moves the node, with its children, to the end of `parent`'s children.
+    pub fn set_parent(&mut self, id: NodeId, parent: NodeId) -> AeResult<()> {
+        if id == self.root || self.is_ancestor(id, parent) {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        let old = self.node(id)?.parent;
+        self.node(parent)?;
+        if let Some(old) = old.and_then(|p| self.nodes.get_mut(&p)) {
+            old.children.retain(|c| *c != id);
+        }
+        self.node_mut(parent)?.children.push(id);
+        self.node_mut(id)?.parent = Some(parent);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes.get(&id).and_then(|n| n.parent)
+    }
+
+    #[inline]
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        self.nodes.get(&id).map_or(&[], |n| &n.children)
+    }
+
+    #[inline]
+    pub fn content(&self, id: NodeId) -> Option<&Content> {
+        self.nodes.get(&id).map(|n| &n.content)
+    }
+
+    #[inline]
+    pub fn position(&self, id: NodeId) -> Option<(f32, f32)> {
+        self.nodes.get(&id).map(|n| (n.x, n.y))
+    }
+
+    #[inline]
+    pub fn set_content(&mut self, id: NodeId, content: Content) -> AeResult<()> {
+        self.node_mut(id)?.content = content;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_position(&mut self, id: NodeId, x: f32, y: f32) -> AeResult<()> {
+        let node = self.node_mut(id)?;
+        (node.x, node.y) = (x, y);
+        Ok(())
+    }
+
+    // The fill color, or the stroke color of a path without a fill. Does
+    // nothing to groups.
+    pub fn set_color(&mut self, id: NodeId, color: Color) -> AeResult<()> {
+        match &mut self.node_mut(id)?.content {
+            Content::Group => {}
+            Content::Rectangle(_, _, _, c) | Content::Text(_, _, c, _) => *c = color,
+            Content::Path { fill, stroke, .. } => match (fill, stroke) {
+                (Some(fill), _) => *fill = color,
+                (None, Some((_, c))) => *c = color,
+                (None, None) => {}
+            },
+        }
+        Ok(())
+    }
+
+    pub fn set_text<S: Into<String>>(&mut self, id: NodeId, text: S) -> AeResult<()> {
+        if !matches!(self.node(id)?.content, Content::Text(..)) {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        if let Content::Text(_, _, _, t) = &mut self.node_mut(id)?.content {
+            *t = text.into();
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) -> AeResult<()> {
+        self.node_mut(id)?.transform = transform;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn set_opacity(&mut self, id: NodeId, opacity: f32) -> AeResult<()> {
+        self.node_mut(id)?.opacity = opacity.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    // Clips the node and its children to (0, 0, width, height) in its own
+    // coordinates.
+    #[inline]
+    pub fn set_clip(&mut self, id: NodeId, clip: Option<(u32, u32)>) -> AeResult<()> {
+        self.node_mut(id)?.clip = clip;
+        Ok(())
+    }
+
+    // Hidden nodes are skipped along with their children.
+    #[inline]
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) -> AeResult<()> {
+        self.node_mut(id)?.visible = visible;
+        Ok(())
+    }
+
+    // Whether anything changed since the commands were last built.
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // The scene as commands, rebuilt if anything changed.
+    pub fn commands(&mut self) -> &[Command] {
+        if self.dirty {
+            let mut commands = std::mem::take(&mut self.commands);
+            commands.clear();
+            self.flatten(self.root, &mut commands);
+            self.commands = commands;
+            self.dirty = false;
+        }
+        &self.commands
+    }
+
+    #[inline]
+    fn node(&self, id: NodeId) -> AeResult<&Node> {
+        self.nodes.get(&id).ok_or(AcureError::InvalidNode(id))
+    }
+
+    // Anything that needs a node to change marks the scene dirty.
+    #[inline]
+    fn node_mut(&mut self, id: NodeId) -> AeResult<&mut Node> {
+        let node = self.nodes.get_mut(&id).ok_or(AcureError::InvalidNode(id))?;
+        self.dirty = true;
+        Ok(node)
+    }
+
+    fn is_ancestor(&self, ancestor: NodeId, mut id: NodeId) -> bool {
+        loop {
+            if id == ancestor {
+                return true;
+            }
+            match self.parent(id) {
+                Some(parent) => id = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn flatten(&self, id: NodeId, commands: &mut Vec<Command>) {
+        let Some(node) = self.nodes.get(&id) else {
+            return;
+        };
+        if !node.visible || node.opacity <= 0.0 {
+            return;
+        }
+
+        let transform = node.transform.then(Transform::translate(node.x, node.y));
+        let transformed = !transform.is_identity();
+        if transformed {
+            commands.push(Command::PushTransform(transform));
+        }
+        if node.opacity < 1.0 {
+            commands.push(Command::PushOpacity(node.opacity));
+        }
+        if let Some((width, height)) = node.clip {
+            commands.push(Command::PushClip(0, 0, width, height));
+        }
+
+        match &node.content {
+            Content::Group => {}
+            Content::Rectangle(width, height, radius, color) => commands.push(
+                Command::FillRectangle(0, 0, *width, *height, *radius, *color),
+            ),
+            Content::Text(width, height, color, text) => commands.push(Command::WriteString(
+                0,
+                0,
+                *width,
+                *height,
+                *color,
+                text.clone(),
+            )),
+            Content::Path { path, fill, stroke } => {
+                if let Some(fill) = fill {
+                    commands.push(Command::FillPath(path.clone(), *fill));
+                }
+                if let Some((stroke, color)) = stroke {
+                    commands.push(Command::StrokePath(path.clone(), *stroke, *color));
+                }
+            }
+        }
+        for child in &node.children {
+            self.flatten(*child, commands);
+        }
+
+        if node.clip.is_some() {
+            commands.push(Command::PopClip);
+        }
+        if node.opacity < 1.0 {
+            commands.push(Command::PopOpacity);
+        }
+        if transformed {
+            commands.push(Command::PopTransform);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattens_parents_first_with_their_state() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let group = scene.insert(root, Content::Group).unwrap();
+        scene.set_position(group, 5.0, 10.0).unwrap();
+        scene.set_opacity(group, 0.5).unwrap();
+        scene.set_clip(group, Some((20, 30))).unwrap();
+        let red = Color::from_hex("#f00").unwrap();
+        scene
+            .insert(group, Content::Rectangle(20, 30, 2.0, red))
+            .unwrap();
+        scene
+            .insert(group, Content::Text(20, 10, Color::BLACK, "hi".into()))
+            .unwrap();
+        let mut path = Path::new();
+        path.rect(0.0, 0.0, 4.0, 4.0);
+        let shape = scene
+            .insert(
+                root,
+                Content::Path {
+                    path: path.clone(),
+                    fill: Some(red),
+                    stroke: Some((Stroke::new(2.0), Color::BLACK)),
+                },
+            )
+            .unwrap();
+        scene
+            .set_transform(shape, Transform::scale(2.0, 2.0))
+            .unwrap();
+
+        assert_eq!(
+            scene.commands(),
+            [
+                Command::PushTransform(Transform::translate(5.0, 10.0)),
+                Command::PushOpacity(0.5),
+                Command::PushClip(0, 0, 20, 30),
+                Command::FillRectangle(0, 0, 20, 30, 2.0, red),
+                Command::WriteString(0, 0, 20, 10, Color::BLACK, "hi".into()),
+                Command::PopClip,
+                Command::PopOpacity,
+                Command::PopTransform,
+                Command::PushTransform(Transform::scale(2.0, 2.0)),
+                Command::FillPath(path.clone(), red),
+                Command::StrokePath(path, Stroke::new(2.0), Color::BLACK),
+                Command::PopTransform,
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_hidden_and_transparent_nodes() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let hidden = scene.insert(root, Content::Group).unwrap();
+        scene
+            .insert(hidden, Content::Rectangle(1, 1, 0.0, Color::BLACK))
+            .unwrap();
+        scene.set_visible(hidden, false).unwrap();
+        let clear = scene
+            .insert(root, Content::Rectangle(2, 2, 0.0, Color::BLACK))
+            .unwrap();
+        scene.set_opacity(clear, -1.0).unwrap();
+        assert!(scene.commands().is_empty());
+
+        scene.set_visible(hidden, true).unwrap();
+        assert_eq!(
+            scene.commands(),
+            [Command::FillRectangle(0, 0, 1, 1, 0.0, Color::BLACK)]
+        );
+    }
+
+    #[test]
+    fn rebuilds_only_after_a_change() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let group = scene.insert(root, Content::Group).unwrap();
+        let rect = scene
+            .insert(group, Content::Rectangle(1, 1, 0.0, Color::BLACK))
+            .unwrap();
+        scene.commands();
+        assert!(!scene.is_dirty());
+
+        scene.set_color(rect, Color::WHITE).unwrap();
+        assert!(scene.is_dirty());
+        assert_eq!(
+            scene.commands(),
+            [Command::FillRectangle(0, 0, 1, 1, 0.0, Color::WHITE)]
+        );
+
+        // Moving a node moves it after its new siblings.
+        let other = scene
+            .insert(root, Content::Rectangle(2, 2, 0.0, Color::BLACK))
+            .unwrap();
+        scene.set_parent(rect, root).unwrap();
+        assert_eq!(scene.children(root), [group, other, rect]);
+        assert_eq!(scene.parent(rect), Some(root));
+        assert_eq!(
+            scene.commands(),
+            [
+                Command::FillRectangle(0, 0, 2, 2, 0.0, Color::BLACK),
+                Command::FillRectangle(0, 0, 1, 1, 0.0, Color::WHITE),
+            ]
+        );
+
+        scene.set_parent(rect, group).unwrap();
+        scene.remove(group).unwrap();
+        assert!(!scene.contains(rect));
+        assert_eq!(scene.len(), 2);
+        assert_eq!(
+            scene.commands(),
+            [Command::FillRectangle(0, 0, 2, 2, 0.0, Color::BLACK)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_changes() {
+        let mut scene = Scene::new();
+        let root = scene.root();
+        let group = scene.insert(root, Content::Group).unwrap();
+        let child = scene.insert(group, Content::Group).unwrap();
+
+        assert!(matches!(
+            scene.remove(root),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        assert!(matches!(
+            scene.set_parent(group, child),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        scene.commands();
+        assert!(matches!(
+            scene.set_text(group, "no"),
+            Err(AcureError::UnauthorizedOperation)
+        ));
+        // A rejected edit changes nothing.
+        assert!(!scene.is_dirty());
+
+        scene.remove(group).unwrap();
+        assert!(scene.is_empty());
+        // Ids aren't reused, so the old one stays invalid.
+        let new = scene.insert(root, Content::Group).unwrap();
+        assert_ne!(new, child);
+        assert!(matches!(
+            scene.set_position(child, 1.0, 1.0),
+            Err(AcureError::InvalidNode(id)) if id == child
+        ));
+        assert!(matches!(
+            scene.insert(child, Content::Group),
+            Err(AcureError::InvalidNode(_))
+        ));
+    }
+}
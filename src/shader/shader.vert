@@ -1,13 +1,15 @@
 precision mediump float;
 
-attribute vec3 position;
+attribute vec2 aPos;
+attribute vec4 aColor;
 
 uniform mat4 projectionMatrix;
+uniform float linearColor;
 
-attribute vec4 color;
 varying vec4 vColor;
     
 void main(void){
-    gl_Position = vec4(position, 1.0);
-    vColor = color;
-}
\ No newline at end of file
+    gl_Position = projectionMatrix * vec4(aPos, 0.0, 1.0);
+    vec3 decoded = mix(aColor.rgb / 12.92, pow((aColor.rgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, aColor.rgb));
+    vColor = vec4(mix(aColor.rgb, decoded, linearColor), aColor.a);
+}
@@ -3,9 +3,11 @@ layout (location = 0) in vec2 aPos;
 layout (location = 1) in vec4 aColor;
 out vec4 color;
 uniform mat4 projectionMatrix;
+uniform float linearColor;
 
 void main(){
     vec4 position = vec4(aPos, 0.0, 1.0);
     gl_Position = projectionMatrix * position;
-    color = aColor;
-}
\ No newline at end of file
+    vec3 decoded = mix(aColor.rgb / 12.92, pow((aColor.rgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, aColor.rgb));
+    color = vec4(mix(aColor.rgb, decoded, linearColor), aColor.a);
+}
@@ -0,0 +1,630 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::{
+    color::{linear_to_srgb, srgb_to_linear},
+    font::{layout, system_font, Font, Outline},
+    image::pixel_bounds,
+    surface::{Capabilities, DrawState, Surface},
+    AeResult, AlignMode, Color, ColorSpace, Command, FillRule, Image, LayoutMode, Path, Rect,
+    Transform,
+};
+
+// Maximum distance in pixels between a curve and its flattened polyline.
+const TOLERANCE: f32 = 0.2;
+
+// Renders into a CPU-side buffer of premultiplied 0xAARRGGBB pixels.
+pub struct SoftwareSurface {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+    color_space: ColorSpace,
+    state: DrawState,
+    // Buffers saved by PushOpacity, with the opacity to composite at.
+    layers: Vec<(Vec<u32>, f32)>,
+    // Whether `pixels` holds a finished frame to draw damage over.
+    drawn: bool,
+    font: Option<Font>,
+    font_searched: bool,
+    // Glyph outlines in font units.
+    outlines: HashMap<u16, Path>,
+}
+
+impl SoftwareSurface {
+    #[inline]
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width as usize) * (height as usize)],
+            color_space: ColorSpace::Srgb,
+            state: DrawState::new(),
+            layers: vec![],
+            drawn: false,
+            font: None,
+            font_searched: false,
+            outlines: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    fn fill_rectangle(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        radius: f64,
+        color: Color,
+    ) {
+        let mut path = Path::new();
+        path.rounded_rect(
+            x as f32,
+            y as f32,
+            width as f32,
+            height as f32,
+            radius as f32,
+            radius as f32,
+        );
+        let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+        self.fill_contours(&contours, FillRule::NonZero, color);
+    }
+
+    // Glyphs are filled as paths, like everything else.
+    fn write_string(&mut self, rect: Rect, color: Color, text: &str) {
+        if !self.font_searched {
+            self.font_searched = true;
+            self.font = system_font();
+        }
+        let Some(font) = &self.font else {
+            return;
+        };
+        let Ok(face) = ttf_parser::Face::parse(&font.data, font.index) else {
+            return;
+        };
+
+        let line = layout(&face, rect.x, rect.y, rect.width, rect.height, text);
+        let scale = line.size / face.units_per_em() as f32;
+        let transform = self.state.transform();
+        let mut contours = vec![];
+        for (glyph, offset) in line.glyphs {
+            let outline = self.outlines.entry(glyph.0).or_insert_with(|| {
+                let mut outline = Outline(Path::new());
+                face.outline_glyph(glyph, &mut outline);
+                outline.0
+            });
+            // Font units point up.
+            let placed = Transform::scale(scale, -scale)
+                .then(Transform::translate(line.origin.0 + offset, line.origin.1))
+                .then(transform);
+            contours.extend(outline.fill_contours(&placed, TOLERANCE));
+        }
+        self.fill_contours(&contours, FillRule::NonZero, color);
+    }
+
+    fn fill_contours(&mut self, contours: &[Vec<(f32, f32)>], rule: FillRule, color: Color) {
+        let mut points = contours.iter().flatten();
+        let Some(&(mut x0, mut y0)) = points.next() else {
+            return;
+        };
+        let (mut x1, mut y1) = (x0, y0);
+        for (x, y) in points {
+            x0 = x0.min(*x);
+            y0 = y0.min(*y);
+            x1 = x1.max(*x);
+            y1 = y1.max(*y);
+        }
+        let bounds = Rect::new(x0, y0, x1 - x0, y1 - y0);
+
+        let clip = match self.state.clip() {
+            Some(clip) => clip.intersect(&self.bounds()),
+            None => self.bounds(),
+        };
+        let region = bounds.round_out().intersect(&clip.round_out());
+        if region.is_empty() {
+            return;
+        }
+
+        let mut rasterizer = Rasterizer::new(region);
+        for points in contours {
+            for (i, p0) in points.iter().enumerate() {
+                let p1 = points[(i + 1) % points.len()];
+                rasterizer.line(*p0, p1);
+            }
+        }
+
+        let paint = Paint::new(color, self.color_space);
+        let width = self.width as usize;
+        rasterizer.for_each(rule, |px, py, coverage| {
+            // Clip edges may fall inside a pixel.
+            let cx = (clip.right().min(px as f32 + 1.0) - clip.x.max(px as f32)).clamp(0.0, 1.0);
+            let cy = (clip.bottom().min(py as f32 + 1.0) - clip.y.max(py as f32)).clamp(0.0, 1.0);
+            let pixel = &mut self.pixels[py as usize * width + px as usize];
+            *pixel = paint.blend(*pixel, coverage * cx * cy);
+        });
+    }
+
+    #[inline]
+    fn bounds(&self) -> Rect {
+        Rect::from_u32(0, 0, self.width, self.height)
+    }
+
+    #[inline]
+    fn push_layer(&mut self, opacity: f32) {
+        let layer = vec![0; self.pixels.len()];
+        let below = std::mem::replace(&mut self.pixels, layer);
+        self.layers.push((below, opacity.clamp(0.0, 1.0)));
+    }
+
+    fn pop_layer(&mut self) {
+        let Some((mut below, opacity)) = self.layers.pop() else {
+            return;
+        };
+        for (dst, src) in below.iter_mut().zip(&self.pixels) {
+            if *src != 0 {
+                let src = unpack(*src, self.color_space).map(|c| c * opacity);
+                *dst = over(src, *dst, self.color_space);
+            }
+        }
+        self.pixels = below;
+    }
+}
+
+impl Surface for SoftwareSurface {
+    #[inline]
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; (width as usize) * (height as usize)];
+        self.layers.clear();
+        self.drawn = false;
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_texture_size: None,
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: true,
+        }
+    }
+
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        if self.color_space != space {
+            self.color_space = space;
+            self.drawn = false;
+        }
+        true
+    }
+
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        self.state.reset();
+        Ok(())
+    }
+
+    // Clips to the bounding box of the damage, which is usually one or two
+    // nearby rectangles anyway.
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.drawn {
+            return false;
+        }
+        let bounds = damage.iter().fold(Rect::default(), |b, r| b.union(r));
+        self.state
+            .set_damage(Some(bounds.intersect(&self.bounds())));
+        true
+    }
+
+    fn clear(&mut self, color: Color) {
+        let [r, g, b, a] = color.to_premultiplied();
+        let pixel = pack([r, g, b, a]);
+        match self.state.clip() {
+            Some(clip) => {
+                let clip = clip.round_out().intersect(&self.bounds());
+                // Damage can lie entirely outside the surface.
+                if clip.is_empty() {
+                    return;
+                }
+                let (x0, x1) = (clip.x as usize, clip.right() as usize);
+                for y in clip.y as usize..clip.bottom() as usize {
+                    let row = y * self.width as usize;
+                    self.pixels[row + x0..row + x1].fill(pixel);
+                }
+            }
+            None => self.pixels.fill(pixel),
+        }
+    }
+
+    #[inline]
+    fn command(
+        &mut self,
+        command: &Command,
+        _align: AlignMode,
+        _layout: LayoutMode,
+    ) -> AeResult<()> {
+        match command {
+            Command::FillRectangle(x, y, width, height, radius, color) => {
+                self.fill_rectangle(*x, *y, *width, *height, *radius, *color)
+            }
+            Command::WriteString(x, y, width, height, color, text) => {
+                self.write_string(Rect::from_u32(*x, *y, *width, *height), *color, text)
+            }
+            Command::FillPath(path, color) => {
+                let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, path.fill_rule(), *color);
+            }
+            Command::StrokePath(path, stroke, color) => {
+                let contours = path.stroke_contours(stroke, &self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, *color);
+            }
+            // Group opacity needs its own layer, so it doesn't go through
+            // the state's opacity.
+            Command::PushOpacity(opacity) => self.push_layer(*opacity),
+            Command::PopOpacity => self.pop_layer(),
+            _ => {
+                self.state.apply(command);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn end(&mut self) -> AeResult<()> {
+        while !self.layers.is_empty() {
+            self.pop_layer();
+        }
+        self.drawn = true;
+        Ok(())
+    }
+
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        let (x, y, width, height) = pixel_bounds(&rect, self.width, self.height);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for row in y..y + height {
+            let start = (row * self.width + x) as usize;
+            pixels.extend_from_slice(&self.pixels[start..start + width as usize]);
+        }
+        Ok(Image::new(width, height, pixels))
+    }
+}
+
+// A source color prepared for blending in a given color space.
+struct Paint {
+    // Premultiplied, in the blend space.
+    color: [f32; 4],
+    // The packed pixel for a fully covered opaque source.
+    opaque: Option<u32>,
+    space: ColorSpace,
+}
+
+impl Paint {
+    #[inline]
+    fn new(color: Color, space: ColorSpace) -> Self {
+        let [r, g, b, a] = match space {
+            ColorSpace::Srgb => color.to_rgba(),
+            ColorSpace::Linear => color.to_linear_rgba(),
+        };
+        let opaque = (a >= 1.0).then(|| pack(color.to_rgba()));
+
+        Self {
+            color: [r * a, g * a, b * a, a],
+            opaque,
+            space,
+        }
+    }
+
+    #[inline]
+    fn blend(&self, dst: u32, coverage: f32) -> u32 {
+        if coverage <= 0.0 {
+            return dst;
+        }
+        if coverage >= 1.0 {
+            if let Some(pixel) = self.opaque {
+                return pixel;
+            }
+        }
+
+        over(self.color.map(|c| c * coverage), dst, self.space)
+    }
+}
+
+// Source-over of a premultiplied color in the blend space onto a pixel.
+#[inline]
+fn over(src: [f32; 4], dst: u32, space: ColorSpace) -> u32 {
+    let dst = unpack(dst, space);
+    let inv = 1.0 - src[3];
+    let out = [
+        src[0] + dst[0] * inv,
+        src[1] + dst[1] * inv,
+        src[2] + dst[2] * inv,
+        src[3] + dst[3] * inv,
+    ];
+
+    match space {
+        ColorSpace::Srgb => pack(out),
+        ColorSpace::Linear => {
+            let a = out[3];
+            if a <= 0.0 {
+                return 0;
+            }
+            let encode = |c: f32| encode_srgb(c / a) * a;
+            pack([encode(out[0]), encode(out[1]), encode(out[2]), a])
+        }
+    }
+}
+
+// Accumulates exact area coverage of closed polygons over a pixel region.
+pub(crate) struct Rasterizer {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    // Two extra cells per row take what falls on or past the right edge.
+    cells: Vec<f32>,
+}
+
+impl Rasterizer {
+    pub(crate) fn new(region: Rect) -> Self {
+        let width = region.width as usize;
+        let height = region.height as usize;
+        Self {
+            x: region.x as i32,
+            y: region.y as i32,
+            width,
+            height,
+            cells: vec![0.0; (width + 2) * height],
+        }
+    }
+
+    pub(crate) fn line(&mut self, p0: (f32, f32), p1: (f32, f32)) {
+        let p0 = (p0.0 - self.x as f32, p0.1 - self.y as f32);
+        let p1 = (p1.0 - self.x as f32, p1.1 - self.y as f32);
+
+        // Anything left of the region only matters through its winding, so
+        // it can be folded onto the left edge; likewise for the right. Split
+        // where the line crosses either edge so the folding keeps it exact.
+        let right = self.width as f32;
+        let mut splits = [0.0, 1.0, 1.0, 1.0];
+        for (i, edge) in [0.0, right].into_iter().enumerate() {
+            let t = (edge - p0.0) / (p1.0 - p0.0);
+            if t > 0.0 && t < 1.0 {
+                splits[i + 1] = t;
+            }
+        }
+        splits.sort_by(f32::total_cmp);
+
+        let at = |t: f32| {
+            (
+                (p0.0 + (p1.0 - p0.0) * t).clamp(0.0, right),
+                p0.1 + (p1.1 - p0.1) * t,
+            )
+        };
+        for pair in splits.windows(2) {
+            if pair[0] < pair[1] {
+                self.segment(at(pair[0]), at(pair[1]));
+            }
+        }
+    }
+
+    fn segment(&mut self, p0: (f32, f32), p1: (f32, f32)) {
+        let right = self.width as f32;
+        if p0.1 == p1.1 {
+            return;
+        }
+        let (dir, p0, p1) = if p0.1 < p1.1 {
+            (1.0, p0, p1)
+        } else {
+            (-1.0, p1, p0)
+        };
+
+        let stride = self.width + 2;
+        let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+        let mut x = p0.0;
+        if p0.1 < 0.0 {
+            x -= p0.1 * dxdy;
+        }
+        let y0 = p0.1.max(0.0) as usize;
+        let y1 = (p1.1.ceil().max(0.0) as usize).min(self.height);
+
+        for y in y0..y1 {
+            let row = y * stride;
+            let dy = ((y + 1) as f32).min(p1.1) - (y as f32).max(p0.1);
+            let xnext = (x + dxdy * dy).clamp(0.0, right);
+            let d = dy * dir;
+            let (x0, x1) = if x < xnext { (x, xnext) } else { (xnext, x) };
+            let x0floor = x0.floor();
+            let x0i = x0floor as usize;
+            let x1ceil = x1.ceil();
+            let x1i = x1ceil as usize;
+
+            if x1i <= x0i + 1 {
+                let xmf = 0.5 * (x + xnext) - x0floor;
+                self.cells[row + x0i] += d - d * xmf;
+                self.cells[row + x0i + 1] += d * xmf;
+            } else {
+                let s = (x1 - x0).recip();
+                let x0f = x0 - x0floor;
+                let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+                let x1f = x1 - x1ceil + 1.0;
+                let am = 0.5 * s * x1f * x1f;
+                self.cells[row + x0i] += d * a0;
+                if x1i == x0i + 2 {
+                    self.cells[row + x0i + 1] += d * (1.0 - a0 - am);
+                } else {
+                    let a1 = s * (1.5 - x0f);
+                    self.cells[row + x0i + 1] += d * (a1 - a0);
+                    for xi in x0i + 2..x1i - 1 {
+                        self.cells[row + xi] += d * s;
+                    }
+                    let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                    self.cells[row + x1i - 1] += d * (1.0 - a2 - am);
+                }
+                self.cells[row + x1i] += d * am;
+            }
+            x = xnext;
+        }
+    }
+
+    // Calls `f` with device coordinates and coverage for every covered pixel.
+    pub(crate) fn for_each<F: FnMut(i32, i32, f32)>(&self, rule: FillRule, mut f: F) {
+        let stride = self.width + 2;
+        for y in 0..self.height {
+            let mut acc = 0.0;
+            for (x, cell) in self.cells[y * stride..y * stride + self.width]
+                .iter()
+                .enumerate()
+            {
+                acc += cell;
+                let coverage = match rule {
+                    FillRule::NonZero => acc.abs().min(1.0),
+                    FillRule::EvenOdd => {
+                        let a = acc.abs() % 2.0;
+                        if a > 1.0 {
+                            2.0 - a
+                        } else {
+                            a
+                        }
+                    }
+                };
+                if coverage > 1.0 / 512.0 {
+                    f(self.x + x as i32, self.y + y as i32, coverage);
+                }
+            }
+        }
+    }
+}
+
+// Premultiplied [r, g, b, a] in 0.0-1.0 to 0xAARRGGBB.
+#[inline]
+fn pack(rgba: [f32; 4]) -> u32 {
+    let [r, g, b, a] = rgba.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u32);
+    (a << 24) | (r << 16) | (g << 8) | b
+}
+
+// 0xAARRGGBB to premultiplied [r, g, b, a] in the blend space.
+#[inline]
+fn unpack(pixel: u32, space: ColorSpace) -> [f32; 4] {
+    let a = (pixel >> 24) as u8;
+    let r = (pixel >> 16) as u8;
+    let g = (pixel >> 8) as u8;
+    let b = pixel as u8;
+
+    match space {
+        ColorSpace::Srgb => [r, g, b, a].map(|c| (c as f32) / 255.0),
+        ColorSpace::Linear => {
+            if a == 0 {
+                return [0.0; 4];
+            }
+            let alpha = (a as f32) / 255.0;
+            let decode = |c: u8| {
+                let straight = ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255);
+                decode_srgb(straight as u8) * alpha
+            };
+            [decode(r), decode(g), decode(b), alpha]
+        }
+    }
+}
+
+#[inline]
+fn decode_srgb(c: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = srgb_to_linear(i as f32 / 255.0);
+        }
+        table
+    });
+    table[c as usize]
+}
+
+#[inline]
+fn encode_srgb(c: f32) -> f32 {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..4096)
+            .map(|i| linear_to_srgb(i as f32 / 4095.0))
+            .collect()
+    });
+    table[(c.clamp(0.0, 1.0) * 4095.0).round() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Acure;
+
+    fn draw(acure: &mut Acure, surface: &mut SoftwareSurface, color: Color) {
+        acure.clear();
+        acure.push(Command::FillRectangle(10, 10, 10, 10, 0.0, Color::BLACK));
+        acure.push(Command::FillRectangle(200, 95, 10, 10, 0.0, color));
+        acure.begin(surface).unwrap();
+        acure.write(surface).unwrap();
+    }
+
+    #[test]
+    fn ignores_damage_outside_the_surface() {
+        let mut acure = Acure::new();
+        acure.set_background_color(Color::WHITE);
+        let mut surface = SoftwareSurface::new(100, 100);
+        draw(&mut acure, &mut surface, Color::BLACK);
+        let before = surface.pixels().to_vec();
+
+        draw(&mut acure, &mut surface, Color::WHITE);
+        assert_eq!(
+            acure.damage(),
+            Some(&[Rect::new(199.0, 94.0, 12.0, 12.0)][..])
+        );
+        assert_eq!(surface.pixels(), before);
+    }
+
+    #[test]
+    fn writes_text_inside_its_box() {
+        if system_font().is_none() {
+            return;
+        }
+        let mut surface = SoftwareSurface::new(64, 32);
+        assert!(surface.capabilities().text);
+        surface.begin().unwrap();
+        surface.clear(Color::WHITE);
+        surface
+            .command(
+                &Command::PushTransform(Transform::translate(16.0, 0.0)),
+                AlignMode::CenterAligned,
+                LayoutMode::NoCare,
+            )
+            .unwrap();
+        surface
+            .command(
+                &Command::WriteString(0, 0, 32, 32, Color::BLACK, "HH".to_owned()),
+                AlignMode::CenterAligned,
+                LayoutMode::NoCare,
+            )
+            .unwrap();
+        surface.end().unwrap();
+
+        let pixels = surface.pixels();
+        assert!(pixels.contains(&0xff000000));
+        for (i, pixel) in pixels.iter().enumerate() {
+            if !(16..48).contains(&(i % 64)) {
+                assert_eq!(*pixel, 0xffffffff);
+            }
+        }
+    }
+}
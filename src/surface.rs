@@ -1,10 +1,147 @@
-use crate::{AlignMode, Color, Command, LayoutMode};
+use crate::{
+    AcureError, AeResult, AlignMode, Color, ColorSpace, Command, Image, LayoutMode, Rect, Transform,
+};
+
+// What a surface can draw, so that callers can adapt to the backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    // Largest width or height in pixels, or None if there's no limit.
+    pub max_texture_size: Option<u32>,
+    // Draws WriteString; surfaces without text skip it.
+    pub text: bool,
+    // Edges of paths and rounded rectangles are antialiased.
+    pub antialiasing: bool,
+    // PushClip is honored, possibly only by its bounding box.
+    pub clip: bool,
+    // `read_pixels` works.
+    pub readback: bool,
+}
 
 pub trait Surface {
     fn surface_resize(&mut self, width: u32, height: u32);
 
-    fn begin(&mut self);
-    fn clear(&self, color: Color);
-    fn command(&self, command: &Command, align: AlignMode, layout: LayoutMode);
-    fn end(&mut self);
+    fn capabilities(&self) -> Capabilities;
+
+    // Returns false if the surface can only blend in sRGB space.
+    fn set_color_space(&mut self, space: ColorSpace) -> bool;
+
+    // Returns false if presenting can't wait for the vertical blank.
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        let _ = vsync;
+        false
+    }
+
+    // Pixels per unit of the window's size on HiDPI outputs. Sizes passed to
+    // `surface_resize` and commands stay in pixels. Returns false if the
+    // surface can't be scaled by the compositor.
+    fn set_buffer_scale(&mut self, scale: u32) -> bool {
+        let _ = scale;
+        false
+    }
+
+    fn begin(&mut self) -> AeResult<()>;
+
+    // Limits the frame to `damage`, in whole device pixels, keeping the
+    // previous frame everywhere else. Called between `begin` and `clear`; an
+    // empty list means nothing changed. Returns false if the surface can't
+    // keep its contents, and the frame is then drawn in full.
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        let _ = damage;
+        false
+    }
+
+    fn clear(&mut self, color: Color);
+    fn command(&mut self, command: &Command, align: AlignMode, layout: LayoutMode) -> AeResult<()>;
+    // Presents the frame. Called after a failed `command` too.
+    fn end(&mut self) -> AeResult<()>;
+
+    // Copies `rect` of the last finished frame, rounded out to whole pixels
+    // and limited to the surface. Fails on surfaces that can't be read.
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        let _ = rect;
+        Err(AcureError::UnauthorizedOperation)
+    }
+}
+
+// The clip, transform and opacity stacks built up by the Push/Pop commands.
+// Unbalanced pops are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct DrawState {
+    damage: Option<Rect>,
+    // Device space, already intersected with the enclosing clip.
+    clips: Vec<Rect>,
+    transforms: Vec<Transform>,
+    opacities: Vec<f32>,
+}
+
+impl DrawState {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        self.damage = None;
+        self.clips.clear();
+        self.transforms.clear();
+        self.opacities.clear();
+    }
+
+    // Clips everything to `damage` until the next reset.
+    #[inline]
+    pub fn set_damage(&mut self, damage: Option<Rect>) {
+        self.damage = damage;
+    }
+
+    // Returns false if `command` draws something rather than changing state.
+    pub fn apply(&mut self, command: &Command) -> bool {
+        match command {
+            Command::PushClip(x, y, width, height) => {
+                let mut rect = Rect::from_u32(*x, *y, *width, *height).transform(&self.transform());
+                if let Some(clip) = self.clip() {
+                    rect = rect.intersect(&clip);
+                }
+                self.clips.push(rect);
+            }
+            Command::PopClip => {
+                self.clips.pop();
+            }
+            Command::PushTransform(transform) => {
+                self.transforms.push(transform.then(self.transform()));
+            }
+            Command::PopTransform => {
+                self.transforms.pop();
+            }
+            Command::PushOpacity(opacity) => {
+                self.opacities
+                    .push(opacity.clamp(0.0, 1.0) * self.opacity());
+            }
+            Command::PopOpacity => {
+                self.opacities.pop();
+            }
+            Command::FillRectangle(..)
+            | Command::WriteString(..)
+            | Command::FillPath(..)
+            | Command::StrokePath(..) => return false,
+        }
+        true
+    }
+
+    #[inline]
+    pub fn transform(&self) -> Transform {
+        self.transforms.last().copied().unwrap_or_default()
+    }
+
+    // The bounding box of the clip in device space, if any. Clips pushed
+    // under a rotation are approximated by their bounds.
+    #[inline]
+    pub fn clip(&self) -> Option<Rect> {
+        self.clips.last().copied().or(self.damage)
+    }
+
+    #[inline]
+    pub fn opacity(&self) -> f32 {
+        self.opacities.last().copied().unwrap_or(1.0)
+    }
 }
@@ -0,0 +1,490 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use crate::{
+    path::{LineCap, LineJoin, PathSegment},
+    surface::{Capabilities, Surface},
+    AeResult, AlignMode, Color, ColorSpace, Command, FillRule, LayoutMode, Path,
+};
+
+mod import;
+
+pub use import::SvgDocument;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Group {
+    Clip,
+    Transform,
+    Opacity,
+}
+
+// Writes each frame as a standalone SVG document. The last finished frame is
+// kept in memory; with `to_directory` every frame is also written out as
+// `frame-00000.svg`, `frame-00001.svg`, ...
+pub struct SvgSurface {
+    width: u32,
+    height: u32,
+    body: String,
+    document: String,
+    groups: Vec<Group>,
+    clip_id: u32,
+    directory: Option<PathBuf>,
+    frame: u32,
+}
+
+impl SvgSurface {
+    #[inline]
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            body: String::new(),
+            document: String::new(),
+            groups: vec![],
+            clip_id: 0,
+            directory: None,
+            frame: 0,
+        }
+    }
+
+    #[inline]
+    pub fn to_directory<P: Into<PathBuf>>(width: u32, height: u32, directory: P) -> Self {
+        Self {
+            directory: Some(directory.into()),
+            ..Self::new(width, height)
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // The last finished frame.
+    #[inline]
+    pub fn document(&self) -> &str {
+        &self.document
+    }
+
+    #[inline]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> AeResult<()> {
+        fs::write(path, &self.document)?;
+        Ok(())
+    }
+
+    fn open_group(&mut self, group: Group, attributes: &str) {
+        let _ = write!(self.body, "<g {}>", attributes);
+        self.groups.push(group);
+    }
+
+    fn close_group(&mut self, group: Group) {
+        match self.groups.pop() {
+            Some(open) => {
+                if open != group {
+                    warn!("Pop{:?} closes a Push{:?} group", group, open);
+                }
+                self.body.push_str("</g>");
+            }
+            None => warn!("Pop{:?} without a matching push", group),
+        }
+    }
+}
+
+impl Surface for SvgSurface {
+    #[inline]
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_texture_size: None,
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: false,
+        }
+    }
+
+    // Blending is up to whatever renders the document, which is sRGB.
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        space == ColorSpace::Srgb
+    }
+
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        self.body.clear();
+        self.groups.clear();
+        self.clip_id = 0;
+        let _ = write!(
+            self.body,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = self.width,
+            h = self.height
+        );
+        Ok(())
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        if color.to_rgba()[3] > 0.0 {
+            let _ = write!(
+                self.body,
+                r#"<rect width="100%" height="100%"{}/>"#,
+                paint("fill", color)
+            );
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: &Command,
+        _align: AlignMode,
+        _layout: LayoutMode,
+    ) -> AeResult<()> {
+        match command {
+            Command::FillRectangle(x, y, width, height, radius, color) => {
+                let _ = write!(
+                    self.body,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}""#,
+                    x, y, width, height
+                );
+                if *radius > 0.0 && radius.is_finite() {
+                    let _ = write!(self.body, r#" rx="{}""#, radius);
+                }
+                let _ = write!(self.body, "{}/>", paint("fill", *color));
+            }
+            // Laid out like the Direct2D surface: centered in the rectangle.
+            Command::WriteString(x, y, width, height, color, text) => {
+                let _ = write!(
+                    self.body,
+                    r#"<text x="{}" y="{}" font-family="Yu gothic, sans-serif" font-size="{}" text-anchor="middle" dominant-baseline="central"{}>{}</text>"#,
+                    *x as f32 + *width as f32 / 2.0,
+                    *y as f32 + *height as f32 / 2.0,
+                    *height as f32 / 1.7,
+                    paint("fill", *color),
+                    escape(text)
+                );
+            }
+            Command::FillPath(path, color) => {
+                if !is_finite(path) {
+                    warn!("SvgSurface: skipping a path with non-finite coordinates");
+                    return Ok(());
+                }
+                let rule = match path.fill_rule() {
+                    FillRule::NonZero => "",
+                    FillRule::EvenOdd => r#" fill-rule="evenodd""#,
+                };
+                let _ = write!(
+                    self.body,
+                    r#"<path d="{}"{}{}/>"#,
+                    path_data(path),
+                    rule,
+                    paint("fill", *color)
+                );
+            }
+            Command::StrokePath(path, stroke, color) => {
+                if !is_finite(path) || !stroke.width.is_finite() || !stroke.miter_limit.is_finite()
+                {
+                    warn!("SvgSurface: skipping a stroke with non-finite values");
+                    return Ok(());
+                }
+                let cap = match stroke.cap {
+                    LineCap::Butt => "butt",
+                    LineCap::Round => "round",
+                    LineCap::Square => "square",
+                };
+                let join = match stroke.join {
+                    LineJoin::Miter => "miter",
+                    LineJoin::Round => "round",
+                    LineJoin::Bevel => "bevel",
+                };
+                let _ = write!(
+                    self.body,
+                    r#"<path d="{}" fill="none"{} stroke-width="{}" stroke-linecap="{}" stroke-linejoin="{}" stroke-miterlimit="{}"/>"#,
+                    path_data(path),
+                    paint("stroke", *color),
+                    stroke.width,
+                    cap,
+                    join,
+                    stroke.miter_limit
+                );
+            }
+            Command::PushClip(x, y, width, height) => {
+                self.clip_id += 1;
+                let _ = write!(
+                    self.body,
+                    r#"<clipPath id="clip{}"><rect x="{}" y="{}" width="{}" height="{}"/></clipPath>"#,
+                    self.clip_id, x, y, width, height
+                );
+                let attributes = format!(r#"clip-path="url(#clip{})""#, self.clip_id);
+                self.open_group(Group::Clip, &attributes);
+            }
+            Command::PopClip => self.close_group(Group::Clip),
+            Command::PushTransform(t) => {
+                // Nothing drawn through it would end up anywhere.
+                let attributes = if [t.a, t.b, t.c, t.d, t.e, t.f].iter().all(|v| v.is_finite()) {
+                    format!(
+                        r#"transform="matrix({} {} {} {} {} {})""#,
+                        t.a, t.b, t.c, t.d, t.e, t.f
+                    )
+                } else {
+                    r#"display="none""#.to_owned()
+                };
+                self.open_group(Group::Transform, &attributes);
+            }
+            Command::PopTransform => self.close_group(Group::Transform),
+            Command::PushOpacity(opacity) => {
+                let opacity = if opacity.is_nan() {
+                    0.0
+                } else {
+                    opacity.clamp(0.0, 1.0)
+                };
+                let attributes = format!(r#"opacity="{}""#, opacity);
+                self.open_group(Group::Opacity, &attributes);
+            }
+            Command::PopOpacity => self.close_group(Group::Opacity),
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        for _ in self.groups.drain(..) {
+            self.body.push_str("</g>");
+        }
+        self.body.push_str("</svg>\n");
+        std::mem::swap(&mut self.document, &mut self.body);
+
+        let frame = self.frame;
+        self.frame += 1;
+        if let Some(directory) = &self.directory {
+            fs::write(
+                directory.join(format!("frame-{:05}.svg", frame)),
+                &self.document,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// ` fill="#rrggbb"`, plus an opacity attribute if the color isn't opaque.
+fn paint(attribute: &str, color: Color) -> String {
+    let rgba = color.to_rgba().map(|c| if c.is_nan() { 0.0 } else { c });
+    let [r, g, b, a] = rgba.map(|c| (c * 255.0).round() as u8);
+    let mut out = format!(r##" {}="#{:02x}{:02x}{:02x}""##, attribute, r, g, b);
+    if a < 255 {
+        let _ = write!(out, r#" {}-opacity="{}""#, attribute, rgba[3]);
+    }
+    out
+}
+
+// NaN and infinities can't be written as SVG numbers.
+fn is_finite(path: &Path) -> bool {
+    path.segments().iter().all(|segment| match *segment {
+        PathSegment::MoveTo(x, y) | PathSegment::LineTo(x, y) => x.is_finite() && y.is_finite(),
+        PathSegment::QuadTo(x1, y1, x, y) => [x1, y1, x, y].iter().all(|v| v.is_finite()),
+        PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+            [x1, y1, x2, y2, x, y].iter().all(|v| v.is_finite())
+        }
+        PathSegment::Close => true,
+    })
+}
+
+fn path_data(path: &Path) -> String {
+    let mut out = String::new();
+    for segment in path.segments() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        let _ = match segment {
+            PathSegment::MoveTo(x, y) => write!(out, "M{} {}", x, y),
+            PathSegment::LineTo(x, y) => write!(out, "L{} {}", x, y),
+            PathSegment::QuadTo(x1, y1, x, y) => write!(out, "Q{} {} {} {}", x1, y1, x, y),
+            PathSegment::CubicTo(x1, y1, x2, y2, x, y) => {
+                write!(out, "C{} {} {} {} {} {}", x1, y1, x2, y2, x, y)
+            }
+            PathSegment::Close => write!(out, "Z"),
+        };
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{Stroke, Transform};
+
+    const HEADER: &str =
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20" viewBox="0 0 40 20">"#;
+
+    fn draw(surface: &mut SvgSurface, commands: &[Command]) -> String {
+        surface.begin().unwrap();
+        for command in commands {
+            surface
+                .command(command, AlignMode::CenterAligned, LayoutMode::NoCare)
+                .unwrap();
+        }
+        surface.end().unwrap();
+        surface.document().to_owned()
+    }
+
+    // The document without the enclosing <svg> element.
+    fn body(commands: &[Command]) -> String {
+        let document = draw(&mut SvgSurface::new(40, 20), commands);
+        document
+            .strip_prefix(HEADER)
+            .and_then(|d| d.strip_suffix("</svg>\n"))
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn writes_rectangles_with_their_radius() {
+        let red = Color::from_hex("#f00").unwrap();
+        assert_eq!(
+            body(&[
+                Command::FillRectangle(1, 2, 3, 4, 0.0, red),
+                Command::FillRectangle(1, 2, 3, 4, 1.5, Color::RGBA(0.0, 0.0, 1.0, 0.5)),
+            ]),
+            concat!(
+                r##"<rect x="1" y="2" width="3" height="4" fill="#ff0000"/>"##,
+                r##"<rect x="1" y="2" width="3" height="4" rx="1.5" fill="#0000ff" fill-opacity="0.5"/>"##,
+            )
+        );
+    }
+
+    #[test]
+    fn escapes_text() {
+        let text = Command::WriteString(0, 0, 40, 17, Color::BLACK, r#"<a & "b's">"#.into());
+        assert_eq!(
+            body(&[text]),
+            concat!(
+                r##"<text x="20" y="8.5" font-family="Yu gothic, sans-serif" font-size="10" "##,
+                r##"text-anchor="middle" dominant-baseline="central" fill="#000000">"##,
+                "&lt;a &amp; &quot;b&apos;s&quot;&gt;</text>"
+            )
+        );
+    }
+
+    #[test]
+    fn nests_groups() {
+        let mut path = Path::new();
+        path.move_to(0.0, 0.0);
+        path.line_to(4.0, 0.0);
+        path.close();
+        assert_eq!(
+            body(&[
+                Command::PushClip(0, 0, 10, 10),
+                Command::PushTransform(Transform::translate(2.0, 3.0)),
+                Command::PushClip(1, 1, 5, 5),
+                Command::PushOpacity(0.25),
+                Command::StrokePath(path, Stroke::new(2.0), Color::BLACK),
+                Command::PopOpacity,
+                Command::PopClip,
+                Command::PopTransform,
+                // Left open, and closed at the end of the frame.
+                Command::PushOpacity(2.0),
+            ]),
+            concat!(
+                r#"<clipPath id="clip1"><rect x="0" y="0" width="10" height="10"/></clipPath>"#,
+                r#"<g clip-path="url(#clip1)">"#,
+                r#"<g transform="matrix(1 0 0 1 2 3)">"#,
+                r#"<clipPath id="clip2"><rect x="1" y="1" width="5" height="5"/></clipPath>"#,
+                r#"<g clip-path="url(#clip2)">"#,
+                r#"<g opacity="0.25">"#,
+                r##"<path d="M0 0 L4 0 Z" fill="none" stroke="#000000" stroke-width="2" "##,
+                r#"stroke-linecap="butt" stroke-linejoin="miter" stroke-miterlimit="4"/>"#,
+                "</g></g></g>",
+                r#"<g opacity="1"></g></g>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn writes_a_document_per_frame() {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let directory = std::env::temp_dir().join(format!(
+            "acure-svg-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut surface = SvgSurface::to_directory(40, 20, &directory);
+
+        let first = draw(
+            &mut surface,
+            &[Command::FillRectangle(0, 0, 1, 1, 0.0, Color::BLACK)],
+        );
+        surface.begin().unwrap();
+        surface.clear(Color::WHITE);
+        surface.end().unwrap();
+        let second = surface.document().to_owned();
+
+        assert_eq!(
+            second,
+            format!(
+                r##"{}<rect width="100%" height="100%" fill="#ffffff"/></svg>{}"##,
+                HEADER, "\n"
+            )
+        );
+        let read = |name| std::fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(read("frame-00000.svg"), first);
+        assert_eq!(read("frame-00001.svg"), second);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn leaves_out_non_finite_numbers() {
+        let mut path = Path::new();
+        path.move_to(0.0, 0.0);
+        path.line_to(f32::NAN, 1.0);
+        let document = body(&[
+            Command::FillRectangle(
+                0,
+                0,
+                1,
+                1,
+                f64::INFINITY,
+                Color::RGBA(0.0, 0.0, 0.0, f32::NAN),
+            ),
+            Command::FillPath(path.clone(), Color::BLACK),
+            Command::StrokePath(path, Stroke::new(1.0), Color::BLACK),
+            Command::StrokePath(Path::new(), Stroke::new(f32::INFINITY), Color::BLACK),
+            Command::PushTransform(Transform::scale(f32::INFINITY, 1.0)),
+            Command::PopTransform,
+            Command::PushOpacity(f32::NAN),
+            Command::PopOpacity,
+        ]);
+        assert_eq!(
+            document,
+            concat!(
+                r##"<rect x="0" y="0" width="1" height="1" fill="#000000" fill-opacity="0"/>"##,
+                r#"<g display="none"></g><g opacity="0"></g>"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,774 @@
+use std::fs;
+
+use roxmltree::Node;
+
+use crate::{
+    path::{LineCap, LineJoin},
+    AcureError, AeResult, Color, Command, FillRule, Path, Rect, Stroke, Transform,
+};
+
+// A parsed SVG icon: paths, basic shapes and groups, with their fill, stroke,
+// opacity and transforms. Gradients, text, `use`, clip paths and masks are
+// skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgDocument {
+    width: f32,
+    height: f32,
+    view_box: Rect,
+    // In view box coordinates.
+    commands: Vec<Command>,
+}
+
+impl SvgDocument {
+    pub fn parse(text: &str) -> AeResult<Self> {
+        let document =
+            roxmltree::Document::parse(text).map_err(|e| AcureError::InvalidSvg(e.to_string()))?;
+        let root = document.root_element();
+        if root.tag_name().name() != "svg" {
+            return Err(AcureError::InvalidSvg(format!(
+                "root element is <{}>",
+                root.tag_name().name()
+            )));
+        }
+
+        let view_box = match root.attribute("viewBox") {
+            Some(v) => {
+                let v = numbers(v);
+                if v.len() != 4 || v[2] <= 0.0 || v[3] <= 0.0 {
+                    return Err(AcureError::InvalidSvg(format!("viewBox {:?}", v)));
+                }
+                Some(Rect::new(v[0], v[1], v[2], v[3]))
+            }
+            None => None,
+        };
+        let width = root.attribute("width").and_then(length);
+        let height = root.attribute("height").and_then(length);
+        let (width, height, view_box) = match (width, height, view_box) {
+            (w, h, Some(v)) => (w.unwrap_or(v.width), h.unwrap_or(v.height), v),
+            (Some(w), Some(h), None) => (w, h, Rect::new(0.0, 0.0, w, h)),
+            _ => {
+                return Err(AcureError::InvalidSvg(
+                    "no viewBox, width or height".to_owned(),
+                ))
+            }
+        };
+
+        let mut commands = vec![];
+        for child in root.children() {
+            element(child, &Style::default(), &mut commands);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            view_box,
+            commands,
+        })
+    }
+
+    #[inline]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> AeResult<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    #[inline]
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    #[inline]
+    pub fn view_box(&self) -> Rect {
+        self.view_box
+    }
+
+    #[inline]
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    // The commands scaled to fit in the rectangle, keeping the aspect ratio
+    // and centering (`xMidYMid meet`).
+    pub fn to_commands(&self, x: f32, y: f32, width: f32, height: f32) -> Vec<Command> {
+        let v = self.view_box;
+        let scale = (width / v.width).min(height / v.height);
+        let transform = Transform::translate(-v.x, -v.y)
+            .then(Transform::scale(scale, scale))
+            .then(Transform::translate(
+                x + (width - v.width * scale) / 2.0,
+                y + (height - v.height * scale) / 2.0,
+            ));
+
+        let mut commands = Vec::with_capacity(self.commands.len() + 2);
+        commands.push(Command::PushTransform(transform));
+        commands.extend(self.commands.iter().cloned());
+        commands.push(Command::PopTransform);
+        commands
+    }
+}
+
+// The inherited presentation properties.
+#[derive(Clone, Debug)]
+struct Style {
+    color: Color,
+    fill: Option<Color>,
+    fill_opacity: f32,
+    fill_rule: FillRule,
+    stroke: Option<Color>,
+    stroke_opacity: f32,
+    stroke_style: Stroke,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            color: Color::BLACK,
+            fill: Some(Color::BLACK),
+            fill_opacity: 1.0,
+            fill_rule: FillRule::NonZero,
+            stroke: None,
+            stroke_opacity: 1.0,
+            stroke_style: Stroke::new(1.0),
+        }
+    }
+}
+
+fn element(node: Node, parent: &Style, commands: &mut Vec<Command>) {
+    if !node.is_element() {
+        return;
+    }
+    let name = node.tag_name().name();
+    let declarations = node
+        .attribute("style")
+        .map(declarations)
+        .unwrap_or_default();
+    let property = |name: &str| {
+        declarations
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+            .or_else(|| node.attribute(name))
+            .map(str::trim)
+    };
+    if property("display") == Some("none") {
+        return;
+    }
+
+    let mut path = Path::new();
+    match name {
+        "g" | "svg" | "a" => {}
+        "path" => {
+            if let Some(d) = node.attribute("d") {
+                path = path_data(d);
+            }
+        }
+        "rect" => {
+            let (x, y) = (number(node, "x"), number(node, "y"));
+            let (width, height) = (number(node, "width"), number(node, "height"));
+            if width <= 0.0 || height <= 0.0 {
+                return;
+            }
+            let rx = node.attribute("rx").and_then(length);
+            let ry = node.attribute("ry").and_then(length);
+            let (rx, ry) = match (rx, ry) {
+                (Some(rx), Some(ry)) => (rx, ry),
+                (Some(r), None) | (None, Some(r)) => (r, r),
+                (None, None) => (0.0, 0.0),
+            };
+            path.rounded_rect(x, y, width, height, rx, ry);
+        }
+        "circle" => {
+            let r = number(node, "r");
+            if r <= 0.0 {
+                return;
+            }
+            path.ellipse(number(node, "cx"), number(node, "cy"), r, r);
+        }
+        "ellipse" => {
+            let (rx, ry) = (number(node, "rx"), number(node, "ry"));
+            if rx <= 0.0 || ry <= 0.0 {
+                return;
+            }
+            path.ellipse(number(node, "cx"), number(node, "cy"), rx, ry);
+        }
+        "line" => {
+            path.move_to(number(node, "x1"), number(node, "y1"));
+            path.line_to(number(node, "x2"), number(node, "y2"));
+        }
+        "polyline" | "polygon" => {
+            let points = node.attribute("points").map(numbers).unwrap_or_default();
+            for (i, point) in points.chunks_exact(2).enumerate() {
+                if i == 0 {
+                    path.move_to(point[0], point[1]);
+                } else {
+                    path.line_to(point[0], point[1]);
+                }
+            }
+            if name == "polygon" && !path.is_empty() {
+                path.close();
+            }
+        }
+        _ => {
+            debug!("Skipping <{}>", name);
+            return;
+        }
+    }
+
+    let mut style = parent.clone();
+    if let Some(color) = property("color").and_then(|c| paint(c, &style)) {
+        style.color = color;
+    }
+    if let Some(fill) = property("fill") {
+        style.fill = paint(fill, &style);
+    }
+    if let Some(stroke) = property("stroke") {
+        style.stroke = paint(stroke, &style);
+    }
+    if let Some(opacity) = property("fill-opacity").and_then(opacity) {
+        style.fill_opacity = opacity;
+    }
+    if let Some(opacity) = property("stroke-opacity").and_then(opacity) {
+        style.stroke_opacity = opacity;
+    }
+    match property("fill-rule") {
+        Some("nonzero") => style.fill_rule = FillRule::NonZero,
+        Some("evenodd") => style.fill_rule = FillRule::EvenOdd,
+        _ => {}
+    }
+    if let Some(width) = property("stroke-width").and_then(length) {
+        style.stroke_style.width = width;
+    }
+    match property("stroke-linecap") {
+        Some("butt") => style.stroke_style.cap = LineCap::Butt,
+        Some("round") => style.stroke_style.cap = LineCap::Round,
+        Some("square") => style.stroke_style.cap = LineCap::Square,
+        _ => {}
+    }
+    match property("stroke-linejoin") {
+        Some("miter") => style.stroke_style.join = LineJoin::Miter,
+        Some("round") => style.stroke_style.join = LineJoin::Round,
+        Some("bevel") => style.stroke_style.join = LineJoin::Bevel,
+        _ => {}
+    }
+    if let Some(limit) = property("stroke-miterlimit").and_then(|v| v.parse().ok()) {
+        style.stroke_style.miter_limit = limit;
+    }
+
+    let mut transform = node
+        .attribute("transform")
+        .map(transform)
+        .unwrap_or_default();
+    // Nested <svg> elements are placed like groups, ignoring their viewBox.
+    if name == "svg" {
+        transform = Transform::translate(number(node, "x"), number(node, "y")).then(transform);
+    }
+    let opacity = property("opacity").and_then(opacity).unwrap_or(1.0);
+    if opacity <= 0.0 {
+        return;
+    }
+
+    if !transform.is_identity() {
+        commands.push(Command::PushTransform(transform));
+    }
+    if opacity < 1.0 {
+        commands.push(Command::PushOpacity(opacity));
+    }
+
+    match name {
+        "g" | "svg" | "a" => {
+            for child in node.children() {
+                element(child, &style, commands);
+            }
+        }
+        _ if path.is_empty() => {}
+        _ => {
+            if let Some(fill) = style.fill.filter(|_| name != "line") {
+                path.set_fill_rule(style.fill_rule);
+                commands.push(Command::FillPath(
+                    path.clone(),
+                    multiply_alpha(fill, style.fill_opacity),
+                ));
+            }
+            if let Some(stroke) = style.stroke.filter(|_| style.stroke_style.width > 0.0) {
+                commands.push(Command::StrokePath(
+                    path,
+                    style.stroke_style,
+                    multiply_alpha(stroke, style.stroke_opacity),
+                ));
+            }
+        }
+    }
+
+    if opacity < 1.0 {
+        commands.push(Command::PopOpacity);
+    }
+    if !transform.is_identity() {
+        commands.push(Command::PopTransform);
+    }
+}
+
+// `name: value; name: value`
+fn declarations(style: &str) -> Vec<(&str, &str)> {
+    style
+        .split(';')
+        .filter_map(|d| d.split_once(':'))
+        .map(|(n, v)| (n.trim(), v.trim()))
+        .collect()
+}
+
+// None for "none", gradients and anything else that can't be parsed.
+fn paint(value: &str, style: &Style) -> Option<Color> {
+    match value {
+        "none" => None,
+        "currentColor" => Some(style.color),
+        _ if value.starts_with("url(") => {
+            // A fallback color may follow the reference.
+            let fallback = value.split_once(')').map(|(_, f)| f.trim())?;
+            if fallback.is_empty() {
+                warn!("Unsupported paint {}", value);
+                None
+            } else {
+                paint(fallback, style)
+            }
+        }
+        _ => match value.parse() {
+            Ok(color) => Some(color),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        },
+    }
+}
+
+#[inline]
+fn multiply_alpha(color: Color, opacity: f32) -> Color {
+    color.with_alpha(color.to_rgba()[3] * opacity)
+}
+
+#[inline]
+fn opacity(value: &str) -> Option<f32> {
+    let opacity = match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+        None => value.parse::<f32>().ok()?,
+    };
+    Some(opacity.clamp(0.0, 1.0))
+}
+
+#[inline]
+fn number(node: Node, name: &str) -> f32 {
+    node.attribute(name).and_then(length).unwrap_or(0.0)
+}
+
+// A length in pixels. Percentages aren't supported.
+fn length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E' || c == '%')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.trim().parse::<f32>().ok()?;
+    let scale = match unit {
+        "" | "px" => 1.0,
+        "pt" => 4.0 / 3.0,
+        "pc" => 16.0,
+        "mm" => 96.0 / 25.4,
+        "cm" => 96.0 / 2.54,
+        "in" => 96.0,
+        _ => return None,
+    };
+    Some(number * scale)
+}
+
+// A list of numbers separated by commas and/or whitespace.
+fn numbers(value: &str) -> Vec<f32> {
+    let mut parser = Parser::new(value);
+    let mut out = vec![];
+    while let Some(n) = parser.number() {
+        out.push(n);
+    }
+    out
+}
+
+// A transform list, e.g. `translate(10 20) rotate(45)`.
+fn transform(value: &str) -> Transform {
+    let mut result = Transform::IDENTITY;
+    for item in value.split_inclusive(')') {
+        let Some((name, args)) = item.trim_end_matches(')').split_once('(') else {
+            continue;
+        };
+        let args = numbers(args);
+        let arg = |i: usize| args.get(i).copied().unwrap_or(0.0);
+        let t = match (
+            name.trim_matches(|c: char| c == ',' || c.is_whitespace()),
+            args.len(),
+        ) {
+            ("matrix", 6) => Transform::new(arg(0), arg(1), arg(2), arg(3), arg(4), arg(5)),
+            ("translate", 1 | 2) => Transform::translate(arg(0), arg(1)),
+            ("scale", 1) => Transform::scale(arg(0), arg(0)),
+            ("scale", 2) => Transform::scale(arg(0), arg(1)),
+            ("rotate", 1) => Transform::rotate(arg(0)),
+            ("rotate", 3) => Transform::translate(-arg(1), -arg(2))
+                .then(Transform::rotate(arg(0)))
+                .then(Transform::translate(arg(1), arg(2))),
+            ("skewX", 1) => Transform::new(1.0, 0.0, arg(0).to_radians().tan(), 1.0, 0.0, 0.0),
+            ("skewY", 1) => Transform::new(1.0, arg(0).to_radians().tan(), 0.0, 1.0, 0.0, 0.0),
+            _ => {
+                warn!("Invalid transform {}", item.trim());
+                return Transform::IDENTITY;
+            }
+        };
+        // The rightmost transform applies first.
+        result = t.then(result);
+    }
+    result
+}
+
+// SVG path data. Like browsers, everything up to the first error is kept.
+fn path_data(d: &str) -> Path {
+    let mut path = Path::new();
+    let mut parser = Parser::new(d);
+    let mut command = None;
+    // The current point, the start of the subpath and the last control point
+    // of a cubic or quadratic, for the smooth variants.
+    let (mut x, mut y) = (0.0, 0.0);
+    let (mut start_x, mut start_y) = (0.0, 0.0);
+    let mut control: Option<(char, f32, f32)> = None;
+
+    loop {
+        parser.skip_separators();
+        let Some(next) = parser.peek() else {
+            break;
+        };
+        let c = if next.is_ascii_alphabetic() {
+            parser.pos += 1;
+            next as char
+        } else {
+            // Repeated commands can leave out the letter; after a moveto the
+            // implicit command is a lineto.
+            match command {
+                Some('M') => 'L',
+                Some('m') => 'l',
+                Some(c) if c != 'Z' && c != 'z' => c,
+                _ => {
+                    warn!("Invalid path data at {}: {}", parser.pos, d);
+                    break;
+                }
+            }
+        };
+        let relative = c.is_ascii_lowercase();
+        let (dx, dy) = if relative { (x, y) } else { (0.0, 0.0) };
+
+        let ok = match c.to_ascii_uppercase() {
+            'M' => parser.pair().map(|(px, py)| {
+                (x, y) = (px + dx, py + dy);
+                (start_x, start_y) = (x, y);
+                path.move_to(x, y);
+                None
+            }),
+            'L' => parser.pair().map(|(px, py)| {
+                (x, y) = (px + dx, py + dy);
+                path.line_to(x, y);
+                None
+            }),
+            'H' => parser.number().map(|px| {
+                x = px + dx;
+                path.line_to(x, y);
+                None
+            }),
+            'V' => parser.number().map(|py| {
+                y = py + dy;
+                path.line_to(x, y);
+                None
+            }),
+            'C' | 'S' => {
+                let first = if c.eq_ignore_ascii_case(&'C') {
+                    parser.pair().map(|(px, py)| (px + dx, py + dy))
+                } else {
+                    Some(reflect(control, 'C', x, y))
+                };
+                match (first, parser.pair(), parser.pair()) {
+                    (Some((x1, y1)), Some((x2, y2)), Some((px, py))) => {
+                        let (x2, y2) = (x2 + dx, y2 + dy);
+                        (x, y) = (px + dx, py + dy);
+                        path.cubic_to(x1, y1, x2, y2, x, y);
+                        Some(Some(('C', x2, y2)))
+                    }
+                    _ => None,
+                }
+            }
+            'Q' | 'T' => {
+                let first = if c.eq_ignore_ascii_case(&'Q') {
+                    parser.pair().map(|(px, py)| (px + dx, py + dy))
+                } else {
+                    Some(reflect(control, 'Q', x, y))
+                };
+                match (first, parser.pair()) {
+                    (Some((x1, y1)), Some((px, py))) => {
+                        (x, y) = (px + dx, py + dy);
+                        path.quad_to(x1, y1, x, y);
+                        Some(Some(('Q', x1, y1)))
+                    }
+                    _ => None,
+                }
+            }
+            'A' => {
+                let radii = parser.pair();
+                let rotation = parser.number();
+                let large_arc = parser.flag();
+                let sweep = parser.flag();
+                match (radii, rotation, large_arc, sweep, parser.pair()) {
+                    (
+                        Some((rx, ry)),
+                        Some(rotation),
+                        Some(large_arc),
+                        Some(sweep),
+                        Some((px, py)),
+                    ) => {
+                        (x, y) = (px + dx, py + dy);
+                        path.arc_to(rx, ry, rotation, large_arc, sweep, x, y);
+                        Some(None)
+                    }
+                    _ => None,
+                }
+            }
+            'Z' => {
+                path.close();
+                (x, y) = (start_x, start_y);
+                Some(None)
+            }
+            _ => None,
+        };
+
+        match ok {
+            Some(c) => control = c,
+            None => {
+                warn!("Invalid path data at {}: {}", parser.pos, d);
+                break;
+            }
+        }
+        command = Some(c);
+    }
+    path
+}
+
+// The first control point of a smooth curve: the reflection of the previous
+// curve's last control point, or the current point if it wasn't the same kind.
+#[inline]
+fn reflect(control: Option<(char, f32, f32)>, kind: char, x: f32, y: f32) -> (f32, f32) {
+    match control {
+        Some((k, cx, cy)) if k == kind => (2.0 * x - cx, 2.0 * y - cy),
+        _ => (x, y),
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    #[inline]
+    fn new(s: &'a str) -> Self {
+        Self {
+            s: s.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    #[inline]
+    fn skip_separators(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n' | b',') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    // Numbers don't need separators where it's unambiguous: "1-2", "1.5.5".
+    fn number(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let start = self.pos;
+        if let Some(b'+' | b'-') = self.peek() {
+            self.pos += 1;
+        }
+        let mut digits = self.digits();
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            digits += self.digits();
+        }
+        if digits == 0 {
+            self.pos = start;
+            return None;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            let mark = self.pos;
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                self.pos = mark;
+            }
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    #[inline]
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    #[inline]
+    fn pair(&mut self) -> Option<(f32, f32)> {
+        Some((self.number()?, self.number()?))
+    }
+
+    // Arc flags are a single digit, so "a1 1 0 00 10 10" is valid.
+    #[inline]
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.peek()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(flag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PathSegment;
+
+    fn segments(d: &str) -> Vec<PathSegment> {
+        path_data(d).segments().to_vec()
+    }
+
+    #[test]
+    fn parses_path_data() {
+        use PathSegment::*;
+        assert_eq!(
+            segments("M1 2L3 4 5 6h1v-1zm1,1 2-2"),
+            [
+                MoveTo(1.0, 2.0),
+                LineTo(3.0, 4.0),
+                LineTo(5.0, 6.0),
+                LineTo(6.0, 6.0),
+                LineTo(6.0, 5.0),
+                Close,
+                MoveTo(2.0, 3.0),
+                LineTo(4.0, 1.0),
+            ]
+        );
+        assert_eq!(
+            segments("M0 0C1 1 2 1 3 0S5-1 6 0Q7 1 8 0T10 0"),
+            [
+                MoveTo(0.0, 0.0),
+                CubicTo(1.0, 1.0, 2.0, 1.0, 3.0, 0.0),
+                CubicTo(4.0, -1.0, 5.0, -1.0, 6.0, 0.0),
+                QuadTo(7.0, 1.0, 8.0, 0.0),
+                QuadTo(9.0, -1.0, 10.0, 0.0),
+            ]
+        );
+        // Everything before an error is kept.
+        assert_eq!(
+            segments("M1.5.5L2 2L3"),
+            [MoveTo(1.5, 0.5), LineTo(2.0, 2.0)]
+        );
+        // Arc flags don't need separators.
+        let arc = segments("M0 0a1 1 0 00 10 10");
+        assert!(arc.len() > 1);
+        assert!(matches!(arc.last(), Some(CubicTo(.., x, y)) if (*x, *y) == (10.0, 10.0)));
+    }
+
+    #[test]
+    fn parses_transforms_and_lengths() {
+        let t = transform("translate(10 20) scale(2)");
+        assert_eq!(t.apply(1.0, 1.0), (12.0, 22.0));
+        let t = transform("rotate(90 5 5)");
+        let (x, y) = t.apply(10.0, 5.0);
+        assert!((x - 5.0).abs() < 1e-4 && (y - 10.0).abs() < 1e-4);
+        assert!(transform("frobnicate(1)").is_identity());
+        assert_eq!(length("2in"), Some(192.0));
+        assert_eq!(length("1e1px"), Some(10.0));
+        assert_eq!(length("50%"), None);
+    }
+
+    #[test]
+    fn imports_shapes_with_their_style() {
+        let svg = SvgDocument::parse(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 10" width="40">
+                <g transform="translate(1 2)" opacity="0.5" color="#00ff00">
+                    <rect width="4" height="3" fill="currentColor"/>
+                    <circle cx="5" cy="5" r="2" style="fill: none; stroke: red; stroke-width: 2"/>
+                </g>
+                <rect width="0" height="3"/>
+                <text>skipped</text>
+                <path d="M0 0H1" display="none"/>
+            </svg>"##,
+        )
+        .unwrap();
+        assert_eq!((svg.width(), svg.height()), (40.0, 10.0));
+        assert_eq!(svg.view_box(), Rect::new(0.0, 0.0, 20.0, 10.0));
+
+        let commands = svg.commands();
+        assert_eq!(commands.len(), 6);
+        assert_eq!(
+            commands[0],
+            Command::PushTransform(Transform::translate(1.0, 2.0))
+        );
+        assert_eq!(commands[1], Command::PushOpacity(0.5));
+        assert!(matches!(
+            &commands[2],
+            Command::FillPath(_, color) if color.to_argb() == [255, 0, 255, 0]
+        ));
+        assert!(matches!(
+            &commands[3],
+            Command::StrokePath(_, stroke, color)
+                if stroke.width == 2.0 && color.to_argb() == [255, 255, 0, 0]
+        ));
+        assert_eq!(commands[4], Command::PopOpacity);
+        assert_eq!(commands[5], Command::PopTransform);
+    }
+
+    #[test]
+    fn fits_the_view_box() {
+        let svg = SvgDocument::parse(r#"<svg viewBox="10 10 20 10"/>"#).unwrap();
+        let commands = svg.to_commands(0.0, 0.0, 100.0, 100.0);
+        let Command::PushTransform(t) = commands[0] else {
+            panic!("{:?}", commands[0]);
+        };
+        // Scaled by 5 and centered vertically.
+        assert_eq!(t.apply(10.0, 10.0), (0.0, 25.0));
+        assert_eq!(t.apply(30.0, 20.0), (100.0, 75.0));
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for text in ["<html/>", "<svg/>", r#"<svg viewBox="0 0 0 10"/>"#, "<svg"] {
+            assert!(
+                matches!(SvgDocument::parse(text), Err(AcureError::InvalidSvg(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{fs::File, io::BufWriter};
+
+use crate::{record::Frame, surface::Surface, Acure, AcureError, AeResult, Image, Rect};
+
+// Set to replace reference images with what was rendered instead of
+// comparing against them.
+pub const BLESS_VAR: &str = "ACURE_BLESS";
+
+// How far a rendering may stray from its reference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tolerance {
+    // Largest difference allowed in any channel of a pixel.
+    pub channel: u8,
+    // How many pixels may differ by more than that.
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance::new(0, 0);
+
+    #[inline]
+    pub const fn new(channel: u8, pixels: usize) -> Self {
+        Self { channel, pixels }
+    }
+}
+
+pub struct Comparison {
+    // Pixels that differ by more than the tolerance allows.
+    pub pixels: usize,
+    // The largest difference in any channel.
+    pub max_channel: u8,
+    // The reference dimmed, with the differing pixels in red.
+    pub diff: Image,
+}
+
+impl Comparison {
+    #[inline]
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.pixels <= tolerance.pixels
+    }
+}
+
+// Draws `frame` at `width` x `height` and reads it back.
+pub fn render<S: Surface + ?Sized>(
+    surface: &mut S,
+    frame: &Frame,
+    width: u32,
+    height: u32,
+) -> AeResult<Image> {
+    surface.surface_resize(width, height);
+    let mut acure = Acure::new();
+    acure.set_damage_tracking(false);
+    acure.replay(frame, surface)?;
+    surface.read_pixels(Rect::from_u32(0, 0, width, height))
+}
+
+// Pixels outside either image, when their sizes differ, count as differing.
+pub fn compare(actual: &Image, expected: &Image, tolerance: &Tolerance) -> Comparison {
+    let width = actual.width().max(expected.width());
+    let height = actual.height().max(expected.height());
+    let mut pixels = 0;
+    let mut max_channel = 0;
+    let mut diff = Vec::with_capacity(width as usize * height as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            let (a, e) = match (actual.pixel(x, y), expected.pixel(x, y)) {
+                (Some(a), Some(e)) => (a, e),
+                _ => {
+                    pixels += 1;
+                    max_channel = 255;
+                    diff.push(0xffff0000);
+                    continue;
+                }
+            };
+            let difference = a
+                .to_be_bytes()
+                .iter()
+                .zip(e.to_be_bytes())
+                .map(|(a, e)| a.abs_diff(e))
+                .max()
+                .unwrap_or(0);
+            max_channel = max_channel.max(difference);
+
+            if difference > tolerance.channel {
+                pixels += 1;
+                diff.push(0xff000000 | (128 + difference as u32 / 2) << 16);
+            } else {
+                let [_, r, g, b] = e.to_be_bytes();
+                let luma = (r as u32 * 3 + g as u32 * 6 + b as u32) / 30;
+                diff.push(0xff000000 | luma << 16 | luma << 8 | luma);
+            }
+        }
+    }
+
+    Comparison {
+        pixels,
+        max_channel,
+        diff: Image::new(width, height, diff),
+    }
+}
+
+// Compares `actual` with the PNG at `reference`. On failure the rendering
+// and the diff are written next to it as <name>.actual.png and
+// <name>.diff.png.
+pub fn check<P: AsRef<std::path::Path>>(
+    actual: &Image,
+    reference: P,
+    tolerance: &Tolerance,
+) -> AeResult<()> {
+    let reference = reference.as_ref();
+    if std::env::var_os(BLESS_VAR).is_some() {
+        return write_png(actual, reference);
+    }
+
+    let actual_path = reference.with_extension("actual.png");
+    if !reference.exists() {
+        write_png(actual, &actual_path)?;
+        return Err(AcureError::ImageMismatch(format!(
+            "No reference at {}, wrote {}",
+            reference.display(),
+            actual_path.display()
+        )));
+    }
+
+    let comparison = compare(actual, &read_png(reference)?, tolerance);
+    if comparison.passes(tolerance) {
+        return Ok(());
+    }
+    let diff_path = reference.with_extension("diff.png");
+    write_png(actual, &actual_path)?;
+    write_png(&comparison.diff, &diff_path)?;
+    Err(AcureError::ImageMismatch(format!(
+        "{} pixels of {} differ by up to {}, see {}",
+        comparison.pixels,
+        reference.display(),
+        comparison.max_channel,
+        diff_path.display()
+    )))
+}
+
+pub fn read_png<P: AsRef<std::path::Path>>(path: P) -> AeResult<Image> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(std::io::Error::from)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(std::io::Error::from)?;
+    let data = &data[..info.buffer_size()];
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => data.to_vec(),
+        png::ColorType::Rgb => data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        // Expanded to RGB by the decoder.
+        png::ColorType::Indexed => unreachable!(),
+    };
+    Ok(Image::from_rgba8(info.width, info.height, &rgba))
+}
+
+pub fn write_png<P: AsRef<std::path::Path>>(image: &Image, path: P) -> AeResult<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(std::io::Error::from)?;
+    writer
+        .write_image_data(&image.to_rgba8())
+        .map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{software::SoftwareSurface, Color, Command};
+
+    const RED: u32 = 0xffff0000;
+    const WHITE: u32 = 0xffffffff;
+
+    fn temp_dir() -> std::path::PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "acure-testing-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn renders_a_frame() {
+        let mut acure = Acure::new();
+        acure.set_background_color(Color::WHITE);
+        acure.push(Command::FillRectangle(
+            0,
+            0,
+            2,
+            4,
+            0.0,
+            Color::from_hex("#f00").unwrap(),
+        ));
+        let mut surface = SoftwareSurface::new(1, 1);
+        let image = render(&mut surface, &acure.frame(), 4, 4).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 4));
+        assert_eq!(image.pixels()[..4], [RED, RED, WHITE, WHITE]);
+    }
+
+    #[test]
+    fn counts_pixels_outside_the_tolerance() {
+        let expected = Image::new(2, 1, vec![WHITE, RED]);
+        let actual = Image::new(2, 1, vec![0xfff8ffff, 0xffff0010]);
+
+        let exact = compare(&actual, &expected, &Tolerance::EXACT);
+        assert_eq!((exact.pixels, exact.max_channel), (2, 16));
+        assert!(!exact.passes(&Tolerance::EXACT));
+        assert!(exact.passes(&Tolerance::new(0, 2)));
+
+        let loose = compare(&actual, &expected, &Tolerance::new(8, 0));
+        assert_eq!(loose.pixels, 1);
+        // Matching pixels are shown dimmed, the others in red.
+        assert_eq!(loose.diff.pixels(), [0xff555555, 0xff880000]);
+        assert!(compare(&expected, &expected, &Tolerance::EXACT).passes(&Tolerance::EXACT));
+    }
+
+    #[test]
+    fn counts_pixels_outside_either_image() {
+        let small = Image::new(1, 1, vec![WHITE]);
+        let large = Image::new(2, 2, vec![WHITE; 4]);
+        let comparison = compare(&small, &large, &Tolerance::new(255, 0));
+        assert_eq!((comparison.pixels, comparison.max_channel), (3, 255));
+        assert_eq!((comparison.diff.width(), comparison.diff.height()), (2, 2));
+        assert_eq!(comparison.diff.pixel(1, 1), Some(RED));
+    }
+
+    #[test]
+    fn round_trips_png() {
+        let dir = temp_dir();
+        let path = dir.join("image.png");
+        let image = Image::new(2, 2, vec![WHITE, RED, 0x80800000, 0]);
+        write_png(&image, &path).unwrap();
+        assert_eq!(read_png(&path).unwrap(), image);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_the_rendering_and_diff_on_mismatch() {
+        let dir = temp_dir();
+        let reference = dir.join("frame.png");
+        let actual = Image::new(1, 1, vec![RED]);
+
+        // Without a reference the rendering is kept to bless.
+        assert!(matches!(
+            check(&actual, &reference, &Tolerance::EXACT),
+            Err(AcureError::ImageMismatch(_))
+        ));
+        assert_eq!(read_png(dir.join("frame.actual.png")).unwrap(), actual);
+        std::fs::remove_file(dir.join("frame.actual.png")).unwrap();
+
+        write_png(&actual, &reference).unwrap();
+        check(&actual, &reference, &Tolerance::EXACT).unwrap();
+        assert!(!dir.join("frame.diff.png").exists());
+
+        let changed = Image::new(1, 1, vec![WHITE]);
+        assert!(matches!(
+            check(&changed, &reference, &Tolerance::EXACT),
+            Err(AcureError::ImageMismatch(_))
+        ));
+        assert_eq!(read_png(dir.join("frame.actual.png")).unwrap(), changed);
+        assert!(dir.join("frame.diff.png").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
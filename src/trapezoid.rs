@@ -0,0 +1,241 @@
+use crate::FillRule;
+
+// From the upper point to the lower one.
+pub(crate) type Line = ((f32, f32), (f32, f32));
+
+// The part of a band between two horizontal lines that lies between two
+// polygon edges, which is what the RENDER extension fills.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Trapezoid {
+    pub top: f32,
+    pub bottom: f32,
+    pub left: Line,
+    pub right: Line,
+}
+
+// RENDER's 16.16 fixed point.
+#[inline]
+pub(crate) fn fixed(v: f32) -> i32 {
+    (v * 65536.0).round() as i32
+}
+
+#[derive(Clone, Copy)]
+struct Edge {
+    top: (f32, f32),
+    bottom: (f32, f32),
+    winding: i32,
+}
+
+impl Edge {
+    #[inline]
+    fn x_at(&self, y: f32) -> f32 {
+        let (x0, y0) = self.top;
+        let (x1, y1) = self.bottom;
+        x0 + (x1 - x0) * (y - y0) / (y1 - y0)
+    }
+}
+
+// Cuts the polygons into bands at every vertex and every crossing of two
+// edges, so no edge starts, ends or changes places inside one, and pairs up
+// the edges crossing each band by the fill rule.
+pub(crate) fn trapezoids(contours: &[Vec<(f32, f32)>], rule: FillRule) -> Vec<Trapezoid> {
+    let mut edges = vec![];
+    for contour in contours {
+        for (i, p0) in contour.iter().enumerate() {
+            let p1 = contour[(i + 1) % contour.len()];
+            if p0.1 < p1.1 {
+                edges.push(Edge {
+                    top: *p0,
+                    bottom: p1,
+                    winding: 1,
+                });
+            } else if p1.1 < p0.1 {
+                edges.push(Edge {
+                    top: p1,
+                    bottom: *p0,
+                    winding: -1,
+                });
+            }
+        }
+    }
+    let mut ys = edges
+        .iter()
+        .flat_map(|e| [e.top.1, e.bottom.1])
+        .collect::<Vec<_>>();
+    ys.sort_by(f32::total_cmp);
+    ys.dedup();
+
+    let inside = |winding: i32| match rule {
+        FillRule::NonZero => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+    };
+    let mut traps = vec![];
+    for band in ys.windows(2) {
+        let crossing = edges
+            .iter()
+            .filter(|e| e.top.1 <= band[0] && e.bottom.1 >= band[1])
+            .collect::<Vec<_>>();
+
+        // Edges that cross swap places there, so the band is cut again
+        // wherever two of them meet.
+        let mut cuts = vec![band[0], band[1]];
+        for (i, a) in crossing.iter().enumerate() {
+            for b in &crossing[i + 1..] {
+                let d0 = a.x_at(band[0]) - b.x_at(band[0]);
+                let d1 = a.x_at(band[1]) - b.x_at(band[1]);
+                if d0 * d1 < 0.0 {
+                    cuts.push(band[0] + (band[1] - band[0]) * d0 / (d0 - d1));
+                }
+            }
+        }
+        cuts.sort_by(f32::total_cmp);
+        cuts.dedup();
+
+        for cut in cuts.windows(2) {
+            let (top, bottom) = (cut[0], cut[1]);
+            if bottom <= top {
+                continue;
+            }
+            let middle = (top + bottom) / 2.0;
+            let mut crossing = crossing
+                .iter()
+                .map(|e| (e.x_at(middle), *e))
+                .collect::<Vec<_>>();
+            crossing.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            let mut left = None;
+            for (_, edge) in crossing {
+                let was_inside = inside(winding);
+                winding += edge.winding;
+                match (was_inside, inside(winding)) {
+                    (false, true) => left = Some(edge),
+                    (true, false) => {
+                        if let Some(left) = left.take() {
+                            traps.push(Trapezoid {
+                                top,
+                                bottom,
+                                left: (left.top, left.bottom),
+                                right: (edge.top, edge.bottom),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    traps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn x_at(line: Line, y: f32) -> f32 {
+        let ((x0, y0), (x1, y1)) = line;
+        x0 + (x1 - x0) * (y - y0) / (y1 - y0)
+    }
+
+    fn area(traps: &[Trapezoid]) -> f32 {
+        traps
+            .iter()
+            .map(|t| {
+                let top = x_at(t.right, t.top) - x_at(t.left, t.top);
+                let bottom = x_at(t.right, t.bottom) - x_at(t.left, t.bottom);
+                (top + bottom) / 2.0 * (t.bottom - t.top)
+            })
+            .sum()
+    }
+
+    fn square(x: f32, y: f32, size: f32) -> Vec<(f32, f32)> {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+
+    #[test]
+    fn fills_a_square_with_one_trapezoid() {
+        let traps = trapezoids(&[square(1.0, 2.0, 4.0)], FillRule::NonZero);
+        assert_eq!(traps.len(), 1);
+        let trap = traps[0];
+        assert_eq!((trap.top, trap.bottom), (2.0, 6.0));
+        assert_eq!(trap.left, ((1.0, 2.0), (1.0, 6.0)));
+        assert_eq!(trap.right, ((5.0, 2.0), (5.0, 6.0)));
+    }
+
+    #[test]
+    fn cuts_bands_at_every_vertex() {
+        let triangle = vec![(5.0, 0.0), (10.0, 10.0), (0.0, 4.0)];
+        let traps = trapezoids(&[triangle], FillRule::NonZero);
+        assert_eq!(traps.len(), 2);
+        assert_eq!((traps[0].top, traps[0].bottom), (0.0, 4.0));
+        assert_eq!((traps[1].top, traps[1].bottom), (4.0, 10.0));
+        // Half of 5*10 + 10*4 - 5*4 by the shoelace formula.
+        assert!((area(&traps) - 35.0).abs() < 1e-4);
+
+        // A U shape needs two trapezoids side by side in its lower band.
+        let u = vec![
+            (0.0, 0.0),
+            (3.0, 0.0),
+            (3.0, 5.0),
+            (7.0, 5.0),
+            (7.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+        ];
+        let traps = trapezoids(&[u], FillRule::EvenOdd);
+        assert_eq!(traps.iter().filter(|t| t.top == 0.0).count(), 2);
+        assert!((area(&traps) - 80.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn follows_the_fill_rule() {
+        // Both wound the same way, so the inner square is a hole only by
+        // the even-odd rule.
+        let contours = [square(0.0, 0.0, 10.0), square(3.0, 3.0, 4.0)];
+        let nonzero = trapezoids(&contours, FillRule::NonZero);
+        assert!((area(&nonzero) - 100.0).abs() < 1e-4);
+        let evenodd = trapezoids(&contours, FillRule::EvenOdd);
+        assert!((area(&evenodd) - 84.0).abs() < 1e-4);
+
+        // Wound the other way it's a hole either way.
+        let mut reversed = square(3.0, 3.0, 4.0);
+        reversed.reverse();
+        let contours = [square(0.0, 0.0, 10.0), reversed];
+        assert!((area(&trapezoids(&contours, FillRule::NonZero)) - 84.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cuts_bands_where_edges_cross() {
+        // Two triangles meeting at (5, 5), wound opposite ways.
+        let bowtie = [vec![(0.0, 0.0), (10.0, 10.0), (10.0, 0.0), (0.0, 10.0)]];
+        for rule in [FillRule::NonZero, FillRule::EvenOdd] {
+            let traps = trapezoids(&bowtie, rule);
+            assert!(traps.iter().all(|t| t.bottom <= 5.0 || t.top >= 5.0));
+            assert!((area(&traps) - 50.0).abs() < 1e-4);
+        }
+
+        // Two strokes of an X, as stroke outlines overlap. Each is 20, and
+        // they share a diamond of 2.
+        let strokes = [
+            vec![(0.0, 0.0), (2.0, 0.0), (12.0, 10.0), (10.0, 10.0)],
+            vec![(10.0, 0.0), (12.0, 0.0), (2.0, 10.0), (0.0, 10.0)],
+        ];
+        assert!((area(&trapezoids(&strokes, FillRule::NonZero)) - 38.0).abs() < 1e-4);
+        assert!((area(&trapezoids(&strokes, FillRule::EvenOdd)) - 36.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn skips_flat_polygons() {
+        let line = vec![(0.0, 1.0), (5.0, 1.0), (9.0, 1.0)];
+        assert!(trapezoids(&[line], FillRule::NonZero).is_empty());
+        assert!(trapezoids(&[], FillRule::NonZero).is_empty());
+    }
+
+    #[test]
+    fn converts_to_fixed_point() {
+        assert_eq!(fixed(1.0), 0x10000);
+        assert_eq!(fixed(-0.5), -0x8000);
+        assert_eq!(fixed(2.25), 0x24000);
+    }
+}
@@ -0,0 +1,712 @@
+const FRAGMENT: &str = include_str!("shader/shader.frag");
+const VERTEX: &str = include_str!("shader/shader.vert");
+
+use std::{
+    collections::VecDeque,
+    ffi::c_void,
+    fs::File,
+    os::fd::AsFd,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use memmap2::MmapMut;
+use rustix::{
+    event::{poll, PollFd, PollFlags, Timespec},
+    fs::{memfd_create, MemfdFlags},
+};
+use wayland_client::{
+    backend::{Backend as WaylandBackend, ObjectId},
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_callback::{self, WlCallback},
+        wl_registry::WlRegistry,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
+};
+use wayland_sys::egl::{wayland_egl_option, wl_egl_window, WaylandEgl};
+
+use crate::{
+    egl::{egl, Egl, MAX_BUFFER_AGE},
+    gl::{
+        capabilities, clear, compile_shader, create_program, enable_blending, max_viewport_size,
+        read_pixels, set_scissor, Batch,
+    },
+    software::SoftwareSurface,
+    surface::{Capabilities, DrawState, Surface},
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, Image, LayoutMode, Rect,
+};
+
+// The compositor stops sending frame callbacks while the window is hidden, so
+// frames are drawn anyway after this long.
+const FRAME_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Wraps the application's display and surface in our own connection object,
+// without taking them over.
+unsafe fn connect(
+    backend: Backend,
+    display: NonNull<c_void>,
+    surface: NonNull<c_void>,
+) -> AeResult<(Connection, WlSurface)> {
+    if !wayland_sys::client::is_lib_available() {
+        return Err(AcureError::ContextCreation(
+            backend,
+            anyhow::anyhow!("Can't load libwayland-client"),
+        ));
+    }
+    let error = |e| AcureError::BackendError(backend, anyhow::Error::new(e));
+    let connection = Connection::from_backend(unsafe {
+        WaylandBackend::from_foreign_display(display.as_ptr().cast())
+    });
+    let id = unsafe { ObjectId::from_ptr(WlSurface::interface(), surface.as_ptr().cast()) }
+        .map_err(error)?;
+    let surface = WlSurface::from_id(&connection, id).map_err(error)?;
+    Ok((connection, surface))
+}
+
+// Buffers have to be a whole number of surface units.
+#[inline]
+fn buffer_size(width: u32, height: u32, scale: u32) -> (u32, u32) {
+    let round = |v: u32| v.max(1).div_ceil(scale) * scale;
+    (round(width), round(height))
+}
+
+// wl_surface.set_buffer_scale is from version 3.
+#[inline]
+fn set_buffer_scale(surface: &WlSurface, scale: u32) -> bool {
+    if surface.version() < 3 {
+        return false;
+    }
+    surface.set_buffer_scale(scale as i32);
+    true
+}
+
+struct EglWindow {
+    library: &'static WaylandEgl,
+    window: NonNull<wl_egl_window>,
+}
+
+impl EglWindow {
+    fn new(surface: &WlSurface, width: u32, height: u32) -> AeResult<Self> {
+        let Some(library) = wayland_egl_option() else {
+            return Err(AcureError::ContextCreation(
+                Backend::WaylandEGL,
+                anyhow::anyhow!("Can't load libwayland-egl"),
+            ));
+        };
+        let proxy = surface.id().as_ptr();
+        let window = unsafe { (library.wl_egl_window_create)(proxy, width as i32, height as i32) };
+        match NonNull::new(window) {
+            Some(window) => Ok(Self { library, window }),
+            None => Err(AcureError::ContextCreation(
+                Backend::WaylandEGL,
+                anyhow::anyhow!("Can't create a wl_egl_window"),
+            )),
+        }
+    }
+
+    #[inline]
+    fn resize(&self, width: u32, height: u32) {
+        unsafe {
+            (self.library.wl_egl_window_resize)(
+                self.window.as_ptr(),
+                width as i32,
+                height as i32,
+                0,
+                0,
+            );
+        }
+    }
+}
+
+impl Drop for EglWindow {
+    fn drop(&mut self) {
+        unsafe { (self.library.wl_egl_window_destroy)(self.window.as_ptr()) };
+    }
+}
+
+// Draws with OpenGL ES through a wl_egl_window. With vsync, EGL waits for the
+// compositor's frame callback before swapping.
+pub struct WaylandEglSurface {
+    // As requested, the buffer is rounded up to the scale.
+    size: (u32, u32),
+    width: f32,
+    height: f32,
+    scale: u32,
+    max_size: u32,
+    vsync: bool,
+    batch: Batch,
+    linear: bool,
+    state: DrawState,
+    // This frame's damage, or None if it's drawn in full.
+    damage: Option<Vec<Rect>>,
+    // The damage of the frames presented so far, most recent first.
+    history: VecDeque<Option<Vec<Rect>>>,
+    // Dropped last, the GL objects need the context, the context needs the
+    // window and the window needs the surface.
+    egl: Egl,
+    window: EglWindow,
+    surface: WlSurface,
+    _connection: Connection,
+}
+
+impl WaylandEglSurface {
+    /// # Safety
+    ///
+    /// `display` and `surface` have to be a live `wl_display` and a
+    /// `wl_surface` on it, and outlive the returned surface.
+    pub unsafe fn new(display: NonNull<c_void>, surface: NonNull<c_void>) -> AeResult<Self> {
+        let (connection, surface) = unsafe { connect(Backend::WaylandEGL, display, surface)? };
+        let window = EglWindow::new(&surface, 1, 1)?;
+        let egl = unsafe {
+            Egl::init(
+                Backend::WaylandEGL,
+                display.as_ptr() as egl::NativeDisplayType,
+                window.window.as_ptr() as egl::NativeWindowType,
+            )?
+        };
+        egl.swap_intervals(true);
+        egl.make_current()?;
+
+        gl::load_with(|s| egl.get_proc_address(s));
+
+        let vertex = compile_shader(gl::VERTEX_SHADER, VERTEX)?;
+        let fragment = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT)?;
+        let program = create_program(&[vertex, fragment]);
+
+        let program = unsafe {
+            gl::DeleteShader(vertex);
+            gl::DeleteShader(fragment);
+            let program = program?;
+            gl::UseProgram(program);
+            program
+        };
+        enable_blending();
+
+        Ok(Self {
+            size: (1, 1),
+            width: 1.0,
+            height: 1.0,
+            scale: 1,
+            max_size: max_viewport_size(),
+            vsync: true,
+            batch: Batch::new(program),
+            linear: false,
+            state: DrawState::new(),
+            damage: None,
+            history: VecDeque::new(),
+            egl,
+            window,
+            surface,
+            _connection: connection,
+        })
+    }
+
+    // In EGL's bottom-left origin, for the damage extensions.
+    fn egl_rects(&self, rects: &[Rect]) -> Vec<egl::Int> {
+        rects
+            .iter()
+            .flat_map(|r| {
+                [
+                    r.x as egl::Int,
+                    (self.height - r.bottom()) as egl::Int,
+                    r.width as egl::Int,
+                    r.height as egl::Int,
+                ]
+            })
+            .collect()
+    }
+}
+
+impl Surface for WaylandEglSurface {
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+        let (width, height) = buffer_size(width, height, self.scale);
+        self.window.resize(width, height);
+        self.width = width as f32;
+        self.height = height as f32;
+        self.history.clear();
+        self.batch.set_viewport(self.width, self.height);
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        capabilities(self.max_size)
+    }
+
+    #[inline]
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        self.vsync = vsync;
+        self.egl.swap_intervals(vsync);
+        true
+    }
+
+    // Takes effect with the next swap, which commits the surface.
+    fn set_buffer_scale(&mut self, scale: u32) -> bool {
+        let scale = scale.max(1);
+        if !set_buffer_scale(&self.surface, scale) {
+            return false;
+        }
+        self.scale = scale;
+        self.surface_resize(self.size.0, self.size.1);
+        true
+    }
+
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        let linear = space == ColorSpace::Linear;
+        if linear == self.linear {
+            return true;
+        }
+        if !self.egl.set_srgb(linear) {
+            return false;
+        }
+        // The surface was recreated.
+        self.history.clear();
+        self.egl.swap_intervals(self.vsync);
+
+        self.linear = linear;
+        self.batch.set_linear(linear);
+        true
+    }
+
+    fn begin(&mut self) -> AeResult<()> {
+        self.egl.make_current()?;
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+        self.state.reset();
+        self.batch.set_scissor(None);
+        self.damage = None;
+        Ok(())
+    }
+
+    // The back buffer is `age` frames old, so it's missing this frame's
+    // damage and that of the `age - 1` frames before it.
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        let age = self.egl.buffer_age();
+        if age == 0 || age > self.history.len() {
+            return false;
+        }
+        let mut region = damage.to_vec();
+        for frame in self.history.iter().take(age - 1) {
+            match frame {
+                Some(rects) => region.extend_from_slice(rects),
+                None => return false,
+            }
+        }
+
+        self.damage = Some(damage.to_vec());
+        if damage.is_empty() {
+            return true;
+        }
+        self.egl.set_damage_region(&self.egl_rects(&region));
+        let bounds = region.iter().fold(Rect::default(), |b, r| b.union(r));
+        self.state.set_damage(Some(bounds));
+        // The clear has to be limited too.
+        set_scissor(self.state.clip(), self.height);
+        self.batch.set_scissor(self.state.clip());
+        true
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        clear(&color, self.linear);
+    }
+
+    #[inline]
+    fn command(
+        &mut self,
+        command: &Command,
+        _align: AlignMode,
+        _layout: LayoutMode,
+    ) -> AeResult<()> {
+        self.batch.command(&mut self.state, command);
+        Ok(())
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        // Nothing changed, and the front buffer already shows it.
+        if self.damage.as_ref().is_some_and(|damage| damage.is_empty()) {
+            return Ok(());
+        }
+        self.batch.flush();
+        let swapped = match &self.damage {
+            Some(damage) => self.egl.swap_buffers_with_damage(&self.egl_rects(damage)),
+            None => self.egl.swap_buffers(),
+        };
+        if let Err(e) = swapped {
+            self.history.clear();
+            return Err(e);
+        }
+        self.history.push_front(self.damage.take());
+        self.history.truncate(MAX_BUFFER_AGE);
+        Ok(())
+    }
+
+    // Only while the back buffer still holds the last frame; the compositor
+    // doesn't hand out what's on screen.
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        if self.egl.buffer_age() != 1 {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        self.egl.make_current()?;
+        Ok(read_pixels(&rect, self.width as u32, self.height as u32))
+    }
+}
+
+impl Drop for WaylandEglSurface {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current();
+    }
+}
+
+// Filled in by our event queue.
+#[derive(Default)]
+struct Events {
+    frame_pending: bool,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for Events {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlShm, ()> for Events {
+    fn event(
+        _: &mut Self,
+        _: &WlShm,
+        _: <WlShm as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for Events {
+    fn event(
+        _: &mut Self,
+        _: &WlShmPool,
+        _: <WlShmPool as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WlBuffer, Arc<AtomicBool>> for Events {
+    fn event(
+        _: &mut Self,
+        _: &WlBuffer,
+        event: wl_buffer::Event,
+        busy: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            busy.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Dispatch<WlCallback, ()> for Events {
+    fn event(
+        events: &mut Self,
+        _: &WlCallback,
+        event: wl_callback::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            events.frame_pending = false;
+        }
+    }
+}
+
+// A wl_shm buffer in its own memfd. The compositor reads it until it's
+// released.
+struct ShmBuffer {
+    buffer: WlBuffer,
+    memory: MmapMut,
+    width: u32,
+    height: u32,
+    busy: Arc<AtomicBool>,
+}
+
+impl ShmBuffer {
+    fn new(shm: &WlShm, queue: &QueueHandle<Events>, width: u32, height: u32) -> AeResult<Self> {
+        let stride = width as usize * 4;
+        let size = stride * height as usize;
+        let file =
+            File::from(memfd_create("acure", MemfdFlags::CLOEXEC).map_err(std::io::Error::from)?);
+        file.set_len(size as u64)?;
+        let memory = unsafe { MmapMut::map_mut(&file)? };
+
+        let pool = shm.create_pool(file.as_fd(), size as i32, queue, ());
+        let busy = Arc::new(AtomicBool::new(false));
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            Format::Argb8888,
+            queue,
+            busy.clone(),
+        );
+        // The buffer keeps the memory alive.
+        pool.destroy();
+        Ok(Self {
+            buffer,
+            memory,
+            width,
+            height,
+            busy,
+        })
+    }
+
+    #[inline]
+    fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+// Draws with the software rasterizer and hands each frame to the compositor
+// in shared memory, for when EGL isn't there.
+pub struct WaylandShmSurface {
+    inner: SoftwareSurface,
+    // As requested, the buffers are rounded up to the scale.
+    size: (u32, u32),
+    scale: u32,
+    vsync: bool,
+    // The part of the frame that changed, or None to send all of it.
+    damage: Option<Rect>,
+    buffers: Vec<ShmBuffer>,
+    events: Events,
+    queue: EventQueue<Events>,
+    shm: WlShm,
+    surface: WlSurface,
+    connection: Connection,
+}
+
+impl WaylandShmSurface {
+    /// # Safety
+    ///
+    /// `display` and `surface` have to be a live `wl_display` and a
+    /// `wl_surface` on it, and outlive the returned surface.
+    pub unsafe fn new(display: NonNull<c_void>, surface: NonNull<c_void>) -> AeResult<Self> {
+        let (connection, surface) = unsafe { connect(Backend::Software, display, surface)? };
+        let error = |e: anyhow::Error| AcureError::BackendError(Backend::Software, e);
+        let (globals, queue) =
+            registry_queue_init::<Events>(&connection).map_err(|e| error(e.into()))?;
+        let shm = globals
+            .bind(&queue.handle(), 1..=1, ())
+            .map_err(|e| error(e.into()))?;
+
+        Ok(Self {
+            inner: SoftwareSurface::new(1, 1),
+            size: (1, 1),
+            scale: 1,
+            vsync: true,
+            damage: None,
+            buffers: vec![],
+            events: Events::default(),
+            queue,
+            shm,
+            surface,
+            connection,
+        })
+    }
+
+    // Blocks until the compositor asks for the next frame, or for
+    // FRAME_TIMEOUT.
+    fn wait_for_frame(&mut self) -> AeResult<()> {
+        let error = |e: anyhow::Error| AcureError::BackendError(Backend::Software, e);
+        let deadline = Instant::now() + FRAME_TIMEOUT;
+        loop {
+            self.queue
+                .dispatch_pending(&mut self.events)
+                .map_err(|e| error(e.into()))?;
+            if !self.events.frame_pending {
+                return Ok(());
+            }
+            // None if events came in for the queue in the meantime.
+            let Some(guard) = self.queue.prepare_read() else {
+                continue;
+            };
+            let left = deadline.saturating_duration_since(Instant::now());
+            let timeout = Timespec {
+                tv_sec: left.as_secs() as _,
+                tv_nsec: left.subsec_nanos() as _,
+            };
+            let fd = guard.connection_fd();
+            let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+            if poll(&mut fds, Some(&timeout)).map_err(std::io::Error::from)? == 0 {
+                self.events.frame_pending = false;
+                return Ok(());
+            }
+            guard.read().map_err(|e| error(e.into()))?;
+        }
+    }
+
+    // A buffer the compositor is done with, in the current size.
+    fn free_buffer(&mut self) -> AeResult<usize> {
+        let (width, height) = (self.inner.width(), self.inner.height());
+        self.buffers
+            .retain(|b| b.is_busy() || (b.width, b.height) == (width, height));
+        if let Some(free) = self.buffers.iter().position(|b| !b.is_busy()) {
+            return Ok(free);
+        }
+        let buffer = ShmBuffer::new(&self.shm, &self.queue.handle(), width, height)?;
+        self.buffers.push(buffer);
+        Ok(self.buffers.len() - 1)
+    }
+}
+
+impl Surface for WaylandShmSurface {
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+        let (width, height) = buffer_size(width, height, self.scale);
+        self.inner.surface_resize(width, height);
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    #[inline]
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        self.vsync = vsync;
+        true
+    }
+
+    // Takes effect with the next frame, which commits the surface.
+    fn set_buffer_scale(&mut self, scale: u32) -> bool {
+        let scale = scale.max(1);
+        if !set_buffer_scale(&self.surface, scale) {
+            return false;
+        }
+        self.scale = scale;
+        self.surface_resize(self.size.0, self.size.1);
+        true
+    }
+
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        self.inner.set_color_space(space)
+    }
+
+    fn begin(&mut self) -> AeResult<()> {
+        self.damage = None;
+        // Picks up buffer releases and frame callbacks.
+        self.queue
+            .dispatch_pending(&mut self.events)
+            .map_err(|e| AcureError::BackendError(Backend::Software, e.into()))?;
+        self.inner.begin()
+    }
+
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.inner.set_damage(damage) {
+            return false;
+        }
+        self.damage = Some(damage.iter().fold(Rect::default(), |b, r| b.union(r)));
+        true
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        self.inner.clear(color);
+    }
+
+    #[inline]
+    fn command(&mut self, command: &Command, align: AlignMode, layout: LayoutMode) -> AeResult<()> {
+        self.inner.command(command, align, layout)
+    }
+
+    // The whole frame is copied into a free buffer, so none ever holds an
+    // older one; the damage only tells the compositor what to redraw.
+    fn end(&mut self) -> AeResult<()> {
+        self.inner.end()?;
+        if self.damage.is_some_and(|damage| damage.is_empty()) {
+            return Ok(());
+        }
+        if self.vsync {
+            self.wait_for_frame()?;
+        }
+
+        let (width, height) = (self.inner.width(), self.inner.height());
+        let damage = self
+            .damage
+            .unwrap_or_else(|| Rect::from_u32(0, 0, width, height))
+            .round_out();
+        let buffer = self.free_buffer()?;
+        let buffer = &mut self.buffers[buffer];
+        // wl_shm formats are little endian.
+        for (dst, pixel) in buffer.memory.chunks_exact_mut(4).zip(self.inner.pixels()) {
+            dst.copy_from_slice(&pixel.to_le_bytes());
+        }
+        buffer.busy.store(true, Ordering::Release);
+
+        self.surface.attach(Some(&buffer.buffer), 0, 0);
+        let (x, y, w, h) = (
+            damage.x as i32,
+            damage.y as i32,
+            damage.width as i32,
+            damage.height as i32,
+        );
+        // wl_surface.damage_buffer is from version 4.
+        if self.surface.version() >= 4 {
+            self.surface.damage_buffer(x, y, w, h);
+        } else {
+            // In surface units, rounded out.
+            let scale = self.scale as i32;
+            let (left, top) = (x / scale, y / scale);
+            let right = (x + w + scale - 1) / scale;
+            let bottom = (y + h + scale - 1) / scale;
+            self.surface.damage(left, top, right - left, bottom - top);
+        }
+        if self.vsync {
+            self.surface.frame(&self.queue.handle(), ());
+            self.events.frame_pending = true;
+        }
+        self.surface.commit();
+        self.connection
+            .flush()
+            .map_err(|e| AcureError::BackendError(Backend::Software, e.into()))
+    }
+
+    #[inline]
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        self.inner.read_pixels(rect)
+    }
+}
+
+impl Drop for WaylandShmSurface {
+    fn drop(&mut self) {
+        self.buffers.clear();
+        let _ = self.connection.flush();
+    }
+}
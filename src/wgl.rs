@@ -4,31 +4,47 @@ const FRAGMENT: &'static str = include_str!("shader/shader_core.frag");
 const VERTEX: &'static str = include_str!("shader/shader_core.vert");
 
 use std::{
-    ffi::{c_void, CString},
+    ffi::c_void,
     mem::{size_of, MaybeUninit},
     ptr::{null, null_mut},
 };
 
-use gl::types::{GLboolean, GLfloat, GLuint};
+use gl::types::GLuint;
 use windows::{
-    core::PCSTR,
+    core::{w, PCSTR},
     Win32::{
         Foundation::HWND,
         Graphics::{
-            Gdi::{GetDC, HDC},
+            Gdi::{GetDC, ReleaseDC, HDC},
             OpenGL::*,
         },
+        UI::WindowsAndMessaging::{CreateWindowExW, DestroyWindow, WINDOW_EX_STYLE, WS_POPUP},
     },
 };
 
 use crate::{
-    gl::{compile_shader, create_program, Vao, Vbo},
-    AcureError, AeResult, Backend,
+    gl::{
+        capabilities, clear, compile_shader, create_program, enable_blending, max_viewport_size,
+        read_pixels, Batch, Vao,
+    },
+    surface::{Capabilities, DrawState},
+    AcureError, AeResult, Backend, Image, Rect,
 };
 
-type wglCreateContextAttribsARB =
-    fn(hDC: *mut c_void, hshareContext: *mut c_void, attribList: *const i32) -> *mut c_void;
-type wglSwapIntervalEXT = fn(i32);
+type wglCreateContextAttribsARB = extern "system" fn(
+    hDC: *mut c_void,
+    hshareContext: *mut c_void,
+    attribList: *const i32,
+) -> *mut c_void;
+type wglChoosePixelFormatARB = extern "system" fn(
+    hdc: *mut c_void,
+    piAttribIList: *const i32,
+    pfAttribFList: *const f32,
+    nMaxFormats: u32,
+    piFormats: *mut i32,
+    nNumFormats: *mut u32,
+) -> i32;
+type wglSwapIntervalEXT = extern "system" fn(i32) -> i32;
 
 const WGL_CONTEXT_MAJOR_VERSION_ARB: i32 = 0x2091;
 const WGL_CONTEXT_MINOR_VERSION_ARB: i32 = 0x2092;
@@ -45,6 +61,16 @@ const WGL_CONTEXT_COMPATIBILITY_PROFILE_BIT_ARB: i32 = 0x00000002;
 const ERROR_INVALID_VERSION_ARB: i32 = 0x2095;
 const ERROR_INVALID_PROFILE_ARB: i32 = 0x2096;
 
+const WGL_DRAW_TO_WINDOW_ARB: i32 = 0x2001;
+const WGL_SUPPORT_OPENGL_ARB: i32 = 0x2010;
+const WGL_DOUBLE_BUFFER_ARB: i32 = 0x2011;
+const WGL_PIXEL_TYPE_ARB: i32 = 0x2013;
+const WGL_COLOR_BITS_ARB: i32 = 0x2014;
+const WGL_DEPTH_BITS_ARB: i32 = 0x2022;
+const WGL_STENCIL_BITS_ARB: i32 = 0x2023;
+const WGL_TYPE_RGBA_ARB: i32 = 0x202B;
+const WGL_FRAMEBUFFER_SRGB_CAPABLE_ARB: i32 = 0x20A9;
+
 static VERTEX_DATA: [f32; 6] = [0.0, 0.5, 0.5, -0.5, -0.5, -0.5];
 
 pub struct Wgl {
@@ -52,6 +78,7 @@ pub struct Wgl {
     wglSwapIntervalEXT: wglSwapIntervalEXT,
     hdc: HDC,
     ctx: HGLRC,
+    srgb_capable: bool,
 }
 
 impl Wgl {
@@ -60,8 +87,8 @@ impl Wgl {
     where
         F: Fn(&str) -> *const c_void,
     {
-        let mut hdc = unsafe { GetDC(hwnd) };
-        let pfd = PIXELFORMATDESCRIPTOR {
+        let hdc = unsafe { GetDC(hwnd) };
+        let mut pfd = PIXELFORMATDESCRIPTOR {
             nSize: size_of::<PIXELFORMATDESCRIPTOR>() as u16,
             nVersion: 1,
             dwFlags: PFD_DRAW_TO_WINDOW | PFD_SUPPORT_OPENGL | PFD_DOUBLEBUFFER,
@@ -90,20 +117,129 @@ impl Wgl {
             dwDamageMask: 0,
         };
 
-        let pixel_format = unsafe { ChoosePixelFormat(hdc, &pfd) };
-        let ctx = unsafe {
-            match SetPixelFormat(hdc, pixel_format, &pfd) {
+        // The WGL extensions can only be loaded with a current context, and a
+        // window's pixel format can only be set once, so bootstrap them on a
+        // hidden dummy window.
+        let dummy_hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE(0),
+                w!("STATIC"),
+                w!(""),
+                WS_POPUP,
+                0,
+                0,
+                1,
+                1,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        if dummy_hwnd.0 == 0 {
+            return Err(AcureError::BackendError(
+                Backend::WGL,
+                windows::core::Error::from_win32().into(),
+            ));
+        }
+        let dummy_hdc = unsafe { GetDC(dummy_hwnd) };
+
+        let dummy_ctx = unsafe {
+            let pixel_format = ChoosePixelFormat(dummy_hdc, &pfd);
+            match SetPixelFormat(dummy_hdc, pixel_format, &pfd) {
                 Ok(_) => {}
                 Err(e) => return Err(AcureError::BackendError(Backend::WGL, e.into())),
             }
-            let ctx = wglCreateContext(hdc).unwrap();
-            match wglMakeCurrent(hdc, ctx) {
+            let ctx = match wglCreateContext(dummy_hdc) {
+                Ok(ctx) => ctx,
+                Err(e) => return Err(AcureError::ContextCreation(Backend::WGL, e.into())),
+            };
+            match wglMakeCurrent(dummy_hdc, ctx) {
                 Ok(_) => {}
                 Err(e) => return Err(AcureError::BackendError(Backend::WGL, e.into())),
             }
             ctx
         };
 
+        let wglCreateContextAttribsARB = func("wglCreateContextAttribsARB");
+        if wglCreateContextAttribsARB.is_null() {
+            return Err(crate::AcureError::NullPtrError(
+                "Could not load wglCreateContextAttribsARB".to_owned(),
+            ));
+        }
+
+        let wglCreateContextAttribsARB: wglCreateContextAttribsARB =
+            unsafe { std::mem::transmute(wglCreateContextAttribsARB) };
+
+        let wglSwapIntervalEXT = func("wglSwapIntervalEXT");
+        if wglSwapIntervalEXT.is_null() {
+            return Err(crate::AcureError::NullPtrError(
+                "Could not load wglSwapIntervalEXT".to_owned(),
+            ));
+        }
+
+        let wglSwapIntervalEXT: wglSwapIntervalEXT =
+            unsafe { std::mem::transmute(wglSwapIntervalEXT) };
+
+        let wglChoosePixelFormatARB = func("wglChoosePixelFormatARB");
+        let srgb_format = if wglChoosePixelFormatARB.is_null() {
+            None
+        } else {
+            let wglChoosePixelFormatARB: wglChoosePixelFormatARB =
+                unsafe { std::mem::transmute(wglChoosePixelFormatARB) };
+            let attribs = [
+                WGL_DRAW_TO_WINDOW_ARB,
+                1,
+                WGL_SUPPORT_OPENGL_ARB,
+                1,
+                WGL_DOUBLE_BUFFER_ARB,
+                1,
+                WGL_PIXEL_TYPE_ARB,
+                WGL_TYPE_RGBA_ARB,
+                WGL_COLOR_BITS_ARB,
+                32,
+                WGL_DEPTH_BITS_ARB,
+                24,
+                WGL_STENCIL_BITS_ARB,
+                8,
+                WGL_FRAMEBUFFER_SRGB_CAPABLE_ARB,
+                1,
+                0,
+            ];
+            let mut format = 0;
+            let mut count = 0;
+            let ok = (wglChoosePixelFormatARB)(
+                hdc.0 as *mut c_void,
+                attribs.as_ptr(),
+                null(),
+                1,
+                &mut format,
+                &mut count,
+            );
+            (ok != 0 && count > 0).then_some(format)
+        };
+
+        let srgb_capable = srgb_format.is_some();
+        let pixel_format = match srgb_format {
+            Some(format) => unsafe {
+                DescribePixelFormat(
+                    hdc,
+                    format,
+                    size_of::<PIXELFORMATDESCRIPTOR>() as u32,
+                    Some(&mut pfd),
+                );
+                format
+            },
+            None => unsafe { ChoosePixelFormat(hdc, &pfd) },
+        };
+
+        unsafe {
+            match SetPixelFormat(hdc, pixel_format, &pfd) {
+                Ok(_) => {}
+                Err(e) => return Err(AcureError::BackendError(Backend::WGL, e.into())),
+            }
+        }
+
         let attribs = [
             WGL_CONTEXT_MAJOR_VERSION_ARB,
             3,
@@ -114,68 +250,64 @@ impl Wgl {
             0,
         ];
 
-        let wglCreateContextAttribsARB = func("wglCreateContextAttribsARB");
-        if wglCreateContextAttribsARB.is_null() {
-            return Err(crate::AcureError::NullPtrError(
-                "Could not load wglCreateContextAttribsARB".to_owned(),
-            ));
-        }
-
-        let wglCreateContextAttribsARB: wglCreateContextAttribsARB =
-            unsafe { std::mem::transmute(wglCreateContextAttribsARB) };
         let new_ctx = HGLRC((wglCreateContextAttribsARB)(
             hdc.0 as *mut c_void,
             null_mut(),
             attribs.as_ptr(),
         ) as isize);
+        if new_ctx.is_invalid() {
+            return Err(AcureError::ContextCreation(
+                Backend::WGL,
+                windows::core::Error::from_win32().into(),
+            ));
+        }
         unsafe {
-            match wglDeleteContext(ctx) {
+            match wglMakeCurrent(hdc, new_ctx) {
                 Ok(_) => {}
                 Err(e) => return Err(AcureError::BackendError(Backend::WGL, e.into())),
             };
-            match wglMakeCurrent(hdc, new_ctx) {
+            match wglDeleteContext(dummy_ctx) {
                 Ok(_) => {}
                 Err(e) => return Err(AcureError::BackendError(Backend::WGL, e.into())),
             };
+            ReleaseDC(dummy_hwnd, dummy_hdc);
+            let _ = DestroyWindow(dummy_hwnd);
         }
 
-        let wglSwapIntervalEXT = func("wglSwapIntervalEXT");
-        if wglSwapIntervalEXT.is_null() {
-            return Err(crate::AcureError::NullPtrError(
-                "Could not load wglSwapIntervalEXT".to_owned(),
-            ));
-        }
-
-        let wglSwapIntervalEXT: wglSwapIntervalEXT =
-            unsafe { std::mem::transmute(wglSwapIntervalEXT) };
-
         Ok(Self {
             wglCreateContextAttribsARB,
             wglSwapIntervalEXT,
             hdc,
             ctx: new_ctx,
+            srgb_capable,
         })
     }
 
+    #[inline]
+    pub fn is_srgb_capable(&self) -> bool {
+        self.srgb_capable
+    }
+
     #[inline]
     pub unsafe fn make_current(&self) {}
 
     #[inline]
     pub unsafe fn swap_intervals(&self, interval: bool) {
-        ((self.wglSwapIntervalEXT)(interval as i32));
+        (self.wglSwapIntervalEXT)(interval as i32);
     }
 
     #[inline]
-    pub fn swap_buffers(&self) {
-        unsafe {
-            SwapBuffers(self.hdc).unwrap();
-        }
+    pub fn swap_buffers(&self) -> AeResult<()> {
+        unsafe { SwapBuffers(self.hdc) }
+            .map_err(|e| AcureError::BackendError(Backend::WGL, e.into()))
     }
 
+    // Null for functions the driver doesn't have.
     #[inline]
     pub fn get_proc_address(&self, procname: &str) -> *const c_void {
         unsafe {
-            wglGetProcAddress(PCSTR(format!("{}\0", procname).as_ptr())).unwrap() as *const c_void
+            wglGetProcAddress(PCSTR(format!("{}\0", procname).as_ptr()))
+                .map_or(null(), |f| f as *const c_void)
         }
     }
 }
@@ -183,7 +315,7 @@ impl Wgl {
 impl Drop for Wgl {
     fn drop(&mut self) {
         unsafe {
-            wglDeleteContext(self.ctx).unwrap();
+            let _ = wglDeleteContext(self.ctx);
         }
     }
 }
@@ -193,10 +325,14 @@ pub struct WglSurface {
     wgl: Wgl,
     vertex: u32,
     fragment: u32,
-    program: u32,
-    projection: Vec<f32>,
+    // Core profiles draw nothing without a bound vertex array.
+    _vao: Vao,
+    batch: Batch,
     width: f32,
     height: f32,
+    max_size: u32,
+    linear: bool,
+    state: DrawState,
 }
 
 impl WglSurface {
@@ -217,46 +353,28 @@ impl WglSurface {
 
             wgl.swap_intervals(true);
 
-            let vertex = compile_shader(gl::VERTEX_SHADER, VERTEX);
-            let fragment = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT);
-
-            let program = create_program(&[vertex, fragment]);
-
-            let left = 0.0;
-            let right = 1600.0;
-            let bottom = 0.0;
-            let top = 900.0;
-            let near_val = -1.0;
-            let far_val = 1.0;
-
-            let projection: Vec<f32> = vec![
-                2.0 / (right - left),
-                0.0,
-                0.0,
-                0.0,
-                0.0,
-                2.0 / (top - bottom),
-                0.0,
-                0.0,
-                0.0,
-                0.0,
-                -2.0 / (far_val - near_val),
-                0.0,
-                -(right + left) / (right - left),
-                -(top + bottom) / (top - bottom),
-                -(far_val + near_val) / (far_val - near_val),
-                1.0,
-            ];
+            let vertex = compile_shader(gl::VERTEX_SHADER, VERTEX)?;
+            let fragment = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT)?;
+
+            let program = create_program(&[vertex, fragment])?;
+
+            enable_blending();
+
+            let vao = Vao::new(1);
+            let batch = Batch::new(program);
 
             Ok(Self {
                 hwnd,
                 wgl,
                 vertex,
                 fragment,
-                program,
-                projection,
+                _vao: vao,
+                batch,
                 width: 0.0,
                 height: 0.0,
+                max_size: max_viewport_size(),
+                linear: false,
+                state: DrawState::new(),
             })
         }
     }
@@ -267,140 +385,83 @@ impl crate::Surface for WglSurface {
     fn surface_resize(&mut self, width: u32, height: u32) {
         self.width = width as f32;
         self.height = height as f32;
-        unsafe {
-            gl::Viewport(0, 0, width as i32, height as i32);
-            self.projection[0] = 2.0 / self.width;
-            self.projection[5] = 2.0 / self.height;
-            self.projection[12] = -(self.width + 0.0) / (self.width - 0.0);
-            self.projection[13] = -(self.height + 0.0) / (self.height - 0.0);
-        }
+        self.batch.set_viewport(self.width, self.height);
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        capabilities(self.max_size)
     }
 
     #[inline]
-    fn begin(&mut self) {}
+    fn set_vsync(&mut self, vsync: bool) -> bool {
+        unsafe { self.wgl.swap_intervals(vsync) };
+        true
+    }
 
     #[inline]
-    fn clear(&self, color: crate::Color) {
+    fn set_color_space(&mut self, space: crate::ColorSpace) -> bool {
+        let linear = space == crate::ColorSpace::Linear;
+        if linear == self.linear {
+            return true;
+        }
+        if linear && !self.wgl.is_srgb_capable() {
+            return false;
+        }
+
+        self.linear = linear;
         unsafe {
-            match color {
-                crate::Color::ARGB(a, r, g, b) => {
-                    gl::ClearColor(
-                        (r as f32) / 255.0,
-                        (g as f32) / 255.0,
-                        (b as f32) / 255.0,
-                        (a as f32) / 255.0,
-                    );
-                }
+            if linear {
+                gl::Enable(gl::FRAMEBUFFER_SRGB);
+            } else {
+                gl::Disable(gl::FRAMEBUFFER_SRGB);
             }
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-            gl::ClearDepth(1.0);
         }
+        self.batch.set_linear(linear);
+        true
+    }
+
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+        self.state.reset();
+        self.batch.set_scissor(None);
+        Ok(())
+    }
+
+    #[inline]
+    fn clear(&mut self, color: crate::Color) {
+        clear(&color, self.linear);
     }
 
     #[inline]
     fn command(
-        &self,
+        &mut self,
         command: &crate::Command,
         align: crate::AlignMode,
         layout: crate::LayoutMode,
-    ) {
-        match command {
-            crate::Command::FillRectangle(x, y, width, height, radius, color) => unsafe {
-                let vert_color: Vec<f32>;
-                match color {
-                    crate::Color::ARGB(a, r, g, b) => {
-                        let a = (*a as f32) / 255.0;
-                        let r = (*r as f32) / 255.0;
-                        let g = (*g as f32) / 255.0;
-                        let b = (*b as f32) / 255.0;
-                        vert_color = vec![r, g, b, a];
-                    }
-                }
-
-                let x = *x as f32;
-                let y = *y as f32;
-                let width = *width as f32;
-                let height = *height as f32;
-
-                let vao = Vao::new(1);
-                let vbo = Vbo::gen(&[
-                    x,
-                    self.height - y,
-                    vert_color[0],
-                    vert_color[1],
-                    vert_color[2],
-                    vert_color[3],
-                    x,
-                    self.height - y - height,
-                    vert_color[0],
-                    vert_color[1],
-                    vert_color[2],
-                    vert_color[3],
-                    x + width,
-                    self.height - y - height,
-                    vert_color[0],
-                    vert_color[1],
-                    vert_color[2],
-                    vert_color[3],
-                    x + width,
-                    self.height - y,
-                    vert_color[0],
-                    vert_color[1],
-                    vert_color[2],
-                    vert_color[3],
-                ]);
-
-                gl::UseProgram(self.program);
-                let matrix = gl::GetUniformLocation(
-                    self.program,
-                    CString::new("projectionMatrix").unwrap().as_ptr(),
-                );
-                gl::UniformMatrix4fv(matrix, 1, gl::FALSE as GLboolean, self.projection.as_ptr());
-                let color_pos =
-                    gl::GetAttribLocation(self.program, CString::new("color").unwrap().as_ptr());
-                gl::EnableVertexAttribArray(color_pos as u32);
-                gl::VertexAttribPointer(
-                    color_pos as u32,
-                    4,
-                    gl::FLOAT,
-                    gl::FALSE as GLboolean,
-                    0,
-                    vert_color.as_ptr() as *const c_void,
-                );
-                gl::BindFragDataLocation(
-                    self.program,
-                    0,
-                    CString::new("out_color").unwrap().as_ptr(),
-                );
-
-                gl::VertexAttribPointer(
-                    0,
-                    2,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    (6 * std::mem::size_of::<GLfloat>()) as i32,
-                    null(),
-                );
-                gl::EnableVertexAttribArray(0);
-                gl::VertexAttribPointer(
-                    1,
-                    4,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    (6 * std::mem::size_of::<GLfloat>()) as i32,
-                    (3 * std::mem::size_of::<GLfloat>()) as *const c_void,
-                );
-                gl::EnableVertexAttribArray(1);
-
-                gl::DrawArrays(gl::TRIANGLE_FAN, 0, 4);
-            },
-
-            crate::Command::WriteString(x, y, width, height, color, text) => {}
-        }
+    ) -> AeResult<()> {
+        self.batch.command(&mut self.state, command);
+        Ok(())
     }
 
     #[inline]
-    fn end(&mut self) {
-        self.wgl.swap_buffers();
+    fn end(&mut self) -> AeResult<()> {
+        self.batch.flush();
+        self.wgl.swap_buffers()
+    }
+
+    // The last frame is in the front buffer once it's swapped.
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        unsafe {
+            gl::ReadBuffer(gl::FRONT);
+        }
+        let image = read_pixels(&rect, self.width as u32, self.height as u32);
+        unsafe {
+            gl::ReadBuffer(gl::BACK);
+        }
+        Ok(image)
     }
 }
@@ -6,45 +6,69 @@ use std::{
 
 use x11::{
     xft::{
-        XftColorAllocValue, XftColorFree, XftDraw, XftDrawCreate, XftDrawStringUtf8,
-        XftFontOpenName, XftTextExtentsUtf8,
+        XftColorAllocValue, XftColorFree, XftDraw, XftDrawChange, XftDrawCreate, XftDrawDestroy,
+        XftDrawSetClip, XftDrawSetClipRectangles, XftDrawStringUtf8, XftFontClose, XftFontOpenName,
     },
     xlib::{
-        Colormap, Visual, XAllocColor, XColor, XCreateGC, XDefaultColormap, XDefaultScreen,
-        XDefaultVisual, XFillRectangle, XFlush, XFreeColormap, XFreeColors, XGetWindowAttributes,
-        XOpenDisplay, XPending, XSetBackground, XSetForeground, XWindowAttributes, _XDisplay, _XGC,
+        _XDisplay, Colormap, Pixmap, Visual, XAllPlanes, XCloseDisplay, XCopyArea, XCreateGC,
+        XCreatePixmap, XDefaultScreen, XDestroyImage, XFlush, XFreeGC, XFreePixmap, XGetImage,
+        XGetPixel, XGetWindowAttributes, XOpenDisplay, XPending, XRectangle, XWindowAttributes,
+        ZPixmap, _XGC,
     },
-    xrender::XRenderColor,
+    xrender::{
+        CPClipMask, PictOpOver, PictOpSrc, PictStandardA8, XLineFixed, XPointFixed,
+        XRenderChangePicture, XRenderColor, XRenderCompositeTrapezoids, XRenderCreatePicture,
+        XRenderCreateSolidFill, XRenderFillRectangle, XRenderFindStandardFormat,
+        XRenderFindVisualFormat, XRenderFreePicture, XRenderPictFormat, XRenderPictureAttributes,
+        XRenderQueryExtension, XRenderSetPictureClipRectangles, XTrapezoid,
+    },
+};
+
+use crate::{
+    image::pixel_bounds,
+    surface::{Capabilities, DrawState, Surface},
+    trapezoid::{fixed, trapezoids, Trapezoid},
+    AcureError, AeResult, Backend, Color, ColorSpace, FillRule, Image, Path, Rect,
 };
 
-use crate::{surface::Surface, Color};
+// Maximum distance in pixels between a curve and its flattened polyline.
+const TOLERANCE: f32 = 0.5;
+// The fontconfig pattern text is drawn with.
+const FONT: &str = "Yu gothic-12";
+// How many colors each surface keeps allocated.
+const MAX_COLORS: usize = 64;
 
-pub struct XftColor {
+pub(crate) struct XftColor {
     display: *mut _XDisplay,
+    visual: *mut Visual,
+    colormap: Colormap,
     inner: x11::xft::XftColor,
 }
 
 impl XftColor {
-    pub fn alloc(display: *mut _XDisplay, color: Color) -> Self {
+    // `color` is premultiplied, as RENDER takes it. On visuals that aren't
+    // TrueColor the pixel comes from `colormap`, so it has to be the one of
+    // the drawable's window.
+    pub fn alloc(
+        display: *mut _XDisplay,
+        visual: *mut Visual,
+        colormap: Colormap,
+        color: &XRenderColor,
+    ) -> AeResult<Self> {
         unsafe {
-            let mut inner = unsafe { MaybeUninit::uninit().assume_init() };
-            let color = match color {
-                Color::ARGB(a, r, g, b) => XRenderColor {
-                    red: (r as u16) * 255,
-                    green: (g as u16) * 255,
-                    blue: (b as u16) * 255,
-                    alpha: (a as u16) * 255,
-                },
-            };
-
-            XftColorAllocValue(
+            let mut inner = MaybeUninit::zeroed();
+            if XftColorAllocValue(display, visual, colormap, color, inner.as_mut_ptr()) == 0 {
+                return Err(AcureError::BackendError(
+                    Backend::X11,
+                    anyhow::anyhow!("XftColorAllocValue failed"),
+                ));
+            }
+            Ok(Self {
                 display,
-                XDefaultVisual(display, XDefaultScreen(display)),
-                XDefaultColormap(display, XDefaultScreen(display)),
-                &color,
-                &mut inner,
-            );
-            Self { display, inner }
+                visual,
+                colormap,
+                inner: inner.assume_init(),
+            })
         }
     }
 }
@@ -52,146 +76,593 @@ impl XftColor {
 impl Drop for XftColor {
     fn drop(&mut self) {
         unsafe {
-            XftColorFree(
-                self.display,
-                XDefaultVisual(self.display, XDefaultScreen(self.display)),
-                XDefaultColormap(self.display, XDefaultScreen(self.display)),
-                &mut self.inner,
-            );
+            XftColorFree(self.display, self.visual, self.colormap, &mut self.inner);
         }
     }
 }
 
-pub struct X11Surface {
+pub(crate) struct XftFont {
     display: *mut _XDisplay,
+    raw: *mut x11::xft::XftFont,
+}
+
+impl XftFont {
+    // `name` is a fontconfig pattern, e.g. "Sans-12:bold".
+    pub fn open(display: *mut _XDisplay, name: &str) -> AeResult<Self> {
+        let name = CString::new(name)
+            .map_err(|_| AcureError::NullPtrError(format!("Font name {:?}", name)))?;
+        let raw = unsafe { XftFontOpenName(display, XDefaultScreen(display), name.as_ptr()) };
+        if raw.is_null() {
+            return Err(AcureError::NullPtrError(
+                "XftFontOpenName failed".to_owned(),
+            ));
+        }
+        Ok(Self { display, raw })
+    }
+}
+
+impl Drop for XftFont {
+    fn drop(&mut self) {
+        unsafe { XftFontClose(self.display, self.raw) };
+    }
+}
+
+// A handful of values, the least recently used one is dropped to make room.
+struct Lru<K, V> {
+    // Oldest first.
+    entries: Vec<(K, V)>,
+    capacity: usize,
+}
+
+impl<K: PartialEq, V> Lru<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn get_or_try_insert(&mut self, key: K, create: impl FnOnce() -> AeResult<V>) -> AeResult<&V> {
+        match self.entries.iter().position(|(k, _)| *k == key) {
+            Some(i) => {
+                let entry = self.entries.remove(i);
+                self.entries.push(entry);
+            }
+            None => {
+                let value = create()?;
+                if self.entries.len() >= self.capacity {
+                    self.entries.remove(0);
+                }
+                self.entries.push((key, value));
+            }
+        }
+        Ok(&self.entries.last().unwrap().1)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// Draws into a pixmap with the RENDER extension and copies each frame to the
+// window in one go, so it doesn't flicker.
+pub struct X11Surface {
+    display: XDisplay,
+    // Only copies `back` to the window.
     gc: *mut _XGC,
+    // Both draw to `back`.
     xft: *mut XftDraw,
+    picture: c_ulong,
+    // The window's format, and the one of trapezoid masks.
+    format: *mut XRenderPictFormat,
+    a8: *mut XRenderPictFormat,
     window: c_ulong,
+    back: Pixmap,
+    width: u32,
+    height: u32,
+    depth: u32,
+    // The window's, for the Xft colors.
+    visual: *mut Visual,
+    colormap: Colormap,
+    // Opened on the first string.
+    font: Option<XftFont>,
+    // By straight ARGB with the opacity applied.
+    colors: Lru<[u8; 4], XftColor>,
+    state: DrawState,
+    // The rectangles being redrawn, or None for the whole window.
+    damage: Option<Vec<Rect>>,
+    // Whether the window still shows the last frame. The server drops the
+    // contents on expose, so `Acure::invalidate` has to be called then.
+    drawn: bool,
 }
 
 impl X11Surface {
-    pub fn new(window: c_ulong) -> Self {
+    // The back buffer starts out at the window's size.
+    pub fn new(display: XDisplay, window: c_ulong) -> AeResult<Self> {
         unsafe {
-            let display = XOpenDisplay(null());
+            let raw = display.as_ptr();
+            let attributes = get_window_attributes(raw, window);
+            if attributes.visual.is_null() {
+                return Err(AcureError::BackendError(
+                    Backend::X11,
+                    anyhow::anyhow!("Can't get the attributes of window {}", window),
+                ));
+            }
+            let (mut event, mut error) = (0, 0);
+            if XRenderQueryExtension(raw, &mut event, &mut error) == 0 {
+                return Err(AcureError::BackendError(
+                    Backend::X11,
+                    anyhow::anyhow!("The server has no RENDER extension"),
+                ));
+            }
+            let format = XRenderFindVisualFormat(raw, attributes.visual);
+            let a8 = XRenderFindStandardFormat(raw, PictStandardA8);
+            if format.is_null() || a8.is_null() {
+                return Err(AcureError::BackendError(
+                    Backend::X11,
+                    anyhow::anyhow!("No picture format for the visual of window {}", window),
+                ));
+            }
 
-            if display.is_null() {
-                panic!("XOpenDisplay failed");
+            let width = attributes.width.max(1) as u32;
+            let height = attributes.height.max(1) as u32;
+            let depth = attributes.depth as u32;
+            let back = XCreatePixmap(raw, window, width, height, depth);
+            let picture = XRenderCreatePicture(raw, back, format, 0, null());
+            let gc = XCreateGC(raw, window, 0, null_mut());
+            let xft = XftDrawCreate(raw, back, attributes.visual, attributes.colormap);
+            if xft.is_null() {
+                XFreeGC(raw, gc);
+                XRenderFreePicture(raw, picture);
+                XFreePixmap(raw, back);
+                return Err(AcureError::NullPtrError("XftDrawCreate failed".to_owned()));
             }
-            let gc = XCreateGC(display, window, 0, null_mut());
-            let xft = XftDrawCreate(
-                display,
-                window,
-                XDefaultVisual(display, XDefaultScreen(display)),
-                XDefaultColormap(display, XDefaultScreen(display)),
-            );
 
-            Self {
+            Ok(Self {
                 display,
                 gc,
                 xft,
+                picture,
+                format,
+                a8,
                 window,
+                back,
+                width,
+                height,
+                depth,
+                visual: attributes.visual,
+                colormap: attributes.colormap,
+                font: None,
+                colors: Lru::new(MAX_COLORS),
+                state: DrawState::new(),
+                damage: None,
+                drawn: false,
+            })
+        }
+    }
+}
+
+impl X11Surface {
+    // Xft can't rotate or scale glyphs here, so only the origin is
+    // transformed.
+    fn write_string(&mut self, x: f32, y: f32, color: Color, text: &str) -> AeResult<()> {
+        let display = self.display.as_ptr();
+        let font = match &mut self.font {
+            Some(font) => font,
+            font => font.insert(XftFont::open(display, FONT)?),
+        };
+        let [_, _, _, alpha] = color.to_rgba();
+        let argb = color.with_alpha(alpha * self.state.opacity()).to_argb();
+        let [a, r, g, b] = argb;
+        let (visual, colormap) = (self.visual, self.colormap);
+        let color = self.colors.get_or_try_insert(argb, || {
+            let color = render_color(Color::ARGB(a, r, g, b), 1.0);
+            XftColor::alloc(display, visual, colormap, &color)
+        })?;
+        let (x, y) = self.state.transform().apply(x, y);
+        unsafe {
+            XftDrawStringUtf8(
+                self.xft,
+                &color.inner,
+                font.raw,
+                x.round() as i32,
+                (y.round() as i32) + (*font.raw).ascent,
+                text.as_ptr(),
+                text.len() as i32,
+            );
+        }
+        Ok(())
+    }
+
+    fn fill_contours(&mut self, contours: &[Vec<(f32, f32)>], rule: FillRule, color: Color) {
+        let trapezoids = trapezoids(contours, rule)
+            .iter()
+            .map(x_trapezoid)
+            .collect::<Vec<_>>();
+        if trapezoids.is_empty() {
+            return;
+        }
+        let display = self.display.as_ptr();
+        unsafe {
+            let color = render_color(color, self.state.opacity());
+            let source = XRenderCreateSolidFill(display, &color);
+            XRenderCompositeTrapezoids(
+                display,
+                PictOpOver,
+                source,
+                self.picture,
+                self.a8,
+                0,
+                0,
+                trapezoids.as_ptr(),
+                trapezoids.len() as i32,
+            );
+            XRenderFreePicture(display, source);
+        }
+    }
+
+    // The clip is the damage, intersected with the innermost PushClip.
+    fn update_clip(&mut self) {
+        let clip = self.state.clip();
+        let rects = match (&self.damage, clip) {
+            (Some(damage), Some(clip)) => damage.iter().map(|r| r.intersect(&clip)).collect(),
+            (Some(damage), None) => damage.clone(),
+            (None, Some(clip)) => vec![clip],
+            (None, None) => {
+                unsafe {
+                    let attributes = MaybeUninit::<XRenderPictureAttributes>::zeroed();
+                    XRenderChangePicture(
+                        self.display.as_ptr(),
+                        self.picture,
+                        CPClipMask as c_ulong,
+                        attributes.as_ptr(),
+                    );
+                    XftDrawSetClip(self.xft, null_mut());
+                }
+                return;
             }
+        };
+
+        let rects = rects
+            .iter()
+            .filter(|r| !r.is_empty())
+            .map(|r| {
+                let r = r.round_out();
+                XRectangle {
+                    x: r.x as i16,
+                    y: r.y as i16,
+                    width: r.width as u16,
+                    height: r.height as u16,
+                }
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            XRenderSetPictureClipRectangles(
+                self.display.as_ptr(),
+                self.picture,
+                0,
+                0,
+                rects.as_ptr(),
+                rects.len() as i32,
+            );
+            XftDrawSetClipRectangles(self.xft, 0, 0, rects.as_ptr(), rects.len() as i32);
         }
     }
 }
 
 impl Surface for X11Surface {
-    fn surface_resize(&mut self, width: u32, height: u32) {}
+    // The new back buffer's contents are undefined, so the next frame is
+    // drawn in full.
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) != (self.width, self.height) {
+            let display = self.display.as_ptr();
+            unsafe {
+                let back = XCreatePixmap(display, self.window, width, height, self.depth);
+                XftDrawChange(self.xft, back);
+                XRenderFreePicture(display, self.picture);
+                XFreePixmap(display, self.back);
+                self.picture = XRenderCreatePicture(display, back, self.format, 0, null());
+                self.back = back;
+            }
+            self.width = width;
+            self.height = height;
+        }
+        self.drawn = false;
+    }
+
+    // Coordinates are 16 bit.
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_texture_size: Some(i16::MAX as u32),
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: true,
+        }
+    }
+
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        // RENDER blends the encoded values.
+        space == ColorSpace::Srgb
+    }
 
-    fn begin(&mut self) {
+    fn begin(&mut self) -> AeResult<()> {
         unsafe {
-            XPending(self.display);
+            XPending(self.display.as_ptr());
         }
+        self.state.reset();
+        self.damage = None;
+        self.update_clip();
+        Ok(())
     }
 
-    fn clear(&self, color: crate::Color) {
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.drawn {
+            return false;
+        }
+        self.damage = Some(damage.to_vec());
+        self.update_clip();
+        true
+    }
+
+    fn clear(&mut self, color: crate::Color) {
+        let color = render_color(color, 1.0);
         unsafe {
-            let attributes = get_window_attributes(self.display, self.window);
-            XSetForeground(self.display, self.gc, get_color(self.display, color));
-            XFillRectangle(
-                self.display,
-                self.window,
-                self.gc,
+            XRenderFillRectangle(
+                self.display.as_ptr(),
+                PictOpSrc,
+                self.picture,
+                &color,
                 0,
                 0,
-                attributes.width as u32,
-                attributes.height as u32,
+                self.width,
+                self.height,
             );
         }
     }
 
     fn command(
-        &self,
+        &mut self,
         command: &crate::Command,
-        align: crate::AlignMode,
-        layout: crate::LayoutMode,
-    ) {
+        _align: crate::AlignMode,
+        _layout: crate::LayoutMode,
+    ) -> AeResult<()> {
         match command {
-            crate::Command::FillRectangle(x, y, width, height, radius, color) => unsafe {
-                XSetForeground(self.display, self.gc, get_color(self.display, *color));
-                XFillRectangle(
-                    self.display,
+            crate::Command::FillRectangle(x, y, width, height, radius, color) => {
+                let mut path = Path::new();
+                path.rounded_rect(
+                    *x as f32,
+                    *y as f32,
+                    *width as f32,
+                    *height as f32,
+                    *radius as f32,
+                    *radius as f32,
+                );
+                let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, *color);
+            }
+            crate::Command::WriteString(x, y, _, _, color, text) => {
+                self.write_string(*x as f32, *y as f32, *color, text)?;
+            }
+            crate::Command::FillPath(path, color) => {
+                let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, path.fill_rule(), *color);
+            }
+            crate::Command::StrokePath(path, stroke, color) => {
+                let contours = path.stroke_contours(stroke, &self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, *color);
+            }
+            // Opacity is applied to each color rather than to the group.
+            _ => {
+                if self.state.apply(command) {
+                    self.update_clip();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Copies what was redrawn to the window. The GC is never clipped.
+    fn end(&mut self) -> AeResult<()> {
+        let display = self.display.as_ptr();
+        let bounds = match &self.damage {
+            Some(damage) => damage.iter().fold(Rect::default(), |b, r| b.union(r)),
+            None => Rect::from_u32(0, 0, self.width, self.height),
+        };
+        let (x, y, width, height) = pixel_bounds(&bounds, self.width, self.height);
+        unsafe {
+            if width > 0 && height > 0 {
+                XCopyArea(
+                    display,
+                    self.back,
                     self.window,
                     self.gc,
-                    *x as i32,
-                    *y as i32,
-                    *width,
-                    *height,
+                    x as i32,
+                    y as i32,
+                    width,
+                    height,
+                    x as i32,
+                    y as i32,
                 );
-            },
-            crate::Command::WriteString(x, y, width, height, color, text) => {
-                let fontname = CString::new("Yu gothic-12").unwrap();
-                let font = unsafe {
-                    XftFontOpenName(
-                        self.display,
-                        XDefaultScreen(self.display),
-                        fontname.as_ptr(),
-                    )
-                };
-                unsafe {
-                    let color = XftColor::alloc(self.display, *color);
-                    XftDrawStringUtf8(
-                        self.xft,
-                        &color.inner,
-                        font,
-                        *x as i32,
-                        (*y as i32) + (*font).ascent,
-                        text.as_ptr(),
-                        text.len() as i32,
-                    );
-                }
             }
+            XFlush(display);
         }
+        self.drawn = true;
+        Ok(())
+    }
+
+    // From the back buffer, which keeps the last frame even while the window
+    // is covered.
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        get_image(
+            self.display.as_ptr(),
+            self.back,
+            &rect,
+            self.width,
+            self.height,
+        )
     }
+}
 
-    fn end(&mut self) {
+impl Drop for X11Surface {
+    fn drop(&mut self) {
+        let display = self.display.as_ptr();
+        // Before the display, which is dropped first.
+        self.font = None;
+        self.colors.clear();
         unsafe {
-            XFlush(self.display);
+            XftDrawDestroy(self.xft);
+            XRenderFreePicture(display, self.picture);
+            XFreePixmap(display, self.back);
+            XFreeGC(display, self.gc);
+            // A borrowed display isn't closed, which would flush them.
+            XFlush(display);
         }
     }
 }
 
-fn get_color(display: *mut _XDisplay, color: Color) -> c_ulong {
-    let cmap = unsafe { XDefaultColormap(display, 0) };
-    let mut color = match color {
-        Color::ARGB(a, r, g, b) => XColor {
-            pixel: 0,
-            red: (r as u16) * 255,
-            green: (r as u16) * 255,
-            blue: (r as u16) * 255,
-            flags: 0,
-            pad: 0,
-        },
-    };
+// A connection to an X server. One opened here is closed on drop, one
+// borrowed from the application is left to it.
+pub struct XDisplay {
+    raw: *mut _XDisplay,
+    owned: bool,
+}
+
+impl XDisplay {
+    // Connects to `name`, or to $DISPLAY if it's None.
+    pub fn open(name: Option<&str>) -> AeResult<Self> {
+        let unavailable = || {
+            AcureError::DisplayUnavailable(match name {
+                Some(name) => name.to_owned(),
+                None => std::env::var("DISPLAY").unwrap_or_default(),
+            })
+        };
+        let name = name
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| unavailable())?;
+        let raw = unsafe { XOpenDisplay(name.as_ref().map_or(null(), |n| n.as_ptr())) };
+        if raw.is_null() {
+            return Err(unavailable());
+        }
+        Ok(Self { raw, owned: true })
+    }
+
+    /// # Safety
+    ///
+    /// `raw` has to be an open display that outlives everything created
+    /// with it, e.g. the one from the window's `XlibDisplayHandle`.
+    #[inline]
+    pub unsafe fn borrow(raw: *mut _XDisplay) -> Self {
+        Self { raw, owned: false }
+    }
+
+    #[inline]
+    pub fn as_ptr(&self) -> *mut _XDisplay {
+        self.raw
+    }
+}
+
+impl Drop for XDisplay {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { XCloseDisplay(self.raw) };
+        }
+    }
+}
+
+// Reads `rect` of a `width` x `height` drawable. The server has no alpha to
+// give, so the pixels come back opaque.
+pub(crate) fn get_image(
+    display: *mut _XDisplay,
+    drawable: c_ulong,
+    rect: &Rect,
+    width: u32,
+    height: u32,
+) -> AeResult<Image> {
+    let (x, y, width, height) = pixel_bounds(rect, width, height);
+    if width == 0 {
+        return Ok(Image::default());
+    }
+
     unsafe {
-        XAllocColor(display, cmap, &mut color);
-        XFreeColormap(display, cmap);
-        color.pixel as c_ulong
+        let image = XGetImage(
+            display,
+            drawable,
+            x as i32,
+            y as i32,
+            width,
+            height,
+            XAllPlanes(),
+            ZPixmap,
+        );
+        if image.is_null() {
+            return Err(AcureError::NullPtrError("XGetImage failed".to_owned()));
+        }
+
+        // Scales a channel to 8 bits, whatever the visual's depth.
+        let channel = |pixel: c_ulong, mask: c_ulong| {
+            if mask == 0 {
+                return 0;
+            }
+            let shift = mask.trailing_zeros();
+            (((pixel & mask) >> shift) * 255 / (mask >> shift)) as u32
+        };
+        let (red, green, blue) = ((*image).red_mask, (*image).green_mask, (*image).blue_mask);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for row in 0..height as i32 {
+            for column in 0..width as i32 {
+                let pixel = XGetPixel(image, column, row);
+                pixels.push(
+                    0xff000000
+                        | channel(pixel, red) << 16
+                        | channel(pixel, green) << 8
+                        | channel(pixel, blue),
+                );
+            }
+        }
+        XDestroyImage(image);
+        Ok(Image::new(width, height, pixels))
     }
 }
 
+// Zeroed if the window is gone.
 fn get_window_attributes(display: *mut _XDisplay, window: c_ulong) -> XWindowAttributes {
-    let mut attributes = unsafe { MaybeUninit::uninit().assume_init() };
-    unsafe { XGetWindowAttributes(display, window, &mut attributes) };
-    attributes
+    let mut attributes = MaybeUninit::<XWindowAttributes>::zeroed();
+    unsafe {
+        XGetWindowAttributes(display, window, attributes.as_mut_ptr());
+        attributes.assume_init()
+    }
+}
+
+// Premultiplied, as RENDER takes it.
+fn render_color(color: Color, opacity: f32) -> XRenderColor {
+    let [red, green, blue, alpha] = color
+        .to_premultiplied()
+        .map(|c| (c * opacity * 65535.0).round() as u16);
+    XRenderColor {
+        red,
+        green,
+        blue,
+        alpha,
+    }
+}
+
+fn x_trapezoid(trapezoid: &Trapezoid) -> XTrapezoid {
+    let point = |(x, y): (f32, f32)| XPointFixed {
+        x: fixed(x),
+        y: fixed(y),
+    };
+    let line = |(p1, p2)| XLineFixed {
+        p1: point(p1),
+        p2: point(p2),
+    };
+    XTrapezoid {
+        top: fixed(trapezoid.top),
+        bottom: fixed(trapezoid.bottom),
+        left: line(trapezoid.left),
+        right: line(trapezoid.right),
+    }
 }
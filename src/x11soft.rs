@@ -0,0 +1,448 @@
+use std::{
+    ffi::{c_char, c_int, c_ulong},
+    mem::MaybeUninit,
+    ptr::{null, null_mut},
+    sync::{Mutex, PoisonError},
+};
+
+use x11::{
+    xlib::{
+        _XDisplay, False, LSBFirst, MSBFirst, True, TrueColor, Visual, XCreateGC, XCreateImage,
+        XDestroyImage, XErrorEvent, XFlush, XFree, XFreeGC, XGetWindowAttributes, XImage,
+        XLastKnownRequestProcessed, XListPixmapFormats, XNextRequest, XPutImage, XSetErrorHandler,
+        XSync, XWindowAttributes, ZPixmap, _XGC,
+    },
+    xshm::{
+        XShmAttach, XShmCreateImage, XShmDetach, XShmPutImage, XShmQueryExtension, XShmSegmentInfo,
+    },
+};
+
+use crate::{
+    image::pixel_bounds,
+    software::SoftwareSurface,
+    surface::{Capabilities, Surface},
+    x11::XDisplay,
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, Image, LayoutMode, Rect,
+};
+
+type ErrorHandler = Option<unsafe extern "C" fn(*mut _XDisplay, *mut XErrorEvent) -> c_int>;
+
+// The attach request `attach_error` is waiting for.
+struct Attach {
+    display: usize,
+    serial: c_ulong,
+    failed: bool,
+    // Gets every other error.
+    previous: ErrorHandler,
+}
+
+static ATTACH: Mutex<Attach> = Mutex::new(Attach {
+    display: 0,
+    serial: 0,
+    failed: false,
+    previous: None,
+});
+// Held while the error handler is swapped, so two surfaces attaching at
+// once don't restore each other's.
+static ATTACHING: Mutex<()> = Mutex::new(());
+
+// An image in a MIT-SHM segment, which the server reads without the pixels
+// going through the connection.
+struct ShmImage {
+    display: *mut _XDisplay,
+    image: *mut XImage,
+    // `image` points to it.
+    info: Box<XShmSegmentInfo>,
+    width: u32,
+    height: u32,
+    // The serial of the last put, 0 if there's none.
+    pending: c_ulong,
+}
+
+impl ShmImage {
+    // None if the server can't attach the segment, e.g. over the network.
+    unsafe fn new(
+        display: *mut _XDisplay,
+        visual: *mut Visual,
+        depth: u32,
+        width: u32,
+        height: u32,
+    ) -> Option<Self> {
+        let mut info = Box::new(XShmSegmentInfo {
+            shmseg: 0,
+            shmid: -1,
+            shmaddr: null_mut(),
+            readOnly: 1,
+        });
+        let image = XShmCreateImage(
+            display,
+            visual,
+            depth,
+            ZPixmap,
+            null_mut(),
+            &mut *info,
+            width,
+            height,
+        );
+        if image.is_null() {
+            return None;
+        }
+        let mut shm = Self {
+            display,
+            image,
+            info,
+            width,
+            height,
+            pending: 0,
+        };
+        if (*image).bits_per_pixel != 32 {
+            return None;
+        }
+
+        let size = (*image).bytes_per_line as usize * height as usize;
+        shm.info.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+        if shm.info.shmid < 0 {
+            return None;
+        }
+        let addr = libc::shmat(shm.info.shmid, null(), 0);
+        if addr as isize == -1 {
+            libc::shmctl(shm.info.shmid, libc::IPC_RMID, null_mut());
+            return None;
+        }
+        shm.info.shmaddr = addr.cast();
+        (*image).data = addr.cast();
+
+        // Xlib has no checked requests: a failed attach is reported later,
+        // to the process-wide error handler, whose default exits. So the
+        // handler is swapped around the attach, and passes every error that
+        // isn't the attach's on to the one it replaced. Another thread
+        // setting a handler meanwhile is still a race, which Xlib gives no
+        // way to avoid.
+        let _attaching = ATTACHING.lock().unwrap_or_else(PoisonError::into_inner);
+        XSync(display, False);
+        {
+            let mut attach = ATTACH.lock().unwrap_or_else(PoisonError::into_inner);
+            attach.display = display as usize;
+            attach.serial = XNextRequest(display);
+            attach.failed = false;
+            attach.previous = XSetErrorHandler(Some(attach_error));
+        }
+        XShmAttach(display, &mut *shm.info);
+        XSync(display, False);
+        let failed = {
+            let mut attach = ATTACH.lock().unwrap_or_else(PoisonError::into_inner);
+            XSetErrorHandler(attach.previous);
+            attach.display = 0;
+            attach.failed
+        };
+        // It's freed once both sides have detached it.
+        libc::shmctl(shm.info.shmid, libc::IPC_RMID, null_mut());
+        if failed {
+            shm.info.shmseg = 0;
+            return None;
+        }
+        Some(shm)
+    }
+
+    // Copies `rect` of the frame and has the server show it. The server
+    // reads the segment while processing the request, so it's only waited
+    // for if that isn't known to have happened when the segment is written
+    // again. The completion event makes it known without a round trip.
+    unsafe fn put(
+        &mut self,
+        window: c_ulong,
+        gc: *mut _XGC,
+        pixels: &[u32],
+        rect: (u32, u32, u32, u32),
+    ) {
+        if self.pending != 0 && XLastKnownRequestProcessed(self.display) < self.pending {
+            XSync(self.display, False);
+        }
+        let (x, y, w, h) = rect;
+        let stride = (*self.image).bytes_per_line as usize / 4;
+        let data = std::slice::from_raw_parts_mut(
+            (*self.image).data as *mut u32,
+            stride * self.height as usize,
+        );
+        for row in y as usize..(y + h) as usize {
+            let from = row * self.width as usize + x as usize;
+            let to = row * stride + x as usize;
+            data[to..to + w as usize].copy_from_slice(&pixels[from..from + w as usize]);
+        }
+        self.pending = XNextRequest(self.display);
+        XShmPutImage(
+            self.display,
+            window,
+            gc,
+            self.image,
+            x as i32,
+            y as i32,
+            x as i32,
+            y as i32,
+            w,
+            h,
+            True,
+        );
+        XFlush(self.display);
+    }
+}
+
+impl Drop for ShmImage {
+    fn drop(&mut self) {
+        unsafe {
+            if self.info.shmseg != 0 {
+                XShmDetach(self.display, &mut *self.info);
+            }
+            if !self.info.shmaddr.is_null() {
+                libc::shmdt(self.info.shmaddr.cast());
+            }
+            // Both belong to us, and XDestroyImage would free them.
+            (*self.image).data = null_mut();
+            (*self.image).obdata = null_mut();
+            XDestroyImage(self.image);
+        }
+    }
+}
+
+unsafe extern "C" fn attach_error(display: *mut _XDisplay, event: *mut XErrorEvent) -> c_int {
+    let previous = {
+        let mut attach = ATTACH.lock().unwrap_or_else(PoisonError::into_inner);
+        if attach.display == display as usize && attach.serial == (*event).serial {
+            attach.failed = true;
+            return 0;
+        }
+        attach.previous
+    };
+    match previous {
+        Some(previous) => previous(display, event),
+        None => 0,
+    }
+}
+
+// Draws with the software rasterizer, so the pixels match the headless
+// renderer's, and copies each frame to the window. That goes through MIT-SHM
+// where the server can share memory with us and through the connection
+// otherwise. For servers without a usable GL.
+//
+// With MIT-SHM, each frame also sends the window's connection a
+// ShmCompletion event, which the event loop can ignore.
+pub struct X11SoftwareSurface {
+    display: XDisplay,
+    gc: *mut _XGC,
+    window: c_ulong,
+    visual: *mut Visual,
+    depth: u32,
+    inner: SoftwareSurface,
+    // Two, used in turns so a frame can be written while the last one is
+    // being read. Created at the frame's size on the next `end`.
+    shm: Vec<ShmImage>,
+    next: usize,
+    // False once the server turned out not to support MIT-SHM for us.
+    use_shm: bool,
+    // The part of the frame that changed, or None to copy all of it.
+    damage: Option<Rect>,
+}
+
+impl X11SoftwareSurface {
+    pub fn new(display: XDisplay, window: c_ulong) -> AeResult<Self> {
+        let raw = display.as_ptr();
+        unsafe {
+            let mut attributes = MaybeUninit::<XWindowAttributes>::zeroed();
+            if XGetWindowAttributes(raw, window, attributes.as_mut_ptr()) == 0 {
+                return Err(AcureError::BackendError(
+                    Backend::Software,
+                    anyhow::anyhow!("Can't get the attributes of window {}", window),
+                ));
+            }
+            let attributes = attributes.assume_init();
+
+            // The pixels are copied as they are, so they have to be laid out
+            // like the visual's.
+            let visual = &*attributes.visual;
+            if visual.class != TrueColor
+                || (visual.red_mask, visual.green_mask, visual.blue_mask)
+                    != (0xff0000, 0xff00, 0xff)
+                || !matches!(attributes.depth, 24 | 32)
+                || bits_per_pixel(raw, attributes.depth) != Some(32)
+            {
+                return Err(AcureError::BackendError(
+                    Backend::Software,
+                    anyhow::anyhow!("Unsupported visual of depth {}", attributes.depth),
+                ));
+            }
+
+            Ok(Self {
+                gc: XCreateGC(raw, window, 0, null_mut()),
+                display,
+                window,
+                visual: attributes.visual,
+                depth: attributes.depth as u32,
+                inner: SoftwareSurface::new(attributes.width as u32, attributes.height as u32),
+                shm: vec![],
+                next: 0,
+                use_shm: XShmQueryExtension(raw) != 0,
+                damage: None,
+            })
+        }
+    }
+
+    fn put_image(&mut self, rect: &Rect) {
+        let (width, height) = (self.inner.width(), self.inner.height());
+        let (x, y, w, h) = pixel_bounds(rect, width, height);
+        if w == 0 || h == 0 {
+            return;
+        }
+        if self.use_shm {
+            let display = self.display.as_ptr();
+            if self
+                .shm
+                .first()
+                .is_none_or(|shm| (shm.width, shm.height) != (width, height))
+            {
+                self.shm.clear();
+                self.shm = (0..2)
+                    .map_while(|_| unsafe {
+                        ShmImage::new(display, self.visual, self.depth, width, height)
+                    })
+                    .collect();
+            }
+            if self.shm.len() == 2 {
+                self.next = (self.next + 1) % 2;
+                let shm = &mut self.shm[self.next];
+                unsafe { shm.put(self.window, self.gc, self.inner.pixels(), (x, y, w, h)) };
+                return;
+            }
+            info!("MIT-SHM isn't usable, falling back to XPutImage");
+            self.shm.clear();
+            self.use_shm = false;
+        }
+        unsafe {
+            let image = XCreateImage(
+                self.display.as_ptr(),
+                self.visual,
+                self.depth,
+                ZPixmap,
+                0,
+                self.inner.pixels().as_ptr() as *mut c_char,
+                width,
+                height,
+                32,
+                0,
+            );
+            if image.is_null() {
+                warn!("XCreateImage failed");
+                return;
+            }
+            // The pixels are 32 bits whatever the server's format is.
+            if (*image).bits_per_pixel != 32 {
+                warn!("Can't put {} bit pixels", (*image).bits_per_pixel);
+                (*image).data = null_mut();
+                XDestroyImage(image);
+                return;
+            }
+            // In our byte order rather than the server's; Xlib swaps them.
+            (*image).byte_order = if cfg!(target_endian = "little") {
+                LSBFirst
+            } else {
+                MSBFirst
+            };
+            XPutImage(
+                self.display.as_ptr(),
+                self.window,
+                self.gc,
+                image,
+                x as i32,
+                y as i32,
+                x as i32,
+                y as i32,
+                w,
+                h,
+            );
+            // The pixels belong to `inner`.
+            (*image).data = null_mut();
+            XDestroyImage(image);
+            XFlush(self.display.as_ptr());
+        }
+    }
+}
+
+impl Surface for X11SoftwareSurface {
+    #[inline]
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.inner.surface_resize(width, height);
+    }
+
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        self.inner.set_color_space(space)
+    }
+
+    #[inline]
+    fn begin(&mut self) -> AeResult<()> {
+        self.damage = None;
+        self.inner.begin()
+    }
+
+    // The server drops the window's contents on expose, so
+    // `Acure::invalidate` has to be called then.
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.inner.set_damage(damage) {
+            return false;
+        }
+        self.damage = Some(damage.iter().fold(Rect::default(), |b, r| b.union(r)));
+        true
+    }
+
+    #[inline]
+    fn clear(&mut self, color: Color) {
+        self.inner.clear(color);
+    }
+
+    #[inline]
+    fn command(&mut self, command: &Command, align: AlignMode, layout: LayoutMode) -> AeResult<()> {
+        self.inner.command(command, align, layout)
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        self.inner.end()?;
+        let full = Rect::from_u32(0, 0, self.inner.width(), self.inner.height());
+        self.put_image(&self.damage.unwrap_or(full));
+        Ok(())
+    }
+
+    #[inline]
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        self.inner.read_pixels(rect)
+    }
+}
+
+// How many bits the server uses for a pixel of `depth` in images.
+unsafe fn bits_per_pixel(display: *mut _XDisplay, depth: c_int) -> Option<c_int> {
+    let mut count = 0;
+    let formats = XListPixmapFormats(display, &mut count);
+    if formats.is_null() {
+        return None;
+    }
+    let bits = std::slice::from_raw_parts(formats, count.max(0) as usize)
+        .iter()
+        .find(|format| format.depth == depth)
+        .map(|format| format.bits_per_pixel);
+    XFree(formats.cast());
+    bits
+}
+
+impl Drop for X11SoftwareSurface {
+    fn drop(&mut self) {
+        // Before the display, which is dropped first.
+        self.shm.clear();
+        unsafe {
+            XFreeGC(self.display.as_ptr(), self.gc);
+            XFlush(self.display.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::collections::HashMap;
+
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        render::{
+            self, ChangePictureAux, ConnectionExt as _, CreatePictureAux, Glyphinfo, Glyphset,
+            Linefix, PictOp, PictType, Pictformat, Picture, Pointfix,
+        },
+        xproto::{ConnectionExt as _, ImageFormat, ImageOrder, Rectangle, Window},
+    },
+};
+
+pub use x11rb::xcb_ffi::XCBConnection;
+
+use crate::{
+    font::{layout, system_font, Font, Outline},
+    image::pixel_bounds,
+    software::Rasterizer,
+    surface::{Capabilities, DrawState, Surface},
+    trapezoid::{fixed, trapezoids, Trapezoid},
+    AcureError, AeResult, AlignMode, Backend, Color, ColorSpace, Command, FillRule, Image,
+    LayoutMode, Path, Rect, Transform,
+};
+
+// Maximum distance in pixels between a curve and its flattened polyline.
+const TOLERANCE: f32 = 0.25;
+
+// Draws through the RENDER extension. Shapes are sent as trapezoids, which
+// the server antialiases, and text as glyphs uploaded to a glyph set.
+pub struct XcbSurface {
+    window: Window,
+    picture: Picture,
+    // 8-bit alpha, for the coverage of trapezoids and glyphs.
+    a8: Pictformat,
+    glyphset: Glyphset,
+    // Ids in `glyphset` by glyph and size, None for glyphs with no outline.
+    glyphs: HashMap<(u16, u32), Option<u32>>,
+    font: Option<Font>,
+    font_searched: bool,
+    // Pixels can only be read back from 24 and 32 bit windows.
+    readback: bool,
+    width: u32,
+    height: u32,
+    state: DrawState,
+    // The rectangles being redrawn, or None for the whole window.
+    damage: Option<Vec<Rect>>,
+    // Whether the window still shows the last frame. The server drops the
+    // contents on expose, so `Acure::invalidate` has to be called then.
+    drawn: bool,
+    // Dropped last, after the picture and the glyph set are freed.
+    connection: XCBConnection,
+}
+
+impl XcbSurface {
+    // `connection` can be opened with `XCBConnection::connect`, or wrap the
+    // application's with `XCBConnection::from_raw_xcb_connection`.
+    pub fn new(connection: XCBConnection, window: Window) -> AeResult<Self> {
+        if connection
+            .extension_information(render::X11_EXTENSION_NAME)
+            .map_err(error)?
+            .is_none()
+        {
+            return Err(AcureError::BackendError(
+                Backend::XCB,
+                anyhow::anyhow!("The server has no RENDER extension"),
+            ));
+        }
+        connection
+            .render_query_version(0, 11)
+            .map_err(error)?
+            .reply()
+            .map_err(error)?;
+        let formats = connection
+            .render_query_pict_formats()
+            .map_err(error)?
+            .reply()
+            .map_err(error)?;
+        let attributes = connection
+            .get_window_attributes(window)
+            .map_err(error)?
+            .reply()
+            .map_err(error)?;
+        let geometry = connection
+            .get_geometry(window)
+            .map_err(error)?
+            .reply()
+            .map_err(error)?;
+
+        let format = formats
+            .screens
+            .iter()
+            .flat_map(|screen| &screen.depths)
+            .flat_map(|depth| &depth.visuals)
+            .find(|visual| visual.visual == attributes.visual)
+            .map(|visual| visual.format);
+        let a8 = formats
+            .formats
+            .iter()
+            .find(|f| f.type_ == PictType::DIRECT && f.depth == 8 && f.direct.alpha_mask == 0xff)
+            .map(|f| f.id);
+        let (Some(format), Some(a8)) = (format, a8) else {
+            return Err(AcureError::BackendError(
+                Backend::XCB,
+                anyhow::anyhow!("No picture format for the window"),
+            ));
+        };
+
+        let picture = connection.generate_id().map_err(error)?;
+        connection
+            .render_create_picture(picture, window, format, &CreatePictureAux::new())
+            .map_err(error)?;
+        let glyphset = connection.generate_id().map_err(error)?;
+        connection
+            .render_create_glyph_set(glyphset, a8)
+            .map_err(error)?;
+
+        Ok(Self {
+            window,
+            picture,
+            a8,
+            glyphset,
+            glyphs: HashMap::new(),
+            font: None,
+            font_searched: false,
+            readback: matches!(geometry.depth, 24 | 32),
+            width: geometry.width as u32,
+            height: geometry.height as u32,
+            state: DrawState::new(),
+            damage: None,
+            drawn: false,
+            connection,
+        })
+    }
+}
+
+impl XcbSurface {
+    // Premultiplied, with the current opacity.
+    fn color(&self, color: Color) -> render::Color {
+        let opacity = self.state.opacity();
+        let [red, green, blue, alpha] = color
+            .to_premultiplied()
+            .map(|c| (c * opacity * 65535.0).round() as u16);
+        render::Color {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    fn fill_contours(
+        &mut self,
+        contours: &[Vec<(f32, f32)>],
+        rule: FillRule,
+        color: Color,
+    ) -> AeResult<()> {
+        let traps = trapezoids(contours, rule)
+            .iter()
+            .map(render_trapezoid)
+            .collect::<Vec<_>>();
+        if traps.is_empty() {
+            return Ok(());
+        }
+        let source = self.connection.generate_id().map_err(error)?;
+        self.connection
+            .render_create_solid_fill(source, self.color(color))
+            .map_err(error)?;
+        self.connection
+            .render_trapezoids(PictOp::OVER, source, self.picture, self.a8, 0, 0, &traps)
+            .map_err(error)?;
+        self.connection.render_free_picture(source).map_err(error)?;
+        Ok(())
+    }
+
+    // Laid out like the Direct2D surface: centered in the rectangle. Only the
+    // origin is transformed, glyphs aren't rotated or scaled.
+    fn write_string(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+        text: &str,
+    ) -> AeResult<()> {
+        if !self.font_searched {
+            self.font_searched = true;
+            self.font = system_font();
+        }
+        let Some(font) = &self.font else {
+            return Ok(());
+        };
+        let Ok(face) = ttf_parser::Face::parse(&font.data, font.index) else {
+            return Ok(());
+        };
+
+        let line = layout(&face, x, y, width, height, text);
+        let size = line.size;
+        let (x, y) = self.state.transform().apply(line.origin.0, line.origin.1);
+
+        // Each glyph is its own element, positioned relative to the last.
+        let mut commands = vec![];
+        let (mut pen_x, mut pen_y) = (0, 0);
+        for (glyph, offset) in line.glyphs {
+            let next = self.glyphs.len() as u32;
+            let id = *self
+                .glyphs
+                .entry((glyph.0, size.to_bits()))
+                .or_insert_with(|| {
+                    let (info, data) = rasterize_glyph(&face, glyph, size)?;
+                    let added =
+                        self.connection
+                            .render_add_glyphs(self.glyphset, &[next], &[info], &data);
+                    added.is_ok().then_some(next)
+                });
+            let Some(id) = id else {
+                continue;
+            };
+            let (gx, gy) = ((x + offset).round() as i16, y.round() as i16);
+            commands.extend_from_slice(&[1, 0, 0, 0]);
+            commands.extend_from_slice(&(gx - pen_x).to_ne_bytes());
+            commands.extend_from_slice(&(gy - pen_y).to_ne_bytes());
+            commands.extend_from_slice(&id.to_ne_bytes());
+            (pen_x, pen_y) = (gx, gy);
+        }
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        let source = self.connection.generate_id().map_err(error)?;
+        self.connection
+            .render_create_solid_fill(source, self.color(color))
+            .map_err(error)?;
+        self.connection
+            .render_composite_glyphs32(
+                PictOp::OVER,
+                source,
+                self.picture,
+                self.a8,
+                self.glyphset,
+                0,
+                0,
+                &commands,
+            )
+            .map_err(error)?;
+        self.connection.render_free_picture(source).map_err(error)?;
+        Ok(())
+    }
+
+    // The clip is the damage, intersected with the innermost PushClip.
+    fn update_clip(&mut self) -> AeResult<()> {
+        let clip = self.state.clip();
+        let rects = match (&self.damage, clip) {
+            (Some(damage), Some(clip)) => damage.iter().map(|r| r.intersect(&clip)).collect(),
+            (Some(damage), None) => damage.clone(),
+            (None, Some(clip)) => vec![clip],
+            (None, None) => {
+                self.connection
+                    .render_change_picture(
+                        self.picture,
+                        &ChangePictureAux::new().clipmask(x11rb::NONE),
+                    )
+                    .map_err(error)?;
+                return Ok(());
+            }
+        };
+
+        let rects = rects
+            .iter()
+            .filter(|r| !r.is_empty())
+            .map(|r| {
+                let r = r.round_out();
+                Rectangle {
+                    x: r.x as i16,
+                    y: r.y as i16,
+                    width: r.width as u16,
+                    height: r.height as u16,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.connection
+            .render_set_picture_clip_rectangles(self.picture, 0, 0, &rects)
+            .map_err(error)?;
+        Ok(())
+    }
+}
+
+impl Surface for XcbSurface {
+    #[inline]
+    fn surface_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.drawn = false;
+    }
+
+    // Coordinates are 16 bit.
+    #[inline]
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_texture_size: Some(i16::MAX as u32),
+            text: true,
+            antialiasing: true,
+            clip: true,
+            readback: self.readback,
+        }
+    }
+
+    #[inline]
+    fn set_color_space(&mut self, space: ColorSpace) -> bool {
+        // RENDER blends the encoded values.
+        space == ColorSpace::Srgb
+    }
+
+    fn begin(&mut self) -> AeResult<()> {
+        self.state.reset();
+        self.damage = None;
+        self.update_clip()
+    }
+
+    fn set_damage(&mut self, damage: &[Rect]) -> bool {
+        if !self.drawn {
+            return false;
+        }
+        self.damage = Some(damage.to_vec());
+        self.update_clip().is_ok()
+    }
+
+    fn clear(&mut self, color: Color) {
+        let [red, green, blue, alpha] = color
+            .to_premultiplied()
+            .map(|c| (c * 65535.0).round() as u16);
+        let rect = Rectangle {
+            x: 0,
+            y: 0,
+            width: self.width.min(u16::MAX as u32) as u16,
+            height: self.height.min(u16::MAX as u32) as u16,
+        };
+        let color = render::Color {
+            red,
+            green,
+            blue,
+            alpha,
+        };
+        if let Err(e) =
+            self.connection
+                .render_fill_rectangles(PictOp::SRC, self.picture, color, &[rect])
+        {
+            warn!("Can't clear the window: {}", e);
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: &Command,
+        _align: AlignMode,
+        _layout: LayoutMode,
+    ) -> AeResult<()> {
+        match command {
+            Command::FillRectangle(x, y, width, height, radius, color) => {
+                let mut path = Path::new();
+                path.rounded_rect(
+                    *x as f32,
+                    *y as f32,
+                    *width as f32,
+                    *height as f32,
+                    *radius as f32,
+                    *radius as f32,
+                );
+                let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, *color)
+            }
+            Command::WriteString(x, y, width, height, color, text) => self.write_string(
+                *x as f32,
+                *y as f32,
+                *width as f32,
+                *height as f32,
+                *color,
+                text,
+            ),
+            Command::FillPath(path, color) => {
+                let contours = path.fill_contours(&self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, path.fill_rule(), *color)
+            }
+            Command::StrokePath(path, stroke, color) => {
+                let contours = path.stroke_contours(stroke, &self.state.transform(), TOLERANCE);
+                self.fill_contours(&contours, FillRule::NonZero, *color)
+            }
+            // Opacity is applied to each color instead of a group.
+            _ => {
+                if self.state.apply(command) {
+                    self.update_clip()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn end(&mut self) -> AeResult<()> {
+        self.connection.flush().map_err(error)?;
+        self.drawn = true;
+        Ok(())
+    }
+
+    // The server has no alpha to give, so the pixels come back opaque.
+    fn read_pixels(&mut self, rect: Rect) -> AeResult<Image> {
+        if !self.readback {
+            return Err(AcureError::UnauthorizedOperation);
+        }
+        let (x, y, width, height) = pixel_bounds(&rect, self.width, self.height);
+        if width == 0 || height == 0 {
+            return Ok(Image::new(width, height, vec![]));
+        }
+        let image = self
+            .connection
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                self.window,
+                x as i16,
+                y as i16,
+                width as u16,
+                height as u16,
+                !0,
+            )
+            .map_err(error)?
+            .reply()
+            .map_err(error)?;
+        // Both depths are stored in 32 bits per pixel.
+        let msb = self.connection.setup().image_byte_order == ImageOrder::MSB_FIRST;
+        let pixels = image
+            .data
+            .chunks_exact(4)
+            .map(|p| {
+                let p = [p[0], p[1], p[2], p[3]];
+                let pixel = if msb {
+                    u32::from_be_bytes(p)
+                } else {
+                    u32::from_le_bytes(p)
+                };
+                0xff000000 | pixel & 0xffffff
+            })
+            .collect();
+        Ok(Image::new(width, height, pixels))
+    }
+}
+
+impl Drop for XcbSurface {
+    fn drop(&mut self) {
+        let _ = self.connection.render_free_glyph_set(self.glyphset);
+        let _ = self.connection.render_free_picture(self.picture);
+        let _ = self.connection.flush();
+    }
+}
+
+#[inline]
+fn error<E>(error: E) -> AcureError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    AcureError::BackendError(Backend::XCB, error.into())
+}
+
+// In 16.16 fixed point.
+fn render_trapezoid(trapezoid: &Trapezoid) -> render::Trapezoid {
+    let point = |(x, y): (f32, f32)| Pointfix {
+        x: fixed(x),
+        y: fixed(y),
+    };
+    let line = |(p1, p2)| Linefix {
+        p1: point(p1),
+        p2: point(p2),
+    };
+    render::Trapezoid {
+        top: fixed(trapezoid.top),
+        bottom: fixed(trapezoid.bottom),
+        left: line(trapezoid.left),
+        right: line(trapezoid.right),
+    }
+}
+
+// An 8-bit coverage mask of `glyph` at `size` pixels per em, or None if it
+// has no outline. Rows are padded to 32 bits, as RENDER expects.
+fn rasterize_glyph(
+    face: &ttf_parser::Face,
+    glyph: ttf_parser::GlyphId,
+    size: f32,
+) -> Option<(Glyphinfo, Vec<u8>)> {
+    let mut outline = Outline(Path::new());
+    face.outline_glyph(glyph, &mut outline)?;
+    let scale = size / face.units_per_em() as f32;
+    // Font units point up.
+    glyph_mask(&outline.0, scale)
+}
+
+// The mask of an outline in font units, with the origin at the baseline.
+fn glyph_mask(outline: &Path, scale: f32) -> Option<(Glyphinfo, Vec<u8>)> {
+    let contours = outline.fill_contours(&Transform::scale(scale, -scale), TOLERANCE);
+
+    let mut points = contours.iter().flatten();
+    let &(mut x0, mut y0) = points.next()?;
+    let (mut x1, mut y1) = (x0, y0);
+    for (x, y) in points {
+        x0 = x0.min(*x);
+        y0 = y0.min(*y);
+        x1 = x1.max(*x);
+        y1 = y1.max(*y);
+    }
+    let bounds = Rect::new(x0, y0, x1 - x0, y1 - y0).round_out();
+    if bounds.is_empty() {
+        return None;
+    }
+    let mut rasterizer = Rasterizer::new(bounds);
+    for points in &contours {
+        for (i, p0) in points.iter().enumerate() {
+            rasterizer.line(*p0, points[(i + 1) % points.len()]);
+        }
+    }
+
+    let (width, height) = (bounds.width as usize, bounds.height as usize);
+    let stride = (width + 3) & !3;
+    let mut data = vec![0; stride * height];
+    let (x0, y0) = (bounds.x as i32, bounds.y as i32);
+    rasterizer.for_each(FillRule::NonZero, |x, y, coverage| {
+        data[(y - y0) as usize * stride + (x - x0) as usize] = (coverage * 255.0).round() as u8;
+    });
+    let info = Glyphinfo {
+        width: width as u16,
+        height: height as u16,
+        x: -x0 as i16,
+        y: -y0 as i16,
+        x_off: 0,
+        y_off: 0,
+    };
+    Some((info, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyph_mask_covers_the_outline() {
+        // A 10x7 box standing on the baseline, 2 units right of the origin.
+        let mut outline = Path::new();
+        outline.rect(2.0, 0.0, 10.0, 7.0);
+        let (info, data) = glyph_mask(&outline, 1.0).unwrap();
+        assert_eq!((info.width, info.height), (10, 7));
+        assert_eq!((info.x, info.y), (-2, 7));
+        assert_eq!(data.len(), 12 * 7);
+        for row in data.chunks(12) {
+            assert_eq!(row[..10], [255; 10]);
+            assert_eq!(row[10..], [0; 2]);
+        }
+    }
+
+    #[test]
+    fn glyph_mask_scales_a_font_glyph() {
+        let Some(font) = crate::font::system_font() else {
+            return;
+        };
+        let face = ttf_parser::Face::parse(&font.data, font.index).unwrap();
+        let glyph = face.glyph_index('H').unwrap();
+        let (info, data) = rasterize_glyph(&face, glyph, 32.0).unwrap();
+        // A capital is most of the em high, and wider than a stem.
+        assert!(info.height > 16 && info.height <= 32, "{:?}", info);
+        assert!(info.width > 8, "{:?}", info);
+        assert_eq!(
+            data.len(),
+            ((info.width as usize + 3) & !3) * info.height as usize
+        );
+        assert!(data.contains(&255));
+    }
+}